@@ -1,22 +1,27 @@
 //! This file defines a structure called `Animation`.
 //! @todo describe keyframes, etc...
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use anyhow::Result;
-use hermes_five::animation::Track;
+use anyhow::{bail, Result};
+use hermes_five::animation::{Segment, Track};
 use hermes_five::utils::{Easing, State};
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::animation::event::EventKeyframe;
 use crate::animation::group::Group;
+use crate::animation::transition::{Move, Transition};
+use crate::animation::version::AnimationVersion;
+use crate::extra::audio::AudioPlayer;
 use crate::hardware::board::Board;
 use crate::hardware::device::Device;
+use crate::hardware::power::PowerModel;
 use crate::impl_entity;
 use crate::utils::database::Database;
 use crate::utils::entity::Id;
 
 /// Defines the structure of an animation entity.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Animation {
     pub id: Id,
     /// The name of the animation.
@@ -33,28 +38,210 @@ pub struct Animation {
     pub fps: u8,
     /// A hashmap of `Keyframe` keyed by `Group` id.
     pub tracks: HashMap<Id, Vec<Keyframe>>,
+    /// The revision number of the animation: incremented on every change (used for optimistic concurrency).
+    #[serde(default)]
+    pub revision: u64,
+    /// The audio file played in lock-step with the animation (if any).
+    #[serde(default)]
+    pub audio: Option<AudioLane>,
+    /// The discrete actions triggered at given times: they are executed by the playback engine, hence an
+    /// animation with events always plays as a layer of it (see [`crate::animation::playback`]).
+    #[serde(default)]
+    pub events: Vec<EventKeyframe>,
 
     // ########################################
     // # Volatile utility data.
     #[serde(skip)]
     pub inner: hermes_five::animation::Animation,
+    /// The starting time (in ms) of each segment of the inner animation.
+    #[serde(skip)]
+    pub offsets: Vec<u64>,
+    /// The options of the current (or last) play.
+    #[serde(skip)]
+    pub options: PlayOptions,
+    /// The duration (in ms) of the lead-in transition at the beginning of the inner animation.
+    #[serde(skip)]
+    pub lead: u64,
+    /// The factor the animation is slowed down by to fit the power budget (none: not planned yet).
+    #[serde(skip)]
+    pub slowdown: Option<f64>,
+    /// The player of the audio lane.
+    #[serde(skip)]
+    pub audio_player: AudioPlayer,
 }
 impl_entity!(Animation, {
     fn post_load(&mut self, database: &Database) -> Result<()> {
         self.build(database)?;
         Ok(())
     }
+    // Delete all associated versions.
+    fn post_delete(&mut self, database: &mut Database) -> Result<()> {
+        for version in AnimationVersion::list_for(database, &self.id)? {
+            database.delete::<AnimationVersion>(version.id)?;
+        }
+        Ok(())
+    }
 });
 
 impl Animation {
     fn build(&mut self, database: &Database) -> Result<()> {
-        let mut new_segment = hermes_five::animation::Segment::default()
-            .set_repeat(self.repeat)
-            .set_loopback(self.loopback)
-            .set_speed(self.speed)
+        self.build_from(database, self.options.start, None)
+    }
+
+    /// Builds the inner hermes animation so that it starts at the given time (in ms).
+    ///
+    /// The keyframes are clipped to the selection defined by the [`PlayOptions`]: when starting in the
+    /// middle of the animation, a first segment plays the remainder of the selection and, if it repeats
+    /// from a loopback time located before the starting point, a second segment handles the loop as usual.
+    ///
+    /// When a transition is given, the first segment starts with a lead-in: all devices blend from their
+    /// current state to their state at the starting point before the animation actually starts.
+    fn build_from(
+        &mut self,
+        database: &Database,
+        from: u64,
+        transition: Option<&Transition>,
+    ) -> Result<()> {
+        let end = self.options.end;
+        let from = from.max(self.options.start).min(end.unwrap_or(u64::MAX));
+
+        // The states of the devices at the end of the selection: used as the targets of the cut keyframes.
+        let cut = match end {
+            None => HashMap::new(),
+            Some(end) => self.sample(database, end)?,
+        };
+
+        let (lead_moves, lead) = match transition {
+            None => (vec![], 0),
+            Some(transition) => self.plan_lead_in(database, transition, from)?,
+        };
+        let lead_in = transition.map(|transition| (transition, lead_moves.as_slice()));
+        self.lead = lead;
+        self.plan_power(database, (self.options.start, end))?;
+
+        let loopback = self.get_loopback();
+        let repeat_first = loopback.is_some_and(|loopback| from <= loopback);
+        let mut animation = hermes_five::animation::Animation::from(self.build_segment(
+            database,
+            (from, end),
+            &cut,
+            lead_in,
+            repeat_first,
+            loopback.unwrap_or(0).saturating_sub(from) + self.lead,
+        )?);
+        self.offsets = vec![from];
+
+        if let Some(loopback) = loopback.filter(|_| !repeat_first) {
+            animation = animation.with_segment(self.build_segment(
+                database,
+                (loopback, end),
+                &cut,
+                None,
+                true,
+                0,
+            )?);
+            self.offsets.push(loopback);
+        }
+
+        self.inner = animation;
+        Ok(())
+    }
+
+    /// Retrieves the time (in ms) the animation loops back to (if it repeats at all):
+    /// - a looped selection restarts at its start,
+    /// - otherwise the animation own `repeat` / `loopback` settings apply unless the selection is cut short.
+    pub(crate) fn get_loopback(&self) -> Option<u64> {
+        match (self.options.repeat, self.options.end) {
+            (true, _) => Some(self.options.start),
+            (false, None) if self.repeat => Some(self.loopback),
+            _ => None,
+        }
+    }
+
+    /// Plans the power budget of the given (start, end) range (in ms): the animation is slowed down when its
+    /// keyframes moving at once overload a rail (see [`PowerModel::get_slowdown`]).
+    pub fn plan_power(
+        &mut self,
+        database: &Database,
+        (start, end): (u64, Option<u64>),
+    ) -> Result<()> {
+        let end = end.unwrap_or(u64::MAX);
+        let mut moves = vec![];
+        for keyframe in self.tracks.values().flatten() {
+            if keyframe.end <= start || keyframe.start >= end {
+                continue;
+            }
+            for position in &keyframe.positions {
+                if let Some(device) = database.get::<Device>(&position.device)? {
+                    moves.push((device, keyframe.start, keyframe.end));
+                }
+            }
+        }
+        let slowdown = PowerModel::load(database).get_slowdown(
+            moves
+                .iter()
+                .map(|(device, start, end)| (device, *start, *end)),
+        );
+        if slowdown > 1.0 {
+            warn!(
+                "Animation [{}] slowed down by {:.2} to fit the power budget",
+                self.id, slowdown
+            );
+        }
+        self.slowdown = Some(slowdown);
+        Ok(())
+    }
+
+    /// (private)
+    /// Plans the lead-in to the given time (in ms): the moves of the devices to their state at that time.
+    /// Returns the moves along with the duration of the whole lead-in.
+    fn plan_lead_in(
+        &self,
+        database: &Database,
+        transition: &Transition,
+        from: u64,
+    ) -> Result<(Vec<Move>, u64)> {
+        let states = self.sample(database, from)?.into_iter().collect();
+        let moves = transition.plan(database, states)?;
+        let lead = transition.get_duration(&moves);
+        Ok((moves, lead))
+    }
+
+    /// (private)
+    /// Builds a hermes segment out of the animation keyframes clipped to the given (start, end) range (in ms).
+    ///
+    /// The keyframes cut by the end of the range target the given `cut` states instead of their own.
+    /// The lead-in (if any) moves the devices as planned first: all keyframes are shifted by its duration.
+    fn build_segment(
+        &self,
+        database: &Database,
+        (start, end): (u64, Option<u64>),
+        cut: &HashMap<Id, State>,
+        lead_in: Option<(&Transition, &[Move])>,
+        repeat: bool,
+        loopback: u64,
+    ) -> Result<Segment> {
+        let end = end.unwrap_or(u64::MAX);
+        let mut new_segment = Segment::default()
+            .set_repeat(repeat)
+            .set_loopback(loopback)
+            .set_speed(self.get_speed())
             .set_fps(self.fps);
 
         let mut tracks: HashMap<Id, Track> = HashMap::new();
+        // The time (in ms, within the segment) the last keyframe of each device ends.
+        let mut ends: HashMap<Id, u64> = HashMap::new();
+        let lead = lead_in.map_or(0, |_| self.lead);
+        if let Some((transition, moves)) = lead_in {
+            for (device, state, slot) in moves {
+                let track = device.inner.into_track()?.with_keyframe(
+                    hermes_five::animation::Keyframe::new(state.clone(), slot.delay, slot.end())
+                        .set_transition(transition.easing),
+                );
+                tracks.insert(device.id, track);
+            }
+        }
+
         // Loop through each tracks of the animation (one track per group)
         for (group_id, keyframes) in &self.tracks {
             // First: ensure the group associated with the track still exists: if not abort this track.
@@ -64,6 +251,11 @@ impl Animation {
 
             // Loop over the track keyframes...
             for keyframe in keyframes {
+                // Skip the keyframes entirely outside the range.
+                if (start > 0 && keyframe.end <= start) || keyframe.start >= end {
+                    continue;
+                }
+
                 // But for "group keyframes" purpose (see frontend), we harmonised device-track
                 // (track which group is actually a device) and group-track (tracks for group where a keyframes
                 // will control multiple devices) so each keyframe contains positions (can be an array of one)
@@ -76,14 +268,9 @@ impl Animation {
                     };
 
                     // 2. ensure the board associated with the position still exists and is connected.
-                    match database.get::<Board>(&device.bid)? {
-                        None => continue,
-                        Some(board) => {
-                            if !board.connected {
-                                continue;
-                            }
-                        }
-                    };
+                    if !Self::is_connected(database, &device)? {
+                        continue;
+                    }
 
                     // 3. Retrieve the hermes-track for the device (if already created) or create a new one
                     // for the current frontend-track.
@@ -92,17 +279,44 @@ impl Animation {
                         None => device.inner.into_track()?,
                     };
 
-                    // 4. Add the position as a new hermes-keyframe on the hermes-track.
+                    // 4. Add the position as a new hermes-keyframe on the hermes-track (clipped to the
+                    // range: a keyframe in progress is shortened to its remaining part, a keyframe cut by
+                    // the end of the range stops at its intermediate state).
+                    let target = match keyframe.end > end {
+                        true => cut.get(&position.device).unwrap_or(&position.target),
+                        false => &position.target,
+                    };
+                    let keyframe_end = keyframe.end.min(end) - start + lead;
                     let track = track.with_keyframe(
                         hermes_five::animation::Keyframe::new(
-                            position.target.clone(),
-                            keyframe.start,
-                            keyframe.end,
+                            target.clone(),
+                            keyframe.start.saturating_sub(start) + lead,
+                            keyframe_end,
                         )
                         .set_transition(keyframe.transition),
                     );
 
                     tracks.insert(device.id, track);
+                    let last = ends.entry(device.id).or_default();
+                    *last = (*last).max(keyframe_end);
+                }
+            }
+        }
+
+        // A looped selection lasts until its end, even if its keyframes end earlier: the devices hold their
+        // state meanwhile so that the loop period is the selection duration.
+        if end < u64::MAX && self.get_loopback().is_some() {
+            let period = end - start + lead;
+            for (device, last) in ends.into_iter().filter(|(_, last)| *last < period) {
+                if let (Some(track), Some(state)) = (tracks.get(&device), cut.get(&device)) {
+                    let track = track
+                        .clone()
+                        .with_keyframe(hermes_five::animation::Keyframe::new(
+                            state.clone(),
+                            last,
+                            period,
+                        ));
+                    tracks.insert(device, track);
                 }
             }
         }
@@ -112,27 +326,237 @@ impl Animation {
             new_segment = new_segment.with_track(track)
         }
 
-        self.inner = hermes_five::animation::Animation::from(new_segment);
-        Ok(())
+        Ok(new_segment)
     }
 
-    pub fn play(&mut self, database: &Database) -> Result<()> {
-        self.build(database)?;
+    /// Builds the animation (or the selection of it) defined by the given options, without playing it yet:
+    /// see [`Animation::start`].
+    pub fn prepare(&mut self, database: &Database, options: PlayOptions) -> Result<()> {
+        if let Some(end) = options.end {
+            if end <= options.start {
+                bail!(
+                    "Invalid selection: end ({}ms) must be after start ({}ms)",
+                    end,
+                    options.start
+                );
+            }
+        }
+        let (start, transition) = (options.start, options.transition.clone());
+        self.options = options;
+        self.build_from(database, start, transition.as_ref())
+    }
+
+    /// Starts playing the animation prepared beforehand (see [`Animation::prepare`]).
+    pub fn start(&mut self) {
+        debug!("{}", self.inner);
+        self.inner.play();
+        self.play_audio(self.options.start, self.lead);
+    }
+
+    /// Plays the animation starting at the given time (in ms), within the current selection.
+    pub fn play_from(&mut self, database: &Database, from: u64) -> Result<()> {
+        self.play_with(database, from, None)
+    }
+
+    /// (private)
+    /// Plays the animation starting at the given time (in ms), with an optional lead-in transition.
+    fn play_with(
+        &mut self,
+        database: &Database,
+        from: u64,
+        transition: Option<&Transition>,
+    ) -> Result<()> {
+        self.build_from(database, from, transition)?;
         debug!("{}", self.inner);
         // trace!("{:#?}", self.inner);
         self.inner.play();
+        self.play_audio(from, self.lead);
         Ok(())
     }
+
+    /// Pauses the animation (and its audio lane).
+    pub fn pause(&mut self) {
+        self.inner.pause();
+        self.audio_player.pause();
+    }
+
+    /// Stops the animation (and its audio lane).
+    pub fn stop(&mut self) {
+        self.inner.stop();
+        self.audio_player.stop();
+    }
+
+    /// Resynchronizes the audio lane with the animation playhead if needed (ie: after a loop).
+    pub fn sync_audio(&self) {
+        if self.inner.is_playing()
+            && self
+                .audio_player
+                .has_drifted(self.get_progress(), self.get_speed_ratio())
+        {
+            self.play_audio(self.get_progress(), 0);
+        }
+    }
+
+    /// (private)
+    /// Plays the audio lane (if any) from the given position (in ms) of the animation.
+    fn play_audio(&self, from: u64, delay: u64) {
+        if let Some(audio) = &self.audio {
+            let speed = self.get_speed_ratio();
+            if let Err(err) = self
+                .audio_player
+                .play(&audio.path, audio.offset, from, delay, speed)
+            {
+                warn!("Audio lane of animation [{}] failed: {}", self.id, err);
+            }
+        }
+    }
+
+    /// (private)
+    /// Retrieves the speed (in percent) the animation actually plays at: slowed down to fit the power budget.
+    fn get_speed(&self) -> u8 {
+        (self.speed as f64 / self.slowdown.unwrap_or(1.0))
+            .round()
+            .max(1.0) as u8
+    }
+
+    /// Retrieves the actual animation speed as a ratio (1.0 = normal speed).
+    pub(crate) fn get_speed_ratio(&self) -> f32 {
+        self.get_speed() as f32 / 100.0
+    }
+
+    /// Retrieves the total duration (in ms) of the animation: the end of its last keyframe (or its last event).
+    pub fn get_duration(&self) -> u64 {
+        self.tracks
+            .values()
+            .flatten()
+            .map(|keyframe| keyframe.end)
+            .chain(self.events.iter().map(|event| event.time))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Retrieves the current position (in ms) of the playhead.
+    pub fn get_progress(&self) -> u64 {
+        let current = self.inner.get_current();
+        let offset = self.offsets.get(current).copied().unwrap_or(0);
+        match current {
+            // The lead-in transition happens before the starting point.
+            0 => offset + self.inner.get_progress().saturating_sub(self.lead),
+            _ => offset + self.inner.get_progress(),
+        }
+    }
+
+    /// Computes the state of every animated device at the given time (in ms).
+    ///
+    /// Within a keyframe, the state is interpolated (using the keyframe transition) from the target of
+    /// the previous keyframe of the device (or the device default state for a first keyframe).
+    pub fn sample(&self, database: &Database, at: u64) -> Result<HashMap<Id, State>> {
+        // Gather all (keyframe, target) pairs for each device.
+        let mut timelines: HashMap<Id, Vec<(&Keyframe, &State)>> = HashMap::new();
+        for keyframes in self.tracks.values() {
+            for keyframe in keyframes {
+                for position in &keyframe.positions {
+                    timelines
+                        .entry(position.device)
+                        .or_default()
+                        .push((keyframe, &position.target));
+                }
+            }
+        }
+
+        let mut states = HashMap::new();
+        for (device_id, mut timeline) in timelines {
+            let mut device = match database.get::<Device>(&device_id)? {
+                None => continue,
+                Some(device) => device,
+            };
+            timeline.sort_by_key(|(keyframe, _)| keyframe.start);
+
+            // Find the last keyframe started at the given time: none means the device is not animated yet.
+            let index = match timeline
+                .iter()
+                .rposition(|(keyframe, _)| keyframe.start <= at)
+            {
+                None => continue,
+                Some(index) => index,
+            };
+
+            let (keyframe, target) = timeline[index];
+            let state = match at >= keyframe.end {
+                true => target.clone(),
+                false => {
+                    let previous = match index {
+                        0 => device.inner.get_default(),
+                        _ => timeline[index - 1].1.clone(),
+                    };
+                    let progress =
+                        (at - keyframe.start) as f32 / (keyframe.end - keyframe.start) as f32;
+                    device.inner.scale_state(
+                        previous,
+                        target.clone(),
+                        keyframe.transition.call(progress),
+                    )
+                }
+            };
+            states.insert(device_id, state);
+        }
+
+        Ok(states)
+    }
+
+    /// Applies the given device states (see [`Animation::sample`]) to the devices of connected boards.
+    ///
+    /// When `persist` is true, the devices are updated in the database.
+    /// Returns the list of mutations: (device id, new state).
+    pub fn apply_states(
+        database: &mut Database,
+        states: HashMap<Id, State>,
+        persist: bool,
+    ) -> Result<Vec<(Id, State)>> {
+        let mut mutations = vec![];
+        for (device_id, state) in states {
+            let mut device = match database.get::<Device>(&device_id)? {
+                None => continue,
+                Some(device) => device,
+            };
+            if matches!(state, State::Null) || !Self::is_connected(database, &device)? {
+                continue;
+            }
+            let state = device.inner.set_state(state)?;
+            if persist {
+                database.update(device)?;
+            }
+            mutations.push((device_id, state));
+        }
+        Ok(mutations)
+    }
+
+    /// Retrieves the ids of all devices animated by the animation.
+    pub fn get_devices(&self) -> HashSet<Id> {
+        self.tracks
+            .values()
+            .flatten()
+            .flat_map(|keyframe| keyframe.positions.iter().map(|position| position.device))
+            .collect()
+    }
+
+    /// Checks whether the board associated with the device still exists and is connected.
+    pub(crate) fn is_connected(database: &Database, device: &Device) -> Result<bool> {
+        Ok(match database.get::<Board>(&device.bid)? {
+            None => false,
+            Some(board) => board.connected,
+        })
+    }
 }
 
 // ######################################
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Keyframe {
-    positions: Vec<Position>,
-    start: u64,
-    end: u64,
-    transition: Easing,
+    pub positions: Vec<Position>,
+    pub start: u64,
+    pub end: u64,
+    pub transition: Easing,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -140,3 +564,61 @@ pub struct Position {
     pub device: Id,
     pub target: State,
 }
+
+/// An audio file played in lock-step with an animation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AudioLane {
+    /// The path of the audio file.
+    pub path: String,
+    /// The time (in ms) the audio file starts at in the animation (default: 0).
+    #[serde(default)]
+    pub offset: u64,
+}
+
+/// Options to play a selection of an animation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayOptions {
+    /// The time (in ms) the selection starts at (default: 0).
+    pub start: u64,
+    /// The time (in ms) the selection ends at (default: the end of the animation).
+    pub end: Option<u64>,
+    /// Determines whether the selection should replay in a loop (default: false).
+    #[serde(rename = "loop")]
+    pub repeat: bool,
+    /// The transition used to blend the devices from their current state to the starting point (default: none).
+    pub transition: Option<Transition>,
+}
+
+/// The fixtures shared by the tests of the animation modules.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::collections::HashMap;
+
+    use hermes_five::utils::Easing;
+
+    use crate::animation::animation::{Animation, Keyframe};
+    use crate::utils::entity::Id;
+
+    /// Builds a keyframe moving no device.
+    pub(crate) fn keyframe(start: u64, end: u64) -> Keyframe {
+        Keyframe {
+            positions: vec![],
+            start,
+            end,
+            transition: Easing::Linear,
+        }
+    }
+
+    /// Builds an animation (id 1) made of the given tracks.
+    pub(crate) fn animation(tracks: HashMap<Id, Vec<Keyframe>>) -> Animation {
+        Animation {
+            id: 1,
+            name: String::from("animation"),
+            speed: 100,
+            fps: 40,
+            tracks,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! This file defines fine-grained editing operations on an `Animation`.
+//!
+//! Instead of replacing the whole entity, an editor sends an `AnimationEdit` along with the revision
+//! of the animation it is based on. The edit is only applied if that revision is still the current
+//! one (optimistic concurrency): it is then broadcast as a small `AnimationDelta` to other clients.
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::animation::{Animation, Keyframe};
+use crate::utils::entity::Id;
+
+/// Lists all available editing operations.
+/// Tracks are keyed by `Group` id and keyframes are identified by their start time (in ms) on a track.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AnimationEdit {
+    /// Adds a keyframe on an existing track.
+    AddKeyframe { track: Id, keyframe: Keyframe },
+    /// Moves a keyframe to a new start time (its duration is preserved).
+    MoveKeyframe { track: Id, start: u64, to: u64 },
+    /// Deletes a keyframe from a track.
+    DeleteKeyframe { track: Id, start: u64 },
+    /// Adds an (empty) track.
+    AddTrack { track: Id },
+    /// Removes a track and all its keyframes.
+    RemoveTrack { track: Id },
+}
+
+/// An edit request as sent by an editor.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AnimationEditRequest {
+    /// The edited animation id.
+    pub animation: Id,
+    /// The revision of the animation the edit is based on.
+    pub revision: u64,
+    /// The editing operation.
+    pub edit: AnimationEdit,
+}
+
+/// An applied edit as broadcast to the other editors.
+#[derive(Clone, Debug, Serialize)]
+pub struct AnimationDelta {
+    /// The edited animation id.
+    pub animation: Id,
+    /// The revision of the animation after the edit.
+    pub revision: u64,
+    /// The applied editing operation.
+    pub edit: AnimationEdit,
+}
+
+impl AnimationEdit {
+    /// Retrieves the track targeted by the edit.
+    pub fn get_track(&self) -> Id {
+        match self {
+            AnimationEdit::AddKeyframe { track, .. } => *track,
+            AnimationEdit::MoveKeyframe { track, .. } => *track,
+            AnimationEdit::DeleteKeyframe { track, .. } => *track,
+            AnimationEdit::AddTrack { track } => *track,
+            AnimationEdit::RemoveTrack { track } => *track,
+        }
+    }
+}
+
+impl Animation {
+    /// Applies an edit based on the given revision.
+    ///
+    /// # Errors
+    /// * if the revision is not the current one: the edit is based on outdated data.
+    /// * if the edit is invalid (unknown track or keyframe, overlapping keyframes, etc...).
+    pub fn edit(&mut self, revision: u64, edit: AnimationEdit) -> Result<AnimationDelta> {
+        self.check_revision(revision)?;
+        self.apply(&edit)?;
+        self.revision += 1;
+        Ok(AnimationDelta {
+            animation: self.id,
+            revision: self.revision,
+            edit,
+        })
+    }
+
+    /// Checks that a change based on the given revision is based on the current one.
+    pub fn check_revision(&self, revision: u64) -> Result<()> {
+        if revision != self.revision {
+            bail!(
+                "Revision conflict: animation [{}] is at revision {} but the change is based on revision {}",
+                self.id,
+                self.revision,
+                revision
+            );
+        }
+        Ok(())
+    }
+
+    /// (private)
+    /// Applies an edit on the animation tracks.
+    fn apply(&mut self, edit: &AnimationEdit) -> Result<()> {
+        match edit {
+            AnimationEdit::AddKeyframe { track, keyframe } => {
+                if keyframe.end < keyframe.start {
+                    bail!("Keyframe cannot end before it starts");
+                }
+                let keyframes = self.get_track_mut(track)?;
+                Self::check_overlap(keyframes, keyframe.start, keyframe.end, None)?;
+                keyframes.push(keyframe.clone());
+                keyframes.sort_by_key(|keyframe| keyframe.start);
+            }
+            AnimationEdit::MoveKeyframe { track, start, to } => {
+                let keyframes = self.get_track_mut(track)?;
+                let index = Self::find_keyframe(keyframes, track, start)?;
+                let duration = match keyframes[index].end.checked_sub(keyframes[index].start) {
+                    None => bail!("Keyframe cannot end before it starts"),
+                    Some(duration) => duration,
+                };
+                let end = match to.checked_add(duration) {
+                    None => bail!("Keyframe cannot be moved to {}ms: out of range", to),
+                    Some(end) => end,
+                };
+                Self::check_overlap(keyframes, *to, end, Some(index))?;
+                keyframes[index].start = *to;
+                keyframes[index].end = end;
+                keyframes.sort_by_key(|keyframe| keyframe.start);
+            }
+            AnimationEdit::DeleteKeyframe { track, start } => {
+                let keyframes = self.get_track_mut(track)?;
+                let index = Self::find_keyframe(keyframes, track, start)?;
+                keyframes.remove(index);
+            }
+            AnimationEdit::AddTrack { track } => {
+                if self.tracks.contains_key(track) {
+                    bail!("Track [{}] already exists", track);
+                }
+                self.tracks.insert(*track, vec![]);
+            }
+            AnimationEdit::RemoveTrack { track } => {
+                if self.tracks.remove(track).is_none() {
+                    bail!("Track [{}] not found", track);
+                }
+            }
+        };
+        Ok(())
+    }
+
+    /// (private)
+    /// Retrieves the keyframes of a track.
+    fn get_track_mut(&mut self, track: &Id) -> Result<&mut Vec<Keyframe>> {
+        match self.tracks.get_mut(track) {
+            None => bail!("Track [{}] not found", track),
+            Some(keyframes) => Ok(keyframes),
+        }
+    }
+
+    /// (private)
+    /// Retrieves the index of the keyframe starting at the given time.
+    fn find_keyframe(keyframes: &[Keyframe], track: &Id, start: &u64) -> Result<usize> {
+        match keyframes
+            .iter()
+            .position(|keyframe| &keyframe.start == start)
+        {
+            None => bail!("No keyframe at {}ms on track [{}]", start, track),
+            Some(index) => Ok(index),
+        }
+    }
+
+    /// (private)
+    /// Ensures the [start, end] period does not overlap any keyframe of the track (except the ignored one).
+    fn check_overlap(
+        keyframes: &[Keyframe],
+        start: u64,
+        end: u64,
+        ignore: Option<usize>,
+    ) -> Result<()> {
+        let overlap = keyframes.iter().enumerate().find(|(index, keyframe)| {
+            Some(*index) != ignore
+                && (keyframe.start == start || (start < keyframe.end && keyframe.start < end))
+        });
+        match overlap {
+            None => Ok(()),
+            Some((_, keyframe)) => bail!(
+                "Keyframe would overlap the keyframe [{}ms - {}ms]",
+                keyframe.start,
+                keyframe.end
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::animation::animation::fixtures::{self, keyframe};
+
+    use super::*;
+
+    fn animation() -> Animation {
+        Animation {
+            revision: 3,
+            ..fixtures::animation(HashMap::from([(
+                1,
+                vec![keyframe(0, 100), keyframe(200, 300)],
+            )]))
+        }
+    }
+
+    fn starts(animation: &Animation, track: Id) -> Vec<u64> {
+        animation.tracks[&track]
+            .iter()
+            .map(|keyframe| keyframe.start)
+            .collect()
+    }
+
+    #[test]
+    fn test_edit_revision_conflict() {
+        let mut animation = animation();
+        let result = animation.edit(2, AnimationEdit::AddTrack { track: 2 });
+        assert!(result.is_err());
+        assert_eq!(animation.revision, 3);
+        assert!(!animation.tracks.contains_key(&2));
+    }
+
+    #[test]
+    fn test_edit_bumps_revision() {
+        let mut animation = animation();
+        let delta = animation
+            .edit(3, AnimationEdit::AddTrack { track: 2 })
+            .expect("Edit should apply");
+        assert_eq!(delta.revision, 4);
+        assert_eq!(animation.revision, 4);
+        assert!(animation.tracks.contains_key(&2));
+    }
+
+    #[test]
+    fn test_edit_keyframes() {
+        let mut animation = animation();
+
+        // Add a keyframe in between.
+        let edit = AnimationEdit::AddKeyframe {
+            track: 1,
+            keyframe: keyframe(120, 180),
+        };
+        assert!(animation.edit(3, edit).is_ok());
+        assert_eq!(starts(&animation, 1), vec![0, 120, 200]);
+
+        // Overlapping keyframes are rejected.
+        let edit = AnimationEdit::AddKeyframe {
+            track: 1,
+            keyframe: keyframe(250, 400),
+        };
+        assert!(animation.edit(4, edit).is_err());
+
+        // Move a keyframe at the end.
+        let edit = AnimationEdit::MoveKeyframe {
+            track: 1,
+            start: 0,
+            to: 500,
+        };
+        assert!(animation.edit(4, edit).is_ok());
+        assert_eq!(starts(&animation, 1), vec![120, 200, 500]);
+        assert_eq!(animation.tracks[&1][2].end, 600);
+
+        // Moving a keyframe out of range is rejected.
+        let edit = AnimationEdit::MoveKeyframe {
+            track: 1,
+            start: 500,
+            to: u64::MAX,
+        };
+        assert!(animation.edit(5, edit).is_err());
+
+        // Delete a keyframe.
+        let edit = AnimationEdit::DeleteKeyframe {
+            track: 1,
+            start: 200,
+        };
+        assert!(animation.edit(5, edit).is_ok());
+        assert_eq!(starts(&animation, 1), vec![120, 500]);
+
+        // Unknown keyframes and tracks are rejected.
+        let edit = AnimationEdit::DeleteKeyframe {
+            track: 1,
+            start: 200,
+        };
+        assert!(animation.edit(6, edit).is_err());
+        assert!(animation
+            .edit(6, AnimationEdit::RemoveTrack { track: 9 })
+            .is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! This file defines the event keyframes: discrete actions triggered at a given time of an animation.
+//!
+//! Unlike the keyframes which interpolate the state of devices over a period, an event keyframe happens at
+//! an instant: it plays a posture, notifies the UI, sets a variable, starts another animation or calls a
+//! webhook. Event keyframes are executed by the playback engine (see [`crate::animation::playback`]).
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use socketioxide::SocketIo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::animation::animation::Animation;
+use crate::animation::executor::ArcExecutor;
+use crate::animation::playback::{ArcPlayback, LayerOptions, Playback};
+use crate::animation::posture::Posture;
+use crate::animation::transition::Transition;
+use crate::hardware::device::Device;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+/// The maximum time (in ms) allowed for a webhook call.
+const WEBHOOK_TIMEOUT: u64 = 5000;
+
+/// An action triggered at a given time of an animation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventKeyframe {
+    /// The time (in ms) the action is triggered at.
+    pub time: u64,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    /// Plays a posture (with its own transition unless specified).
+    Posture {
+        posture: Id,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Sends an `animation:event` socket event to the UI and notifies the running shows
+    /// (see [`crate::animation::show::Trigger::Event`]).
+    Emit {
+        name: String,
+        #[serde(default)]
+        data: Value,
+    },
+    /// Sets a variable of the playback engine.
+    Variable { name: String, value: Value },
+    /// Starts another animation as a new layer of the playback engine.
+    Animation {
+        animation: Id,
+        #[serde(default)]
+        options: LayerOptions,
+    },
+    /// Sends a POST request (JSON body) to a local http endpoint.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        body: Value,
+    },
+}
+
+impl Action {
+    /// Executes the action triggered by the given animation.
+    pub async fn execute(
+        self,
+        animation: Id,
+        database: ArcDb,
+        playback: ArcPlayback,
+        executor: ArcExecutor,
+        io: SocketIo,
+    ) -> Result<()> {
+        debug!("Animation [{}] triggered event: {:?}", animation, self);
+        let socket = io.of("/ws");
+        match self {
+            Action::Posture {
+                posture,
+                transition,
+            } => {
+                let database = database.clone();
+                let devices = tokio::task::spawn_blocking(move || {
+                    let database = database.read();
+                    match database.get::<Posture>(&posture)? {
+                        None => bail!("Posture [{}] not found", posture),
+                        Some(mut posture) => posture.play(&database, transition)?,
+                    };
+                    database.list::<Device>()
+                })
+                .await??;
+                if let Some(socket) = socket {
+                    socket.emit("device:list", &devices).ok();
+                }
+            }
+            Action::Emit { name, data } => {
+                executor.write().notify(&name);
+                if let Some(socket) = socket {
+                    let event = serde_json::json!({
+                        "animation": animation,
+                        "name": name,
+                        "data": data,
+                    });
+                    socket.emit("animation:event", &event).ok();
+                }
+            }
+            Action::Variable { name, value } => {
+                playback.write().set_variable(name.clone(), value.clone());
+                if let Some(socket) = socket {
+                    socket.emit("playback:variable", &(name, value)).ok();
+                }
+            }
+            Action::Animation {
+                animation: id,
+                options,
+            } => {
+                let other = match database.read().get::<Animation>(&id)? {
+                    None => bail!("Animation [{}] not found", id),
+                    Some(other) => other,
+                };
+                playback.write().start(other, options)?;
+                Playback::run(&playback, &database, &executor, &io);
+            }
+            Action::Webhook { url, body } => {
+                tokio::time::timeout(Duration::from_millis(WEBHOOK_TIMEOUT), post(&url, &body))
+                    .await
+                    .map_err(|_| anyhow!("Webhook {} timed out", url))??;
+            }
+        };
+        Ok(())
+    }
+
+    /// Spawns the execution of the action (failures are logged).
+    pub fn spawn(
+        self,
+        animation: Id,
+        database: &ArcDb,
+        playback: &ArcPlayback,
+        executor: &ArcExecutor,
+        io: &SocketIo,
+    ) {
+        let (database, playback, executor, io) = (
+            database.clone(),
+            playback.clone(),
+            executor.clone(),
+            io.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = self
+                .execute(animation, database, playback, executor, io)
+                .await
+            {
+                warn!("Event of animation [{}] failed: {}", animation, err);
+            }
+        });
+    }
+}
+
+/// (private)
+/// Sends a POST request with a JSON body to the given `http://host[:port]/path` url.
+/// Only local endpoints are allowed: the host must resolve to loopback addresses only.
+async fn post(url: &str, body: &Value) -> Result<()> {
+    let address = match url.strip_prefix("http://") {
+        None => bail!(
+            "Unsupported webhook url (only http:// is supported): {}",
+            url
+        ),
+        Some(address) => address,
+    };
+    let (host, path) = match address.split_once('/') {
+        None => (address, String::from("/")),
+        Some((host, path)) => (host, format!("/{}", path)),
+    };
+    let authority = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+    // The checked addresses are the ones connected to: the host is not resolved twice.
+    let addresses: Vec<SocketAddr> = lookup_host(&authority).await?.collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !address.ip().is_loopback()) {
+        bail!(
+            "Unsupported webhook url (only local endpoints are allowed): {}",
+            url
+        );
+    }
+
+    let body = serde_json::to_string(body)?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(addresses.as_slice()).await?;
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(status) if (200..300).contains(&status) => Ok(()),
+        status => bail!("Webhook {} failed with status {:?}", url, status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_keyframe_serde() {
+        let json = r#"[
+            {"time": 100, "action": {"type": "variable", "name": "mood", "value": "happy"}},
+            {"time": 200, "action": {"type": "animation", "animation": 3}},
+            {"time": 300, "action": {"type": "emit", "name": "wave"}}
+        ]"#;
+        let events: Vec<EventKeyframe> = serde_json::from_str(json).unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[0].action,
+            Action::Variable { ref name, ref value } if name == "mood" && value == "happy"
+        ));
+        assert!(matches!(
+            events[1].action,
+            Action::Animation { animation: 3, .. }
+        ));
+        assert!(matches!(events[2].action, Action::Emit { ref data, .. } if data.is_null()));
+    }
+}
@@ -0,0 +1,375 @@
+//! This file defines the `ShowExecutor`: it runs the shows (see [`Show`]) and streams their live state.
+//!
+//! Each running show is a `ShowRun` driven by a loop of its own: on each tick, the current node is checked
+//! for completion and the transitions of the node are evaluated in order. Animations are played through the
+//! playback engine and variables are shared with it (see [`Playback::get_variables`]).
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use hermes_five::pause;
+use log::warn;
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::Value;
+use socketioxide::SocketIo;
+
+use crate::animation::animation::Animation;
+use crate::animation::playback::{ArcPlayback, LayerStatus, Playback};
+use crate::animation::posture::Posture;
+use crate::animation::show::{Condition, NodeKind, Show, Trigger};
+use crate::hardware::device::Device;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+pub type ArcExecutor = Arc<RwLock<ShowExecutor>>;
+
+/// The interval (in ms) between two evaluations of a running show.
+const TICK_INTERVAL: u64 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// The live state of a running show.
+#[derive(Clone, Debug, Serialize)]
+pub struct ShowRun {
+    /// The show id.
+    pub show: Id,
+    /// The show name.
+    pub name: String,
+    /// The name of the current node.
+    pub node: String,
+    pub status: RunStatus,
+    /// The reason of the failure (if any).
+    pub error: Option<String>,
+    /// The time (in ms) spent on the current node.
+    pub elapsed: u64,
+
+    // ########################################
+    // # Volatile utility data.
+    #[serde(skip)]
+    snapshot: Show,
+    #[serde(skip)]
+    session: u64,
+    #[serde(skip)]
+    entered_at: Instant,
+    /// The expected duration (in ms) of the current node (for waits and postures).
+    #[serde(skip)]
+    duration: u64,
+    /// The playback layer of the current node (for animations).
+    #[serde(skip)]
+    layer: Option<u64>,
+    /// The events received since the last tick.
+    #[serde(skip)]
+    events: Vec<String>,
+    /// The transitions of the current node which already fired (inputs and timeouts fire once).
+    #[serde(skip)]
+    fired: HashSet<usize>,
+}
+
+impl ShowRun {
+    /// (private)
+    /// Moves to the given node and starts its action.
+    fn enter(
+        &mut self,
+        name: &str,
+        database: &ArcDb,
+        playback: &ArcPlayback,
+        executor: &ArcExecutor,
+        io: &SocketIo,
+    ) -> Result<()> {
+        if let Some(layer) = self.layer.take() {
+            playback.write().stop(layer).ok();
+        }
+        let node = match self.snapshot.nodes.get(name) {
+            None => bail!("Node [{}] not found", name),
+            Some(node) => node.clone(),
+        };
+        self.node = name.to_string();
+        self.entered_at = Instant::now();
+        self.elapsed = 0;
+        self.duration = 0;
+        self.events.clear();
+        self.fired.clear();
+
+        match node.kind {
+            NodeKind::Animation { animation, options } => {
+                let animation = match database.read().get::<Animation>(&animation)? {
+                    None => bail!("Animation [{}] not found", animation),
+                    Some(animation) => animation,
+                };
+                let layer = playback.write().start(animation, options)?;
+                self.layer = Some(layer.id);
+                Playback::run(playback, database, executor, io);
+            }
+            NodeKind::Posture {
+                posture,
+                transition,
+            } => {
+                let database = database.read();
+                let posture = match database.get::<Posture>(&posture)? {
+                    None => bail!("Posture [{}] not found", posture),
+                    Some(posture) => posture,
+                };
+                let transition = transition.unwrap_or(posture.transition.clone());
+                let moves = transition.plan(&database, posture.get_states())?;
+                self.duration = transition.get_duration(&moves);
+
+                // Delayed moves are played as keyframes: this does not wait for the transition.
+                match transition.play(moves) {
+                    Err(err) => warn!("Show failed to play posture [{}]: {}", posture.id, err),
+                    Ok(_) => {
+                        if let (Some(socket), Ok(devices)) =
+                            (io.of("/ws"), database.list::<Device>())
+                        {
+                            socket.emit("device:list", &devices).ok();
+                        }
+                    }
+                }
+            }
+            NodeKind::Wait { duration } => self.duration = duration,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// (private)
+    /// Evaluates the current node and its transitions.
+    /// Returns true if the run changed (node, status or variables).
+    fn step(
+        &mut self,
+        database: &ArcDb,
+        playback: &ArcPlayback,
+        executor: &ArcExecutor,
+        io: &SocketIo,
+    ) -> Result<bool> {
+        let node = match self.snapshot.nodes.get(&self.node) {
+            None => bail!("Node [{}] not found", self.node),
+            Some(node) => node.clone(),
+        };
+        self.elapsed = self.entered_at.elapsed().as_millis() as u64;
+        if matches!(node.kind, NodeKind::End) {
+            self.status = RunStatus::Done;
+            return Ok(true);
+        }
+
+        let mut variables = playback.read().get_variables();
+        let outcome = self.get_outcome(&node.kind, database, playback, &variables)?;
+        let events = std::mem::take(&mut self.events);
+        let mut changed = false;
+        for (index, transition) in node.transitions.iter().enumerate() {
+            let fired = match &transition.on {
+                Trigger::Complete => outcome == Some(true),
+                Trigger::Otherwise => outcome == Some(false),
+                Trigger::Event { name } => events.contains(name),
+                Trigger::Input { device, condition } => {
+                    match check_device(database, device, condition)? {
+                        true => self.fired.insert(index),
+                        false => {
+                            self.fired.remove(&index);
+                            false
+                        }
+                    }
+                }
+                Trigger::Timeout { after } => self.elapsed >= *after && self.fired.insert(index),
+            };
+            if !fired
+                || !transition
+                    .when
+                    .as_ref()
+                    .map_or(true, |check| check.check(&variables))
+            {
+                continue;
+            }
+
+            if !transition.set.is_empty() {
+                let mut playback = playback.write();
+                for (name, value) in &transition.set {
+                    playback.set_variable(name.clone(), value.clone());
+                    variables.insert(name.clone(), value.clone());
+                }
+                changed = true;
+            }
+            if let Some(to) = &transition.to {
+                self.enter(to, database, playback, executor, io)?;
+                return Ok(true);
+            }
+        }
+
+        // The node is over but no transition leads anywhere: the show is done.
+        if outcome.is_some() {
+            self.status = RunStatus::Done;
+            return Ok(true);
+        }
+        Ok(changed)
+    }
+
+    /// (private)
+    /// Retrieves the outcome of the current node: none while in progress, true when complete (or for a check
+    /// node, when its condition holds), false when the condition of a check node does not hold.
+    fn get_outcome(
+        &self,
+        kind: &NodeKind,
+        database: &ArcDb,
+        playback: &ArcPlayback,
+        variables: &HashMap<String, Value>,
+    ) -> Result<Option<bool>> {
+        Ok(match kind {
+            NodeKind::Animation { .. } => match self.layer {
+                Some(layer) if playback.read().get_status(layer) == Some(LayerStatus::Playing) => {
+                    None
+                }
+                _ => Some(true),
+            },
+            NodeKind::Posture { .. } | NodeKind::Wait { .. } => {
+                (self.elapsed >= self.duration).then_some(true)
+            }
+            NodeKind::Input { device, condition } => {
+                check_device(database, device, condition)?.then_some(true)
+            }
+            NodeKind::Check {
+                variable,
+                condition,
+            } => Some(condition.check(variables.get(variable).unwrap_or(&Value::Null))),
+            NodeKind::End => Some(true),
+        })
+    }
+}
+
+/// (private)
+/// Checks whether the state of a device satisfies the condition.
+fn check_device(database: &ArcDb, device: &Id, condition: &Condition) -> Result<bool> {
+    let device = match database.read().get::<Device>(device)? {
+        None => bail!("Device [{}] not found", device),
+        Some(device) => device,
+    };
+    let state = serde_json::to_value(device.inner.get_state())?;
+    Ok(condition.check(&state))
+}
+
+/// The shows executor state shared by all sockets.
+#[derive(Debug, Default)]
+pub struct ShowExecutor {
+    runs: HashMap<Id, ShowRun>,
+    next_session: u64,
+}
+
+impl ShowExecutor {
+    /// Retrieves the running shows.
+    pub fn get_runs(&self) -> Vec<ShowRun> {
+        self.runs.values().cloned().collect()
+    }
+
+    /// Sends an event to all running shows (see [`Trigger::Event`]).
+    pub fn notify(&mut self, name: &str) {
+        for run in self.runs.values_mut() {
+            run.events.push(name.to_string());
+        }
+    }
+
+    /// Stops a running show (and the animation it plays, if any).
+    pub fn stop(&mut self, id: Id, playback: &mut Playback) -> Result<ShowRun> {
+        match self.runs.remove(&id) {
+            None => bail!("Show [{}] is not running", id),
+            Some(mut run) => {
+                if let Some(layer) = run.layer.take() {
+                    playback.stop(layer).ok();
+                }
+                run.status = RunStatus::Done;
+                Ok(run)
+            }
+        }
+    }
+
+    /// Starts a show (restarting it if already running) and spawns its loop.
+    pub fn start(
+        executor: &ArcExecutor,
+        show: Show,
+        database: &ArcDb,
+        playback: &ArcPlayback,
+        io: &SocketIo,
+    ) -> Result<ShowRun> {
+        show.validate()?;
+        let id = show.id;
+        let mut guard = executor.write();
+        if guard.runs.contains_key(&id) {
+            guard.stop(id, &mut playback.write())?;
+        }
+        guard.next_session += 1;
+        let session = guard.next_session;
+        let start = show.start.clone();
+        let mut run = ShowRun {
+            show: id,
+            name: show.name.clone(),
+            node: start.clone(),
+            status: RunStatus::Running,
+            error: None,
+            elapsed: 0,
+            snapshot: show,
+            session,
+            entered_at: Instant::now(),
+            duration: 0,
+            layer: None,
+            events: vec![],
+            fired: HashSet::new(),
+        };
+        run.enter(&start, database, playback, executor, io)?;
+        guard.runs.insert(id, run.clone());
+        drop(guard);
+
+        let (executor, database, playback, io) = (
+            executor.clone(),
+            database.clone(),
+            playback.clone(),
+            io.clone(),
+        );
+        tokio::spawn(async move {
+            loop {
+                pause!(TICK_INTERVAL);
+                let run = {
+                    let mut guard = executor.write();
+                    let run = match guard.runs.get_mut(&id) {
+                        Some(run) if run.session == session => run,
+                        _ => break, // Stopped or restarted.
+                    };
+                    let changed = match run.step(&database, &playback, &executor, &io) {
+                        Ok(changed) => changed,
+                        Err(err) => {
+                            warn!("Show [{}] failed: {}", id, err);
+                            run.status = RunStatus::Failed;
+                            run.error = Some(err.to_string());
+                            true
+                        }
+                    };
+                    let run = run.clone();
+                    if run.status != RunStatus::Running {
+                        guard.runs.remove(&id);
+                    }
+                    match changed {
+                        true => Some(run),
+                        false => None,
+                    }
+                };
+
+                if let Some(run) = run {
+                    if let Some(socket) = io.of("/ws") {
+                        socket.emit("show:status", &run).ok();
+                        socket
+                            .emit("playback:variables", &playback.read().get_variables())
+                            .ok();
+                    }
+                    if run.status != RunStatus::Running {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(run)
+    }
+}
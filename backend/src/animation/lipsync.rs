@@ -0,0 +1,247 @@
+//! This file contains the lip-sync generation: a jaw servo track computed out of an audio file.
+//!
+//! The audio is decoded and reduced to an amplitude envelope (one RMS value per window). The envelope
+//! is normalized, gated by a threshold and smoothed, then mapped into the servo range: each window
+//! becomes a keyframe (consecutive windows with the same position are merged).
+use anyhow::{bail, Result};
+use hermes_five::utils::{Easing, State};
+use serde::Deserialize;
+
+use crate::animation::animation::{Animation, AudioLane, Keyframe, Position};
+use crate::animation::group::Group;
+use crate::extra::audio::decode_mono;
+use crate::extra::media::MediaLibrary;
+use crate::hardware::device::Device;
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
+
+/// The settings of the lip-sync generation.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LipSyncOptions {
+    /// The duration (in ms) of each keyframe (default: 50ms).
+    pub window: u64,
+    /// The smoothing factor from 0.0 (none) to 1.0 (frozen) (default: 0.5).
+    pub smoothing: f32,
+    /// The normalized amplitude (from 0.0 to 1.0) under which the mouth is closed (default: 0.1).
+    pub threshold: f32,
+    /// The servo position for a closed mouth (default: the start of the servo range).
+    pub closed: Option<u16>,
+    /// The servo position for a fully opened mouth (default: the end of the servo range).
+    pub open: Option<u16>,
+}
+
+impl Default for LipSyncOptions {
+    fn default() -> Self {
+        Self {
+            window: 50,
+            smoothing: 0.5,
+            threshold: 0.1,
+            closed: None,
+            open: None,
+        }
+    }
+}
+
+/// A lip-sync generation request.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LipSyncRequest {
+    /// The animation to insert the track into.
+    pub animation: Id,
+    /// The (jaw) servo device to animate.
+    pub device: Id,
+    /// The audio file: its name in the media library (see [`MediaLibrary::resolve`]).
+    pub media: String,
+    /// The time (in ms) the audio starts at in the animation (default: 0).
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default)]
+    pub options: LipSyncOptions,
+}
+
+impl LipSyncRequest {
+    /// Checks the request targets without decoding the audio file: see [`LipSyncRequest::apply`].
+    /// Returns the track (keyed by `Group` id) the lip-sync is generated into.
+    pub fn check(&self, database: &Database) -> Result<Id> {
+        self.get_targets(database).map(|(_, group, _)| group)
+    }
+
+    /// Decodes the audio file into its amplitude envelope.
+    ///
+    /// Decoding a whole file takes a while: this is meant to run outside the database lock.
+    pub fn analyze(&self, library: &MediaLibrary) -> Result<Vec<f32>> {
+        let path = library.resolve(&self.media)?;
+        let (samples, sample_rate) = decode_mono(&path.to_string_lossy())?;
+        Ok(envelope(&samples, sample_rate, &self.options))
+    }
+
+    /// Inserts the lip-sync track of the given envelope (see [`LipSyncRequest::analyze`]) into the animation.
+    /// If the animation has no audio lane yet, the audio file becomes its audio lane.
+    ///
+    /// # Errors
+    /// * if the audio file is not part of the media library.
+    /// * if the animation, the device or the device group cannot be found.
+    /// * if the device has no range (ie: it is not a servo).
+    /// * if the animation already has a non-empty track for the device.
+    pub fn apply(
+        &self,
+        database: &Database,
+        library: &MediaLibrary,
+        envelope: &[f32],
+    ) -> Result<Animation> {
+        let path = library.resolve(&self.media)?;
+        let (mut animation, group, (min, max)) = self.get_targets(database)?;
+        let range = (
+            self.options.closed.unwrap_or(min),
+            self.options.open.unwrap_or(max),
+        );
+        let keyframes = to_keyframes(envelope, self.device, range, self.offset, &self.options);
+
+        animation.tracks.insert(group, keyframes);
+        if animation.audio.is_none() {
+            animation.audio = Some(AudioLane {
+                path: path.to_string_lossy().to_string(),
+                offset: self.offset,
+            });
+        }
+        animation.revision += 1;
+        Ok(animation)
+    }
+
+    /// (private)
+    /// Retrieves the animation, the group of the device and the device range.
+    fn get_targets(&self, database: &Database) -> Result<(Animation, Id, (u16, u16))> {
+        let animation = match database.get::<Animation>(&self.animation)? {
+            None => bail!("Animation not found"),
+            Some(animation) => animation,
+        };
+        let device = match database.get::<Device>(&self.device)? {
+            None => bail!("Device not found"),
+            Some(device) => device,
+        };
+        let range = match device.inner.get_range() {
+            None => bail!(
+                "Device [{}] has no range: lip-sync needs a servo",
+                device.id
+            ),
+            Some(range) => range,
+        };
+        let group = match database
+            .list::<Group>()?
+            .into_values()
+            .find(|group| group.device == Some(device.id))
+        {
+            None => bail!("No group found for device [{}]", device.id),
+            Some(group) => group,
+        };
+        if animation
+            .tracks
+            .get(&group.id)
+            .is_some_and(|keyframes| !keyframes.is_empty())
+        {
+            bail!("Animation already has a track for device [{}]", device.id);
+        }
+        Ok((animation, group.id, range))
+    }
+}
+
+/// Computes the amplitude envelope of the given mono samples: one value (from 0.0 to 1.0) per window.
+pub fn envelope(samples: &[f32], sample_rate: u32, options: &LipSyncOptions) -> Vec<f32> {
+    let size = (sample_rate as u64 * options.window.max(1) / 1000).max(1) as usize;
+    let rms = samples
+        .chunks(size)
+        .map(|chunk| {
+            (chunk.iter().map(|sample| sample * sample).sum::<f32>() / chunk.len() as f32).sqrt()
+        })
+        .collect::<Vec<f32>>();
+
+    // Normalize, gate and smooth.
+    let max = rms.iter().cloned().fold(0.0f32, f32::max);
+    let threshold = options.threshold.clamp(0.0, 0.99);
+    let smoothing = options.smoothing.clamp(0.0, 0.99);
+    let mut previous = 0.0;
+    rms.into_iter()
+        .map(|value| {
+            let value = match max > 0.0 {
+                true => value / max,
+                false => 0.0,
+            };
+            let value = match value < threshold {
+                true => 0.0,
+                false => (value - threshold) / (1.0 - threshold),
+            };
+            previous += (value - previous) * (1.0 - smoothing);
+            previous
+        })
+        .collect()
+}
+
+/// Converts an envelope into keyframes for the given device, mapped into the given (closed, open) range.
+pub fn to_keyframes(
+    envelope: &[f32],
+    device: Id,
+    (closed, open): (u16, u16),
+    offset: u64,
+    options: &LipSyncOptions,
+) -> Vec<Keyframe> {
+    let mut keyframes: Vec<Keyframe> = vec![];
+    let mut last = None;
+    for (index, value) in envelope.iter().enumerate() {
+        let position = (closed as f32 + (open as f32 - closed as f32) * value).round() as u16;
+        let start = offset + index as u64 * options.window;
+        let end = start + options.window;
+
+        // Extend the previous keyframe when the position does not change.
+        if last == Some(position) {
+            if let Some(keyframe) = keyframes.last_mut() {
+                keyframe.end = end;
+                continue;
+            }
+        }
+        last = Some(position);
+        keyframes.push(Keyframe {
+            positions: vec![Position {
+                device,
+                target: State::Integer(position as u64),
+            }],
+            start,
+            end,
+            transition: Easing::Linear,
+        });
+    }
+    keyframes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let options = LipSyncOptions {
+            window: 1000,
+            smoothing: 0.0,
+            threshold: 0.1,
+            ..Default::default()
+        };
+        // Four windows of 2 samples: silence, loud, quiet (gated), half.
+        let samples = [0.0, 0.0, 0.8, -0.8, 0.05, -0.05, 0.4, -0.4];
+        let envelope = envelope(&samples, 2, &options);
+        assert_eq!(envelope.len(), 4);
+        assert_eq!(envelope[0], 0.0);
+        assert_eq!(envelope[1], 1.0);
+        assert_eq!(envelope[2], 0.0);
+        assert!((envelope[3] - 0.444).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_to_keyframes() {
+        let options = LipSyncOptions::default();
+        let keyframes = to_keyframes(&[0.0, 0.0, 1.0, 0.5], 3, (10, 30), 1000, &options);
+        let periods = keyframes
+            .iter()
+            .map(|keyframe| (keyframe.start, keyframe.end))
+            .collect::<Vec<(u64, u64)>>();
+        assert_eq!(periods, vec![(1000, 1100), (1100, 1150), (1150, 1200)]);
+    }
+}
@@ -1,3 +1,11 @@
 pub mod animation;
+pub mod edit;
+pub mod event;
+pub mod executor;
 pub mod group;
+pub mod lipsync;
+pub mod playback;
 pub mod posture;
+pub mod show;
+pub mod transition;
+pub mod version;
@@ -0,0 +1,612 @@
+//! This file defines the `Playback` engine: it plays several animations at once as prioritized layers.
+//!
+//! Each running animation is a `Layer` with a priority and a mixing `Policy` (possibly defined per device).
+//! On each tick, the engine samples every layer (see [`Animation::sample`]) and composes the device states
+//! from the lowest to the highest priority: overriding layers replace the states of the lower ones while
+//! blending layers are mixed in. Layers fade in when started and fade out when done so that the control
+//! of a device smoothly returns to the lower layers (or to its resting state). Only the states which changed
+//! are sent to the devices.
+//!
+//! Discrete devices (a sound, a text...) are not interpolated nor blended: their keyframes are triggered once,
+//! when the playhead crosses their start.
+//!
+//! The event keyframes of the layers (see [`EventKeyframe`]) are executed when the playhead crosses them:
+//! their timing is therefore accurate to one tick.
+//!
+//! Each layer plays at the speed of its animation, slowed down if its keyframes overload a power rail (see
+//! [`crate::hardware::power`]).
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use hermes_five::pause;
+use hermes_five::utils::State;
+use log::warn;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use socketioxide::SocketIo;
+
+use crate::animation::animation::{Animation, PlayOptions};
+use crate::animation::event::EventKeyframe;
+use crate::animation::executor::ArcExecutor;
+use crate::hardware::device::Device;
+use crate::utils::database::{ArcDb, Database};
+use crate::utils::entity::Id;
+
+pub type ArcPlayback = Arc<RwLock<Playback>>;
+
+/// The interval (in ms) between two ticks of the engine.
+const TICK_INTERVAL: u64 = 25;
+
+/// Defines how a layer mixes with the layers below it.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Policy {
+    /// The layer replaces the state of the lower layers.
+    #[default]
+    Override,
+    /// The layer is mixed with the lower layers (weight from 0.0 to 1.0).
+    Blend { weight: f32 },
+    /// The layer does not drive the device at all.
+    Ignore,
+}
+
+impl Policy {
+    /// Retrieves the weight of the layer (from 0.0 to 1.0) when mixed with the lower layers.
+    fn get_weight(&self) -> f32 {
+        match self {
+            Policy::Override => 1.0,
+            Policy::Blend { weight } => weight.clamp(0.0, 1.0),
+            Policy::Ignore => 0.0,
+        }
+    }
+}
+
+/// Options to play an animation as a layer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerOptions {
+    /// The priority of the layer: higher layers are composed over the lower ones (default: 0).
+    pub priority: u8,
+    /// The mixing policy of the layer (default: override).
+    pub policy: Policy,
+    /// The mixing policies specific to some devices (keyed by `Device` id).
+    pub devices: HashMap<Id, Policy>,
+    /// The duration (in ms) of the fade in / fade out of the layer (default: 300ms).
+    pub fade: u64,
+    /// The selection of the animation to play.
+    #[serde(flatten)]
+    pub play: PlayOptions,
+}
+
+impl Default for LayerOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            policy: Policy::default(),
+            devices: HashMap::new(),
+            fade: 300,
+            play: PlayOptions::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerStatus {
+    /// The layer is playing (or fading in).
+    Playing,
+    /// The layer is done and fading out: the control returns to the lower layers.
+    Releasing,
+}
+
+/// An animation played by the engine.
+#[derive(Clone, Debug, Serialize)]
+pub struct Layer {
+    /// The layer id (unique for the engine lifetime).
+    pub id: u64,
+    /// The played animation id.
+    pub animation: Id,
+    /// The played animation name.
+    pub name: String,
+    pub options: LayerOptions,
+    pub status: LayerStatus,
+    /// The current position (in ms) of the playhead.
+    pub progress: u64,
+    /// The current weight of the layer due to fading (from 0.0 to 1.0).
+    pub weight: f32,
+
+    // ########################################
+    // # Volatile utility data.
+    #[serde(skip)]
+    snapshot: Animation,
+    #[serde(skip)]
+    time: f64,
+    /// The time (in ms) from which the events have not been triggered yet.
+    #[serde(skip)]
+    cursor: f64,
+    /// The events crossed by the playhead, waiting to be executed.
+    #[serde(skip)]
+    due: Vec<EventKeyframe>,
+    /// The time ranges (in ms) crossed by the playhead since the last composition: (from, to, inclusive).
+    #[serde(skip)]
+    windows: Vec<(f64, f64, bool)>,
+}
+
+impl Layer {
+    /// (private)
+    /// Advances the layer by the given elapsed time (in ms).
+    /// Returns true if the layer status changed.
+    fn advance(&mut self, elapsed: u64) -> bool {
+        let fade = elapsed as f32 / self.options.fade.max(1) as f32;
+        if self.status == LayerStatus::Releasing {
+            self.weight = (self.weight - fade).max(0.0);
+            return false;
+        }
+        self.weight = (self.weight + fade).min(1.0);
+
+        self.time += elapsed as f64 * self.snapshot.get_speed_ratio() as f64;
+        let end = self
+            .options
+            .play
+            .end
+            .unwrap_or(self.snapshot.get_duration()) as f64;
+        let changed = match self.snapshot.get_loopback() {
+            Some(loopback) if (loopback as f64) < end => {
+                if self.time >= end {
+                    let loopback = loopback as f64;
+                    self.collect_events(end, false);
+                    self.cursor = loopback;
+                    self.time = loopback + (self.time - end) % (end - loopback);
+                }
+                false
+            }
+            _ if self.time >= end => {
+                self.time = end;
+                self.status = LayerStatus::Releasing;
+                self.collect_events(end, true);
+                true
+            }
+            _ => false,
+        };
+        self.collect_events(self.time, false);
+        self.progress = self.time as u64;
+        changed
+    }
+
+    /// (private)
+    /// Queues the events located between the cursor and the given time (in ms), then moves the cursor there.
+    fn collect_events(&mut self, to: f64, inclusive: bool) {
+        let window = (self.cursor, to, inclusive);
+        self.due.extend(
+            self.snapshot
+                .events
+                .iter()
+                .filter(|event| crosses(window, event.time))
+                .cloned(),
+        );
+        self.windows.push(window);
+        self.cursor = to;
+    }
+
+    /// (private)
+    /// Retrieves the keyframe targets whose start was crossed by the playhead since the last composition.
+    fn take_triggers(&mut self) -> Vec<(Id, State)> {
+        let windows = std::mem::take(&mut self.windows);
+        self.snapshot
+            .tracks
+            .values()
+            .flatten()
+            .filter(|keyframe| {
+                windows
+                    .iter()
+                    .any(|window| crosses(*window, keyframe.start))
+            })
+            .flat_map(|keyframe| &keyframe.positions)
+            .map(|position| (position.device, position.target.clone()))
+            .collect()
+    }
+
+    /// (private)
+    /// Checks whether the layer is done and fully faded out.
+    fn is_done(&self) -> bool {
+        self.status == LayerStatus::Releasing && self.weight <= 0.0
+    }
+}
+
+/// The playback engine state shared by all sockets.
+#[derive(Debug, Default)]
+pub struct Playback {
+    layers: Vec<Layer>,
+    /// The states of the devices before the engine took control of them.
+    rest: HashMap<Id, State>,
+    /// The last states sent to the devices.
+    sent: HashMap<Id, State>,
+    /// The variables set by the event keyframes and the shows.
+    variables: HashMap<String, Value>,
+    next_id: u64,
+    running: bool,
+}
+
+impl Playback {
+    /// Retrieves the active layers, sorted from the lowest to the highest priority.
+    pub fn get_layers(&self) -> Vec<Layer> {
+        self.layers.clone()
+    }
+
+    /// Retrieves the status of a layer (none when the layer is done).
+    pub fn get_status(&self, id: u64) -> Option<LayerStatus> {
+        self.layers
+            .iter()
+            .find(|layer| layer.id == id)
+            .map(|layer| layer.status)
+    }
+
+    /// Retrieves the variables (set by the event keyframes and the shows).
+    pub fn get_variables(&self) -> HashMap<String, Value> {
+        self.variables.clone()
+    }
+
+    /// Sets a variable.
+    pub fn set_variable(&mut self, name: String, value: Value) {
+        self.variables.insert(name, value);
+    }
+
+    /// Starts playing an animation as a new layer.
+    pub fn start(&mut self, animation: Animation, options: LayerOptions) -> Result<Layer> {
+        if let Some(end) = options.play.end {
+            if end <= options.play.start {
+                bail!(
+                    "Invalid selection: end ({}ms) must be after start ({}ms)",
+                    end,
+                    options.play.start
+                );
+            }
+        }
+        if animation.get_duration() == 0 {
+            bail!("Animation empty: check if it has keyframes.");
+        }
+
+        let mut snapshot = animation;
+        snapshot.options = options.play.clone();
+        snapshot.events.sort_by_key(|event| event.time);
+        self.next_id += 1;
+        let layer = Layer {
+            id: self.next_id,
+            animation: snapshot.id,
+            name: snapshot.name.clone(),
+            status: LayerStatus::Playing,
+            progress: options.play.start,
+            weight: 0.0,
+            time: options.play.start as f64,
+            cursor: options.play.start as f64,
+            due: vec![],
+            windows: vec![],
+            options,
+            snapshot,
+        };
+
+        // Layers of the same priority: the last started is composed over the others.
+        let index = self
+            .layers
+            .iter()
+            .position(|other| other.options.priority > layer.options.priority)
+            .unwrap_or(self.layers.len());
+        self.layers.insert(index, layer.clone());
+        Ok(layer)
+    }
+
+    /// Stops a layer: it fades out and the control returns to the lower layers.
+    pub fn stop(&mut self, id: u64) -> Result<Layer> {
+        match self.layers.iter_mut().find(|layer| layer.id == id) {
+            None => bail!("Layer [{}] not found", id),
+            Some(layer) => {
+                layer.status = LayerStatus::Releasing;
+                Ok(layer.clone())
+            }
+        }
+    }
+
+    /// Stops all layers.
+    pub fn stop_all(&mut self) {
+        for layer in &mut self.layers {
+            layer.status = LayerStatus::Releasing;
+        }
+    }
+
+    /// Spawns the engine loop (unless already running): it runs as long as there are active layers.
+    pub fn run(playback: &ArcPlayback, database: &ArcDb, executor: &ArcExecutor, io: &SocketIo) {
+        if std::mem::replace(&mut playback.write().running, true) {
+            return;
+        }
+
+        let playback = playback.clone();
+        let database = database.clone();
+        let executor = executor.clone();
+        let io = io.clone();
+        tokio::spawn(async move {
+            let mut last_tick = Instant::now();
+            loop {
+                pause!(TICK_INTERVAL);
+                let elapsed = last_tick.elapsed().as_millis() as u64;
+                last_tick = Instant::now();
+
+                let (changed, layers, events) = {
+                    let mut playback = playback.write();
+                    let (changed, events) = playback.tick(&database, elapsed);
+                    (changed, playback.get_layers(), events)
+                };
+                for (animation, event) in events {
+                    event
+                        .action
+                        .spawn(animation, &database, &playback, &executor, &io);
+                }
+                if changed {
+                    if let Some(socket) = io.of("/ws") {
+                        socket.emit("playback:status", &layers).ok();
+                    }
+                }
+                if layers.is_empty() {
+                    playback.write().running = false;
+                    break;
+                }
+            }
+        });
+    }
+
+    /// (private)
+    /// Advances all layers, drives the devices accordingly and removes the layers done.
+    /// Returns true if the layers changed, along with the events to execute (keyed by animation id).
+    fn tick(&mut self, database: &ArcDb, elapsed: u64) -> (bool, Vec<(Id, EventKeyframe)>) {
+        let mut changed = false;
+        let mut events = vec![];
+        for layer in &mut self.layers {
+            // The power budget of a layer is planned on its first tick (see [`Animation::plan_power`]).
+            if layer.snapshot.slowdown.is_none() {
+                let range = (layer.options.play.start, layer.options.play.end);
+                if let Err(err) = layer.snapshot.plan_power(&database.read(), range) {
+                    warn!("Playback failed to plan the power of a layer: {}", err);
+                    layer.snapshot.slowdown = Some(1.0);
+                }
+            }
+            changed |= layer.advance(elapsed);
+            events.extend(layer.due.drain(..).map(|event| (layer.animation, event)));
+        }
+
+        let states = self.compose(&database.read());
+        match states {
+            Ok(states) => {
+                if let Err(err) = Animation::apply_states(&mut database.write(), states, false) {
+                    warn!("Playback failed to drive devices: {}", err);
+                }
+            }
+            Err(err) => warn!("Playback failed to compose layers: {}", err),
+        }
+
+        let count = self.layers.len();
+        self.layers.retain(|layer| !layer.is_done());
+        (changed || count != self.layers.len(), events)
+    }
+
+    /// (private)
+    /// Composes the states of all devices driven by the layers, from the lowest to the highest priority.
+    /// Returns the states to send: the ones which changed and the discrete keyframes triggered.
+    fn compose(&mut self, database: &Database) -> Result<HashMap<Id, State>> {
+        let mut states: HashMap<Id, State> = HashMap::new();
+        let mut triggers: HashMap<Id, State> = HashMap::new();
+        for layer in &mut self.layers {
+            for (device_id, target) in layer.snapshot.sample(database, layer.progress)? {
+                let policy = layer
+                    .options
+                    .devices
+                    .get(&device_id)
+                    .unwrap_or(&layer.options.policy);
+                if matches!(target, State::Null) || matches!(policy, Policy::Ignore) {
+                    continue;
+                }
+                let mut device = match database.get::<Device>(&device_id)? {
+                    None => continue,
+                    Some(device) => device,
+                };
+                if device.inner.is_discrete() {
+                    continue;
+                }
+
+                // Mix with the lower layers or the resting state of the device.
+                let below = match states.remove(&device_id) {
+                    Some(state) => state,
+                    None => self
+                        .rest
+                        .entry(device_id)
+                        .or_insert_with(|| device.inner.get_state())
+                        .clone(),
+                };
+                let state = match layer.weight * policy.get_weight() {
+                    weight if weight >= 1.0 => target,
+                    weight => device.inner.scale_state(below, target, weight),
+                };
+                states.insert(device_id, state);
+            }
+
+            for (device_id, target) in layer.take_triggers() {
+                let policy = layer
+                    .options
+                    .devices
+                    .get(&device_id)
+                    .unwrap_or(&layer.options.policy);
+                if matches!(target, State::Null) || matches!(policy, Policy::Ignore) {
+                    continue;
+                }
+                if let Some(device) = database.get::<Device>(&device_id)? {
+                    if device.inner.is_discrete() {
+                        triggers.insert(device_id, target);
+                    }
+                }
+            }
+        }
+
+        // Forget the devices no longer driven.
+        self.rest
+            .retain(|device_id, _| states.contains_key(device_id));
+        self.sent
+            .retain(|device_id, _| states.contains_key(device_id));
+
+        // Only send the states which changed (triggered keyframes are always sent).
+        states.retain(|device_id, state| {
+            self.sent.insert(*device_id, state.clone()).as_ref() != Some(state)
+        });
+        states.extend(triggers);
+        Ok(states)
+    }
+}
+
+/// (private)
+/// Checks whether the given time (in ms) is within the (from, to, inclusive) window.
+fn crosses((from, to, inclusive): (f64, f64, bool), time: u64) -> bool {
+    let time = time as f64;
+    time >= from && (time < to || (inclusive && time <= to))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation::animation::fixtures::{self, keyframe};
+    use crate::animation::animation::{Keyframe, Position};
+    use crate::animation::event::Action;
+
+    use super::*;
+
+    fn animation(repeat: bool) -> Animation {
+        Animation {
+            repeat,
+            loopback: 200,
+            ..fixtures::animation(HashMap::from([(1, vec![keyframe(0, 1000)])]))
+        }
+    }
+
+    #[test]
+    fn test_layers_order() {
+        let mut playback = Playback::default();
+        let high = LayerOptions {
+            priority: 5,
+            ..Default::default()
+        };
+        let gesture = playback.start(animation(false), high).unwrap();
+        let idle = playback
+            .start(animation(true), LayerOptions::default())
+            .unwrap();
+        let ids = playback
+            .get_layers()
+            .iter()
+            .map(|layer| layer.id)
+            .collect::<Vec<u64>>();
+        assert_eq!(ids, vec![idle.id, gesture.id]);
+        assert!(playback.stop(42).is_err());
+    }
+
+    #[test]
+    fn test_layer_advance() {
+        let mut playback = Playback::default();
+        playback
+            .start(animation(false), LayerOptions::default())
+            .unwrap();
+        playback
+            .start(animation(true), LayerOptions::default())
+            .unwrap();
+
+        // Fading in.
+        let layer = &mut playback.layers[0];
+        assert!(!layer.advance(150));
+        assert_eq!(layer.progress, 150);
+        assert_eq!(layer.weight, 0.5);
+
+        // Reaching the end: the layer is released and fades out.
+        assert!(layer.advance(900));
+        assert_eq!(layer.status, LayerStatus::Releasing);
+        assert_eq!(layer.progress, 1000);
+        assert!(!layer.advance(300));
+        assert!(layer.is_done());
+
+        // Repeating layers loop back.
+        let layer = &mut playback.layers[1];
+        assert!(!layer.advance(1100));
+        assert_eq!(layer.status, LayerStatus::Playing);
+        assert_eq!(layer.progress, 300);
+    }
+
+    #[test]
+    fn test_layer_triggers() {
+        let mut animation = animation(false);
+        animation.tracks = HashMap::from([(
+            1,
+            [0, 100, 500]
+                .into_iter()
+                .map(|start| Keyframe {
+                    positions: vec![Position {
+                        device: start as Id,
+                        target: State::Integer(start),
+                    }],
+                    ..keyframe(start, start + 50)
+                })
+                .collect(),
+        )]);
+        let mut playback = Playback::default();
+        playback.start(animation, LayerOptions::default()).unwrap();
+        let triggers = |layer: &mut Layer| {
+            layer
+                .take_triggers()
+                .into_iter()
+                .map(|(device, _)| device)
+                .collect::<Vec<Id>>()
+        };
+
+        let layer = &mut playback.layers[0];
+        layer.advance(150);
+        assert_eq!(triggers(layer), vec![0, 100]);
+        layer.advance(25);
+        assert!(triggers(layer).is_empty());
+
+        // Keyframes are triggered once, even when the playhead crosses them within a single tick.
+        layer.advance(500);
+        assert_eq!(triggers(layer), vec![500]);
+        assert!(triggers(layer).is_empty());
+    }
+
+    #[test]
+    fn test_layer_events() {
+        let mut animation = animation(true);
+        animation.events = [900, 100, 500]
+            .into_iter()
+            .map(|time| EventKeyframe {
+                time,
+                action: Action::Variable {
+                    name: format!("event{}", time),
+                    value: Value::Null,
+                },
+            })
+            .collect();
+        let mut playback = Playback::default();
+        playback.start(animation, LayerOptions::default()).unwrap();
+        let times = |layer: &mut Layer| {
+            layer
+                .due
+                .drain(..)
+                .map(|event| event.time)
+                .collect::<Vec<u64>>()
+        };
+
+        let layer = &mut playback.layers[0];
+        layer.advance(150);
+        assert_eq!(times(layer), vec![100]);
+        layer.advance(300);
+        assert!(times(layer).is_empty());
+
+        // Looping back (to 200ms): the events before the loopback time are not replayed.
+        layer.advance(800);
+        assert_eq!(times(layer), vec![500, 900]);
+        assert_eq!(layer.progress, 450);
+        layer.advance(100);
+        assert_eq!(times(layer), vec![500]);
+    }
+}
@@ -1,10 +1,7 @@
-use hermes_five::pause_sync;
-use hermes_five::utils::Easing;
 use serde::{Deserialize, Serialize};
 
 use crate::animation::animation::Position;
-use crate::hardware::board::Board;
-use crate::hardware::device::Device;
+use crate::animation::transition::Transition;
 use crate::impl_entity;
 use crate::utils::database::Database;
 use crate::utils::entity::Id;
@@ -15,34 +12,29 @@ pub struct Posture {
     pub name: String,
     pub description: String,
     pub positions: Vec<Position>,
+    /// The default transition used to reach the posture.
+    #[serde(default)]
+    pub transition: Transition,
 }
 
 impl_entity!(Posture);
 
 impl Posture {
-    pub fn play(&mut self, database: &Database) -> anyhow::Result<()> {
-        for position in &self.positions {
-            database
-                .get::<Device>(&position.device)
-                .and_then(|device| match device {
-                    None => Ok(()), // Do not bother with unknown devices
-                    Some(mut device) => {
-                        database
-                            .get::<Board>(&device.bid)
-                            .and_then(|board| match board {
-                                None => Ok(()), // Should not happen ?
-                                Some(board) => match board.connected {
-                                    false => Ok(()), // Do not bother with none connected boards.
-                                    true => device
-                                        .inner
-                                        .animate(position.target.clone(), 500, Easing::SineInOut)
-                                        .map(|_| ()),
-                                },
-                            })
-                    }
-                })?;
-            pause_sync!(100);
-        }
-        Ok(())
+    /// Retrieves the target state of each device of the posture.
+    pub fn get_states(&self) -> Vec<(Id, hermes_five::utils::State)> {
+        self.positions
+            .iter()
+            .map(|position| (position.device, position.target.clone()))
+            .collect()
+    }
+
+    /// Moves all devices to the posture, using the given transition (default: the posture own transition).
+    pub fn play(
+        &mut self,
+        database: &Database,
+        transition: Option<Transition>,
+    ) -> anyhow::Result<Vec<(Id, hermes_five::utils::State)>> {
+        let transition = transition.unwrap_or(self.transition.clone());
+        transition.apply(database, self.get_states())
     }
 }
@@ -0,0 +1,235 @@
+//! This file defines a structure called `Show`: a state machine sequencing animations, postures and waits.
+//!
+//! A show is a set of named `Node`s. Each node does something (plays an animation, waits for an input, checks
+//! a variable, etc...) and owns a list of `ShowTransition`s: when the trigger of a transition fires (the node
+//! completes, an event is received, an input changes, a timeout expires) and its condition holds, the show
+//! moves on to the target node. Shows are run by the `ShowExecutor` (see [`crate::animation::executor`]).
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::animation::playback::LayerOptions;
+use crate::animation::transition::Transition;
+use crate::impl_entity;
+use crate::utils::entity::Id;
+
+/// Defines the structure of a show entity.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Show {
+    pub id: Id,
+    /// The name of the show.
+    pub name: String,
+    /// The description of the show.
+    #[serde(default)]
+    pub description: String,
+    /// The name of the node the show starts with.
+    pub start: String,
+    /// The nodes of the show, keyed by name.
+    pub nodes: HashMap<String, Node>,
+}
+impl_entity!(Show);
+
+impl Show {
+    /// Checks the show consistency: the starting node and the targets of all transitions must exist.
+    pub fn validate(&self) -> Result<()> {
+        if !self.nodes.contains_key(&self.start) {
+            bail!("Starting node [{}] not found", self.start);
+        }
+        for (name, node) in &self.nodes {
+            for transition in &node.transitions {
+                if let Some(to) = &transition.to {
+                    if !self.nodes.contains_key(to) {
+                        bail!(
+                            "Node [{}] has a transition to an unknown node [{}]",
+                            name,
+                            to
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A step of a show.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(flatten)]
+    pub kind: NodeKind,
+    /// The transitions to other nodes, evaluated in order.
+    #[serde(default)]
+    pub transitions: Vec<ShowTransition>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NodeKind {
+    /// Plays an animation (as a playback engine layer): completes when the animation ends.
+    Animation {
+        animation: Id,
+        #[serde(default)]
+        options: LayerOptions,
+    },
+    /// Plays a posture: completes when the devices reached it.
+    Posture {
+        posture: Id,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Waits for the given duration (in ms).
+    Wait { duration: u64 },
+    /// Waits for the state of a device to satisfy the condition.
+    Input { device: Id, condition: Condition },
+    /// Checks a variable: completes right away, triggering `complete` if the condition holds, `otherwise` if not.
+    Check {
+        variable: String,
+        condition: Condition,
+    },
+    /// Ends the show.
+    End,
+}
+
+/// A transition from a node to another.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShowTransition {
+    /// The target node: none means the show stays on the current node (ie: only to set variables).
+    #[serde(default)]
+    pub to: Option<String>,
+    /// What fires the transition (default: the completion of the node).
+    #[serde(default)]
+    pub on: Trigger,
+    /// An additional condition on a variable for the transition to be taken.
+    #[serde(default)]
+    pub when: Option<VariableCheck>,
+    /// The variables set when the transition is taken.
+    #[serde(default)]
+    pub set: HashMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Trigger {
+    /// The node completed (for a check node: the condition holds).
+    #[default]
+    Complete,
+    /// The condition of a check node does not hold.
+    Otherwise,
+    /// An event of the given name has been received (see `show:event`).
+    Event { name: String },
+    /// The state of a device starts satisfying the condition.
+    Input { device: Id, condition: Condition },
+    /// The given time (in ms) elapsed since the node started.
+    Timeout { after: u64 },
+}
+
+/// A condition on a variable.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VariableCheck {
+    pub variable: String,
+    pub condition: Condition,
+}
+
+impl VariableCheck {
+    /// Checks the condition against the given variables (an unset variable is null).
+    pub fn check(&self, variables: &HashMap<String, Value>) -> bool {
+        self.condition
+            .check(variables.get(&self.variable).unwrap_or(&Value::Null))
+    }
+}
+
+/// A comparison of a value (a variable, a device state) to a reference value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Condition {
+    pub op: Operator,
+    #[serde(default)]
+    pub value: Value,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// The value is set and neither false, 0 nor an empty string.
+    Truthy,
+}
+
+impl Condition {
+    /// Checks whether the given value satisfies the condition.
+    /// Numbers are compared as such whatever their representation (ie: 1 == 1.0).
+    pub fn check(&self, actual: &Value) -> bool {
+        let numbers = actual.as_f64().zip(self.value.as_f64());
+        match self.op {
+            Operator::Eq => numbers.map_or(actual == &self.value, |(a, b)| a == b),
+            Operator::Ne => numbers.map_or(actual != &self.value, |(a, b)| a != b),
+            Operator::Gt => numbers.is_some_and(|(a, b)| a > b),
+            Operator::Gte => numbers.is_some_and(|(a, b)| a >= b),
+            Operator::Lt => numbers.is_some_and(|(a, b)| a < b),
+            Operator::Lte => numbers.is_some_and(|(a, b)| a <= b),
+            Operator::Truthy => match actual {
+                Value::Null => false,
+                Value::Bool(value) => *value,
+                Value::Number(value) => value.as_f64() != Some(0.0),
+                Value::String(value) => !value.is_empty(),
+                _ => true,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let condition = |op, value| Condition { op, value };
+        assert!(condition(Operator::Eq, json!(1)).check(&json!(1.0)));
+        assert!(condition(Operator::Eq, json!("a")).check(&json!("a")));
+        assert!(condition(Operator::Ne, json!(true)).check(&json!(false)));
+        assert!(condition(Operator::Gt, json!(10)).check(&json!(12)));
+        assert!(!condition(Operator::Lte, json!(10)).check(&json!("5")));
+        assert!(condition(Operator::Truthy, Value::Null).check(&json!(3)));
+        assert!(!condition(Operator::Truthy, Value::Null).check(&json!("")));
+        assert!(!condition(Operator::Truthy, Value::Null).check(&Value::Null));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut show: Show = serde_json::from_value(json!({
+            "id": 1,
+            "name": "exhibit",
+            "start": "intro",
+            "nodes": {
+                "intro": {
+                    "type": "animation",
+                    "animation": 3,
+                    "transitions": [
+                        {"on": {"type": "input", "device": 2, "condition": {"op": "eq", "value": true}}, "set": {"pressed": true}},
+                        {"to": "ending_a", "when": {"variable": "pressed", "condition": {"op": "truthy"}}},
+                        {"to": "ending_b"}
+                    ]
+                },
+                "ending_a": {"type": "wait", "duration": 1000},
+                "ending_b": {"type": "end"}
+            }
+        }))
+        .unwrap();
+        assert!(show.validate().is_ok());
+
+        show.start = String::from("unknown");
+        assert!(show.validate().is_err());
+        show.start = String::from("intro");
+        show.nodes.remove("ending_b");
+        assert!(show.validate().is_err());
+    }
+}
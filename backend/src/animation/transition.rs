@@ -0,0 +1,118 @@
+//! This file defines a structure called `Transition`: how devices move from their current state to a new one.
+//!
+//! Transitions are used when playing a `Posture`, when an animation replaces another one (the devices
+//! blend from their current state to the starting point of the new animation) and when an animation is
+//! stopped (the devices smoothly return to their default state).
+use anyhow::Result;
+use hermes_five::animation::{Keyframe, Segment};
+use hermes_five::utils::{Easing, State};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::animation::Animation;
+use crate::hardware::device::Device;
+use crate::hardware::power::{PowerModel, Slot};
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transition {
+    /// The duration (in ms) of the move of each device (default: 500ms).
+    pub duration: u64,
+    /// The easing function applied to the move (default: SineInOut).
+    pub easing: Easing,
+    /// The delay (in ms) between the moves of two devices (default: 0, ie: all devices move in parallel).
+    pub stagger: u64,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Self {
+            duration: 500,
+            easing: Easing::SineInOut,
+            stagger: 0,
+        }
+    }
+}
+
+/// A planned move: the device, its target state and when it moves.
+pub type Move = (Device, State, Slot);
+
+impl Transition {
+    /// Plans the moves of the given devices (in order) to the given states.
+    ///
+    /// Unknown devices and devices of disconnected boards are ignored. The moves are scheduled by the
+    /// power model: they may be delayed or slowed down to keep the current drawn within the rails budget.
+    pub fn plan(&self, database: &Database, states: Vec<(Id, State)>) -> Result<Vec<Move>> {
+        let mut moves = vec![];
+        for (device_id, state) in states {
+            let device = match database.get::<Device>(&device_id)? {
+                None => continue, // Do not bother with unknown devices
+                Some(device) => device,
+            };
+            if matches!(state, State::Null) || !Animation::is_connected(database, &device)? {
+                continue;
+            }
+            moves.push((device, state));
+        }
+
+        let schedule = PowerModel::load(database).schedule(
+            moves.iter().map(|(device, _)| device),
+            self.duration,
+            self.stagger,
+        );
+        Ok(moves
+            .into_iter()
+            .zip(schedule.slots)
+            .map(|((device, state), slot)| (device, state, slot))
+            .collect())
+    }
+
+    /// Retrieves the time (in ms) all the planned moves are over (at least the transition duration).
+    pub fn get_duration(&self, moves: &[Move]) -> u64 {
+        moves
+            .iter()
+            .map(|(_, _, slot)| slot.end())
+            .max()
+            .unwrap_or(0)
+            .max(self.duration)
+    }
+
+    /// Moves the devices as planned.
+    ///
+    /// This does not block: the delayed moves are played as keyframes starting at their delay.
+    /// Returns the list of mutations: (device id, new state).
+    pub fn play(&self, moves: Vec<Move>) -> Result<Vec<(Id, State)>> {
+        let mut mutations = vec![];
+        let mut delayed = vec![];
+        for (mut device, state, slot) in moves {
+            let state = match slot.delay {
+                0 => device.inner.animate(state, slot.duration, self.easing)?,
+                delay => {
+                    delayed.push(device.inner.into_track()?.with_keyframe(
+                        Keyframe::new(state.clone(), delay, slot.end()).set_transition(self.easing),
+                    ));
+                    state
+                }
+            };
+            mutations.push((device.id, state));
+        }
+
+        if !delayed.is_empty() {
+            let segment = delayed
+                .into_iter()
+                .fold(Segment::default(), |segment, track| {
+                    segment.with_track(track)
+                });
+            hermes_five::animation::Animation::from(segment).play();
+        }
+        Ok(mutations)
+    }
+
+    /// Moves the given devices (in order) to the given states: see [`Transition::plan`].
+    /// Returns the list of mutations: (device id, new state).
+    pub fn apply(&self, database: &Database, states: Vec<(Id, State)>) -> Result<Vec<(Id, State)>> {
+        let moves = self.plan(database, states)?;
+        self.play(moves)
+    }
+}
@@ -0,0 +1,297 @@
+//! This file defines a structure called `AnimationVersion`: a named snapshot of an `Animation`.
+//!
+//! Versions are stored as their own entity in the `Database`, alongside the animation they belong to.
+//! They can be listed, restored (the snapshot replaces the current animation content) and compared
+//! to one another (or to the current animation) via a structured `AnimationDiff`.
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::animation::{Animation, Keyframe};
+use crate::impl_entity;
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
+
+/// Defines the structure of an animation version entity.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnimationVersion {
+    pub id: Id,
+    /// The id of the animation this version is a snapshot of.
+    pub animation: Id,
+    /// The name of the version (ex: "before rehearsal", "final show").
+    pub name: String,
+    /// The creation timestamp of the version (in ms since epoch).
+    pub created_at: i64,
+    /// The animation as it was when the version has been created.
+    pub snapshot: Animation,
+}
+impl_entity!(AnimationVersion);
+
+impl AnimationVersion {
+    /// Creates a new (unsaved) version from the given animation.
+    pub fn new<S: Into<String>>(animation: &Animation, name: S) -> Self {
+        Self {
+            id: 0,
+            animation: animation.id,
+            name: name.into(),
+            created_at: Utc::now().timestamp_millis(),
+            snapshot: animation.clone(),
+        }
+    }
+
+    /// Lists all versions of the given animation, sorted from the oldest to the newest.
+    pub fn list_for(database: &Database, animation: &Id) -> Result<Vec<AnimationVersion>> {
+        let mut versions = database
+            .list::<AnimationVersion>()?
+            .into_values()
+            .filter(|version| &version.animation == animation)
+            .collect::<Vec<AnimationVersion>>();
+        versions.sort_by_key(|version| (version.created_at, version.id));
+        Ok(versions)
+    }
+
+    /// Restores the version: the snapshot content replaces the current animation content.
+    pub fn restore(&self, database: &mut Database) -> Result<Animation> {
+        let current = match database.get::<Animation>(&self.animation)? {
+            None => bail!("Animation [{}] not found", self.animation),
+            Some(current) => current,
+        };
+        let mut animation = self.snapshot.clone();
+        animation.id = self.animation;
+        animation.revision = current.revision + 1;
+        database.update(animation)
+    }
+
+    /// Retrieves the tracks (keyed by `Group` id) replaced when restoring the version: the tracks of the
+    /// current animation along with the ones of the snapshot.
+    pub fn get_replaced_tracks(&self, database: &Database) -> Result<BTreeSet<Id>> {
+        let mut tracks: BTreeSet<Id> = self.snapshot.tracks.keys().copied().collect();
+        if let Some(current) = database.get::<Animation>(&self.animation)? {
+            tracks.extend(current.tracks.into_keys());
+        }
+        Ok(tracks)
+    }
+
+    /// Computes the differences between this version and another version of the same animation.
+    /// When no other version is given, the comparison is made against the current animation.
+    pub fn diff(&self, database: &Database, other: Option<Id>) -> Result<AnimationDiff> {
+        let target = match other {
+            None => match database.get::<Animation>(&self.animation)? {
+                None => bail!("Animation [{}] not found", self.animation),
+                Some(animation) => animation,
+            },
+            Some(other) => match database.get::<AnimationVersion>(&other)? {
+                None => bail!("Version [{}] not found", other),
+                Some(version) if version.animation != self.animation => {
+                    bail!("Version [{}] belongs to another animation", other)
+                }
+                Some(version) => version.snapshot,
+            },
+        };
+        Ok(AnimationDiff::between(&self.snapshot, &target))
+    }
+}
+
+// ########################################
+// Diff
+
+/// The structured differences between two animations.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AnimationDiff {
+    /// The names of the animation properties that changed (name, speed, fps, etc...).
+    pub properties: Vec<String>,
+    /// The track differences keyed by the track (`Group`) id: unchanged tracks are omitted.
+    pub tracks: HashMap<Id, TrackDiff>,
+}
+
+/// Describes how a track changed between two animations.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum TrackDiff {
+    Added,
+    Removed,
+    Changed { keyframes: Vec<KeyframeDiff> },
+}
+
+/// Describes how a keyframe changed between two animations.
+/// Keyframes of a track are matched by their start time.
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyframeDiff {
+    /// The start time (in ms) of the keyframe.
+    pub start: u64,
+    /// The kind of change.
+    pub change: Change,
+    /// The keyframe before the change (if any).
+    pub before: Option<Keyframe>,
+    /// The keyframe after the change (if any).
+    pub after: Option<Keyframe>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl AnimationDiff {
+    /// Computes the differences to go from the `from` animation to the `to` animation.
+    pub fn between(from: &Animation, to: &Animation) -> Self {
+        let mut properties = vec![];
+        if from.name != to.name {
+            properties.push(String::from("name"));
+        }
+        if from.description != to.description {
+            properties.push(String::from("description"));
+        }
+        if from.repeat != to.repeat {
+            properties.push(String::from("repeat"));
+        }
+        if from.loopback != to.loopback {
+            properties.push(String::from("loopback"));
+        }
+        if from.speed != to.speed {
+            properties.push(String::from("speed"));
+        }
+        if from.fps != to.fps {
+            properties.push(String::from("fps"));
+        }
+        if !same(&from.audio, &to.audio) {
+            properties.push(String::from("audio"));
+        }
+        if !same(&from.events, &to.events) {
+            properties.push(String::from("events"));
+        }
+
+        let mut tracks = HashMap::new();
+        let track_ids = from
+            .tracks
+            .keys()
+            .chain(to.tracks.keys())
+            .collect::<BTreeSet<&Id>>();
+        for track_id in track_ids {
+            match (from.tracks.get(track_id), to.tracks.get(track_id)) {
+                (Some(_), None) => {
+                    tracks.insert(*track_id, TrackDiff::Removed);
+                }
+                (None, Some(_)) => {
+                    tracks.insert(*track_id, TrackDiff::Added);
+                }
+                (Some(before), Some(after)) => {
+                    let keyframes = Self::diff_keyframes(before, after);
+                    if !keyframes.is_empty() {
+                        tracks.insert(*track_id, TrackDiff::Changed { keyframes });
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+
+        Self { properties, tracks }
+    }
+
+    /// Returns true if both animations are identical.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty() && self.tracks.is_empty()
+    }
+
+    /// (private)
+    /// Computes the keyframe differences of a track.
+    fn diff_keyframes(before: &[Keyframe], after: &[Keyframe]) -> Vec<KeyframeDiff> {
+        let starts = before
+            .iter()
+            .chain(after.iter())
+            .map(|keyframe| keyframe.start)
+            .collect::<BTreeSet<u64>>();
+
+        starts
+            .into_iter()
+            .filter_map(|start| {
+                let old = before.iter().find(|keyframe| keyframe.start == start);
+                let new = after.iter().find(|keyframe| keyframe.start == start);
+                let change = match (old, new) {
+                    (Some(_), None) => Change::Removed,
+                    (None, Some(_)) => Change::Added,
+                    (Some(old), Some(new)) => match same(old, new) {
+                        true => return None,
+                        false => Change::Modified,
+                    },
+                    (None, None) => return None,
+                };
+                Some(KeyframeDiff {
+                    start,
+                    change,
+                    before: old.cloned(),
+                    after: new.cloned(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Compares two serializable values by their serialized representation.
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation::animation::fixtures::{animation, keyframe};
+
+    use super::*;
+
+    #[test]
+    fn test_diff_identical() {
+        let from = animation(HashMap::from([(1, vec![keyframe(0, 100)])]));
+        let diff = AnimationDiff::between(&from, &from.clone());
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_diff_properties() {
+        let from = animation(HashMap::new());
+        let mut to = from.clone();
+        to.name = String::from("final show");
+        to.speed = 50;
+        let diff = AnimationDiff::between(&from, &to);
+        assert_eq!(diff.properties, vec!["name", "speed"]);
+        assert!(diff.tracks.is_empty());
+    }
+
+    #[test]
+    fn test_diff_tracks() {
+        let from = animation(HashMap::from([
+            (1, vec![keyframe(0, 100)]),
+            (2, vec![keyframe(0, 100), keyframe(200, 300)]),
+        ]));
+        let to = animation(HashMap::from([
+            (2, vec![keyframe(0, 150), keyframe(400, 500)]),
+            (3, vec![keyframe(0, 100)]),
+        ]));
+        let diff = AnimationDiff::between(&from, &to);
+
+        assert!(matches!(diff.tracks.get(&1), Some(TrackDiff::Removed)));
+        assert!(matches!(diff.tracks.get(&3), Some(TrackDiff::Added)));
+        match diff.tracks.get(&2) {
+            Some(TrackDiff::Changed { keyframes }) => {
+                let changes = keyframes
+                    .iter()
+                    .map(|keyframe| (keyframe.start, keyframe.change.clone()))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    changes,
+                    vec![
+                        (0, Change::Modified),
+                        (200, Change::Removed),
+                        (400, Change::Added)
+                    ]
+                );
+            }
+            other => panic!("Unexpected track diff: {:?}", other),
+        }
+    }
+}
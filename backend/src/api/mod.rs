@@ -4,6 +4,10 @@
 //! This API is currently implemented using `axum` crate.
 use socketioxide::SocketIo;
 
+use crate::animation::executor::ArcExecutor;
+use crate::animation::playback::ArcPlayback;
+use crate::api::sockets::collaboration::ArcCollaboration;
+use crate::extra::media::MediaLibrary;
 use crate::utils::database::ArcDb;
 
 mod payloads;
@@ -14,4 +18,8 @@ pub mod sockets;
 pub struct AppState {
     pub database: ArcDb,
     pub socket: SocketIo,
+    pub media: MediaLibrary,
+    pub playback: ArcPlayback,
+    pub executor: ArcExecutor,
+    pub collaboration: ArcCollaboration,
 }
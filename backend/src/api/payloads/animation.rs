@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::animation::animation::{Animation, Keyframe};
+use crate::animation::animation::{Animation, AudioLane, Keyframe, PlayOptions};
+use crate::animation::event::EventKeyframe;
+use crate::animation::transition::Transition;
 use crate::utils::entity::Id;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -15,6 +17,9 @@ pub struct AnimationPayload {
     pub speed: u8,
     pub fps: u8,
     pub tracks: HashMap<Id, Vec<Keyframe>>,
+    pub revision: u64,
+    pub audio: Option<AudioLane>,
+    pub events: Vec<EventKeyframe>,
     pub playing: bool,
     pub duration: u64,
     pub progress: u64,
@@ -22,6 +27,8 @@ pub struct AnimationPayload {
 
 impl From<Animation> for AnimationPayload {
     fn from(animation: Animation) -> Self {
+        let duration = animation.get_duration();
+        let progress = animation.get_progress();
         Self {
             id: animation.id,
             name: animation.name,
@@ -31,9 +38,52 @@ impl From<Animation> for AnimationPayload {
             speed: animation.speed,
             fps: animation.fps,
             playing: animation.inner.is_playing(),
-            duration: animation.inner.get_duration(),
-            progress: animation.inner.get_progress(),
+            duration,
+            progress,
             tracks: animation.tracks,
+            revision: animation.revision,
+            audio: animation.audio,
+            events: animation.events,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateVersion {
+    pub name: String,
+}
+
+/// The request to play an animation: either its id only or its id along with the play options.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PlayAnimation {
+    Id(Id),
+    WithOptions(Id, PlayOptions),
+}
+
+impl PlayAnimation {
+    pub fn into_parts(self) -> (Id, PlayOptions) {
+        match self {
+            PlayAnimation::Id(id) => (id, PlayOptions::default()),
+            PlayAnimation::WithOptions(id, options) => (id, options),
+        }
+    }
+}
+
+/// The request to stop an animation: either its id only or its id along with a transition to bring the
+/// animated devices back to their default state.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum StopAnimation {
+    Id(Id),
+    WithTransition(Id, Transition),
+}
+
+impl StopAnimation {
+    pub fn into_parts(self) -> (Id, Option<Transition>) {
+        match self {
+            StopAnimation::Id(id) => (id, None),
+            StopAnimation::WithTransition(id, transition) => (id, Some(transition)),
         }
     }
 }
@@ -1,6 +1,9 @@
+use anyhow::{anyhow, Result};
 use serde::Deserialize;
+use serde_json::{json, Value};
 
 use crate::hardware::board::{Board, BoardType};
+use crate::hardware::profile::BoardProfile;
 
 // ########################################
 // API data exchange.
@@ -8,18 +11,46 @@ use crate::hardware::board::{Board, BoardType};
 #[derive(Deserialize, Debug)]
 pub struct CreateBoard {
     pub name: String,
-    #[allow(dead_code)]
     pub model: BoardType,
+    /// The serial port (default: the one of the model profile).
+    #[serde(default)]
+    pub port: Option<String>,
+    /// The connection protocol, as stored in a board (default: a serial protocol on the `port`).
+    #[serde(default)]
+    pub protocol: Option<Value>,
 }
 
-impl Into<Board> for CreateBoard {
-    fn into(self) -> Board {
-        Board {
+impl TryFrom<CreateBoard> for Board {
+    type Error = anyhow::Error;
+
+    fn try_from(payload: CreateBoard) -> Result<Self> {
+        // Boards with a known profile default to the serial settings of their model.
+        let profile = BoardProfile::get(&payload.model);
+        let protocol = payload.protocol.or_else(|| {
+            payload
+                .port
+                .or_else(|| profile.as_ref().map(|profile| profile.port.to_string()))
+                .map(|port| json!({"type": "SerialProtocol", "port": port}))
+        });
+        let inner = match protocol {
+            None => Default::default(),
+            Some(mut protocol) => {
+                if let Some(profile) = &profile {
+                    if protocol["type"] == "SerialProtocol" && protocol.get("baud").is_none() {
+                        protocol["baud"] = json!(profile.baud);
+                    }
+                }
+                serde_json::from_value(json!({ "protocol": protocol }))
+                    .map_err(|error| anyhow!("Invalid protocol: {}", error))?
+            }
+        };
+        Ok(Board {
             id: 0,
-            name: self.name,
-            inner: Default::default(),
+            name: payload.name,
+            inner,
             connected: false,
-            model: Default::default(),
-        }
+            firmware: None,
+            model: payload.model,
+        })
     }
 }
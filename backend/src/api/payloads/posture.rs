@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+use crate::animation::transition::Transition;
+use crate::utils::entity::Id;
+
+/// The request to play a posture: either its id only or its id along with a specific transition.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PlayPosture {
+    Id(Id),
+    WithTransition(Id, Transition),
+}
+
+impl PlayPosture {
+    pub fn into_parts(self) -> (Id, Option<Transition>) {
+        match self {
+            PlayPosture::Id(id) => (id, None),
+            PlayPosture::WithTransition(id, transition) => (id, Some(transition)),
+        }
+    }
+}
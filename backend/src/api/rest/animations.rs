@@ -1,21 +1,259 @@
 //! This file provides general routes and handlers for CRUD operations regarding `Animation`s specifically.
 
+use std::collections::HashMap;
+
 use axum::{Json, Router};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use socketioxide::SocketIo;
+use axum::routing::{delete, get, post};
+use log::debug;
+use serde::Deserialize;
 
-use crate::animations::animation::Animation;
-use crate::storage::storage::Storage;
+use crate::animation::animation::{Animation, PlayOptions};
+use crate::animation::version::AnimationVersion;
+use crate::api::AppState;
+use crate::api::payloads::animation::{AnimationPayload, CreateVersion};
+use crate::api::sockets::animations::play_animation;
+use crate::extra::audio::Waveform;
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
 
 /// Consolidates all available REST API routes for `Animation`.
 pub(crate) fn routes() -> Router<AppState> {
-    Router::new().route("/", get(handler_animations_list))
+    Router::new()
+        .route("/", get(handler_animations_list))
+        .route("/:id/play", post(handler_play_animation))
+        .route("/:id/audio/waveform", get(handler_audio_waveform))
+        .route(
+            "/:id/versions",
+            get(handler_versions_list).post(handler_create_version),
+        )
+        .route("/:id/versions/:version", delete(handler_delete_version))
+        .route("/:id/versions/:version/diff", get(handler_diff_version))
+        .route(
+            "/:id/versions/:version/restore",
+            post(handler_restore_version),
+        )
+}
+
+/// Query parameters for the version diff endpoint.
+#[derive(Debug, Deserialize)]
+struct DiffQuery {
+    /// The version to compare to (default: the current animation).
+    to: Option<Id>,
+}
+
+/// Query parameters for the waveform endpoint.
+#[derive(Debug, Deserialize)]
+struct WaveformQuery {
+    /// The number of peaks to compute (default: 1000).
+    buckets: Option<usize>,
 }
 
 /// GET /:version/animations.
 /// Retrieves all animations information.
-async fn handler_animations_list() -> impl IntoResponse {
-    let animations = Storage::list::<Animation>().unwrap();
+async fn handler_animations_list(State(state): State<AppState>) -> impl IntoResponse {
+    debug!("REST API: [animation:list]");
+    let animations = state
+        .database
+        .read()
+        .list::<Animation>()
+        .unwrap()
+        .into_iter()
+        .map(|(id, animation)| (id, AnimationPayload::from(animation)))
+        .collect::<HashMap<Id, AnimationPayload>>();
     Json(animations)
 }
+
+/// POST /:version/animations/:id/play.
+/// Plays an animation, or a selection of it when play options are given.
+async fn handler_play_animation(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+    options: Option<Json<PlayOptions>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [animation:play] animation {}", id);
+    let options = options.map(|Json(options)| options).unwrap_or_default();
+    let animation = play_animation(
+        &state.database,
+        &state.playback,
+        &state.executor,
+        &id,
+        options,
+        &state.socket,
+    )
+    .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    if let Some(socket) = state.socket.of("/ws") {
+        socket.emit("animation:played", &animation).ok();
+    }
+    Ok(Json(animation))
+}
+
+/// GET /:version/animations/:id/audio/waveform?buckets=:buckets.
+/// Retrieves the waveform of the animation audio lane.
+async fn handler_audio_waveform(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+    Query(query): Query<WaveformQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [animation:waveform] animation {}", id);
+    let audio = state
+        .database
+        .read()
+        .get::<Animation>(&id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?
+        .audio
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Decoding a whole file takes a while: do not block the server meanwhile.
+    let buckets = query.buckets.unwrap_or(1000);
+    let waveform = tokio::task::spawn_blocking(move || Waveform::from_file(&audio.path, buckets))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+    Ok(Json(waveform))
+}
+
+/// GET /:version/animations/:id/versions.
+/// Retrieves all versions of an animation.
+async fn handler_versions_list(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    debug!("REST API: [version:list] animation {}", id);
+    let versions = AnimationVersion::list_for(&state.database.read(), &id)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(Json(versions))
+}
+
+/// POST /:version/animations/:id/versions.
+/// Creates a new version (snapshot) of an animation.
+async fn handler_create_version(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+    Json(payload): Json<CreateVersion>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    debug!("REST API: [version:create] animation {}", id);
+    let mut database = state.database.write();
+    let animation = database
+        .get::<Animation>(&id)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Animation [{}] not found", id),
+            )
+        })?;
+    let version = database
+        .insert(AnimationVersion::new(&animation, payload.name))
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    if let Some(socket) = state.socket.of("/ws") {
+        socket.emit("version:updated", &version).ok();
+    }
+    Ok(Json(version))
+}
+
+/// GET /:version/animations/:id/versions/:version/diff?to=:other.
+/// Retrieves the differences between a version and another version (default: the current animation).
+async fn handler_diff_version(
+    State(state): State<AppState>,
+    Path((id, version)): Path<(Id, Id)>,
+    Query(query): Query<DiffQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    debug!(
+        "REST API: [version:diff] version {} of animation {}",
+        version, id
+    );
+    let database = state.database.read();
+    let version = get_version(&database, &id, &version)?;
+    let diff = version
+        .diff(&database, query.to)
+        .map_err(error(StatusCode::BAD_REQUEST))?;
+    Ok(Json(diff))
+}
+
+/// POST /:version/animations/:id/versions/:version/restore.
+/// Restores a version: the animation content is replaced by the version snapshot.
+/// None of the replaced tracks may be locked by an editor.
+async fn handler_restore_version(
+    State(state): State<AppState>,
+    Path((id, version)): Path<(Id, Id)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    debug!(
+        "REST API: [version:restore] version {} of animation {}",
+        version, id
+    );
+    let collaboration = state.collaboration.read();
+    let mut database = state.database.write();
+    let version = get_version(&database, &id, &version)?;
+    let tracks = version
+        .get_replaced_tracks(&database)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?;
+    // The REST API is no editor: any lock applies.
+    collaboration
+        .check_tracks("", id, &tracks)
+        .map_err(error(StatusCode::CONFLICT))?;
+    let animation = version
+        .restore(&mut database)
+        .map(AnimationPayload::from)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    if let Some(socket) = state.socket.of("/ws") {
+        socket.emit("animation:updated", &animation).ok();
+    }
+    Ok(Json(animation))
+}
+
+/// DELETE /:version/animations/:id/versions/:version.
+/// Deletes a version.
+async fn handler_delete_version(
+    State(state): State<AppState>,
+    Path((id, version)): Path<(Id, Id)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    debug!(
+        "REST API: [version:delete] version {} of animation {}",
+        version, id
+    );
+    let mut database = state.database.write();
+    let version = get_version(&database, &id, &version)?;
+    database
+        .delete::<AnimationVersion>(version.id)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    if let Some(socket) = state.socket.of("/ws") {
+        socket.emit("version:deleted", &version).ok();
+    }
+    Ok(Json(version))
+}
+
+/// (private)
+/// Retrieves a version, ensuring it belongs to the given animation.
+fn get_version(
+    database: &Database,
+    animation: &Id,
+    version: &Id,
+) -> Result<AnimationVersion, (StatusCode, String)> {
+    database
+        .get::<AnimationVersion>(version)
+        .map_err(error(StatusCode::INTERNAL_SERVER_ERROR))?
+        .filter(|existing| &existing.animation == animation)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Version [{}] not found for animation [{}]",
+                    version, animation
+                ),
+            )
+        })
+}
+
+/// (private)
+/// Converts an error into a response with the given status code and the error message.
+fn error<E: ToString>(status: StatusCode) -> impl Fn(E) -> (StatusCode, String) {
+    move |err| (status, err.to_string())
+}
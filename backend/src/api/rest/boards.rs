@@ -1,17 +1,25 @@
 //! This file provides general routes and handlers for CRUD operations regarding `Board`s specifically.
 
 use axum::{Json, Router};
-use axum::extract::State;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
+use log::debug;
 
 use crate::api::AppState;
 use crate::api::payloads::board::CreateBoard;
 use crate::hardware::board::Board;
+use crate::hardware::pins::PinMap;
+use crate::hardware::profile::BoardProfile;
+use crate::utils::entity::Id;
 
 /// Consolidates all available REST API routes for `Board`.
 pub(crate) fn routes() -> Router<AppState> {
-    Router::new().route("/", get(handler_boards_list).post(handler_create_board))
+    Router::new()
+        .route("/", get(handler_boards_list).post(handler_create_board))
+        .route("/profiles", get(handler_board_profiles))
+        .route("/:id/pins", get(handler_board_pins))
 }
 
 /// GET /:version/boards.
@@ -26,8 +34,32 @@ async fn handler_boards_list(State(state): State<AppState>) -> impl IntoResponse
 async fn handler_create_board(
     State(state): State<AppState>,
     Json(payload): Json<CreateBoard>,
-) -> impl IntoResponse {
-    let board: Board = payload.into();
+) -> Result<impl IntoResponse, StatusCode> {
+    let board = Board::try_from(payload).map_err(|_| StatusCode::BAD_REQUEST)?;
     let board = state.database.write().insert(board).unwrap();
-    Json(board)
+    Ok(Json(board))
+}
+
+/// GET /:version/boards/:id/pins.
+/// Retrieves the pin capabilities of a board (reported by the board when open, known from its model otherwise).
+async fn handler_board_pins(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [board:pins] board {}", id);
+    let board = state
+        .database
+        .read()
+        .get::<Board>(&id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let pins = PinMap::get(&board).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(pins))
+}
+
+/// GET /:version/boards/profiles.
+/// Retrieves the known board models profiles.
+async fn handler_board_profiles() -> impl IntoResponse {
+    debug!("REST API: [board:profiles]");
+    Json(BoardProfile::list())
 }
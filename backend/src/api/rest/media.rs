@@ -0,0 +1,122 @@
+//! This file provides general routes and handlers for the media library (audio files shared by all devices).
+
+use axum::{Json, Router};
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get};
+use log::{debug, warn};
+
+use crate::api::AppState;
+use crate::extra::media::Media;
+use crate::utils::entity::Id;
+
+/// Consolidates all available REST API routes for `Media`.
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/",
+            get(handler_media_list)
+                .post(handler_media_upload)
+                // The size limit is checked against the library configuration instead.
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route("/:id", delete(handler_media_delete))
+}
+
+/// GET /:version/media.
+/// Retrieves all media files information.
+pub(super) async fn handler_media_list(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [media:list]");
+    let medias = state
+        .database
+        .read()
+        .list::<Media>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_values()
+        .collect::<Vec<Media>>();
+    Ok(Json(medias))
+}
+
+/// POST /:version/media.
+/// Uploads files (multipart) into the media library.
+pub(super) async fn handler_media_upload(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [media:upload]");
+    let mut medias = vec![];
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        let file_name = match field.file_name() {
+            None => continue, // Not a file.
+            Some(file_name) => file_name.to_string(),
+        };
+
+        let mut data = vec![];
+        while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+            data.extend_from_slice(&chunk);
+            if data.len() > state.media.max_size {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+        }
+
+        // Probing the file decodes it: do not block the server meanwhile.
+        let library = state.media.clone();
+        let media = tokio::task::spawn_blocking(move || library.store(&file_name, &data))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|err| {
+                warn!("Media upload rejected: {}", err);
+                StatusCode::UNPROCESSABLE_ENTITY
+            })?;
+        let media = state
+            .database
+            .write()
+            .insert(media)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        if let Some(socket) = state.socket.of("/ws") {
+            socket.emit("media:updated", &media).ok();
+        }
+        medias.push(media);
+    }
+    Ok(Json(medias))
+}
+
+/// DELETE /:version/media/:id.
+/// Deletes a media file from the library.
+async fn handler_media_delete(
+    State(state): State<AppState>,
+    Path(id): Path<Id>,
+) -> Result<impl IntoResponse, StatusCode> {
+    debug!("REST API: [media:delete] media {}", id);
+    let media = delete_media(&state, id)?;
+    Ok(Json(media))
+}
+
+/// Deletes a media: both its file and its database entry.
+pub(super) fn delete_media(state: &AppState, id: Id) -> Result<Media, StatusCode> {
+    let mut database = state.database.write();
+    let media = database
+        .get::<Media>(&id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state
+        .media
+        .remove(&media)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    database
+        .delete::<Media>(id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if let Some(socket) = state.socket.of("/ws") {
+        socket.emit("media:deleted", &media).ok();
+    }
+    Ok(media)
+}
@@ -5,9 +5,11 @@ use serde::Deserialize;
 
 use crate::api::AppState;
 
+mod animations;
 mod boards;
 mod config;
 mod devices;
+mod media;
 mod root;
 
 /// Generic pagination query parameters to be reused when needed across endpoints.
@@ -28,5 +30,6 @@ pub(crate) fn build_rest_routes() -> Router<AppState> {
         .nest("/config", config::routes())
         .nest("/boards", boards::routes())
         .nest("/devices", devices::routes())
-    // .nest("/animations", animations::routes())
+        .nest("/animations", animations::routes())
+        .nest("/media", media::routes())
 }
@@ -3,12 +3,22 @@ use std::string::String;
 
 use anyhow::Result;
 use serde::Serialize;
+use serde_json::Value;
+
+use crate::utils::validation::ValidationError;
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub enum Ack<T> {
-    Success { success: T },
-    Error { error: String },
+    Success {
+        success: T,
+    },
+    Error {
+        error: String,
+        /// The structured details of the error (see [`ValidationError`]).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        details: Option<Value>,
+    },
 }
 
 impl<T> From<Result<T>> for Ack<T> {
@@ -16,6 +26,9 @@ impl<T> From<Result<T>> for Ack<T> {
         match result {
             Ok(data) => Ack::Success { success: data },
             Err(error) => Ack::Error {
+                details: error
+                    .downcast_ref::<ValidationError>()
+                    .map(|error| error.details.clone()),
                 error: error.to_string(),
             },
         }
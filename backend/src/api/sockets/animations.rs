@@ -1,16 +1,29 @@
 use std::collections::HashMap;
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Result};
+use hermes_five::animation::AnimationEvent;
+use hermes_five::pause;
 use log::debug;
 use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+use socketioxide::SocketIo;
 
-use crate::animation::animation::Animation;
-use crate::api::payloads::animation::AnimationPayload;
+use crate::animation::animation::{Animation, PlayOptions};
+use crate::animation::edit::AnimationEditRequest;
+use crate::animation::executor::ArcExecutor;
+use crate::animation::lipsync::LipSyncRequest;
+use crate::animation::playback::{ArcPlayback, LayerOptions, Playback};
+use crate::api::payloads::animation::{AnimationPayload, PlayAnimation, StopAnimation};
 use crate::api::sockets::ack::Ack;
-use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
-use crate::utils::database::ArcDb;
+use crate::api::sockets::collaboration::{room, ArcCollaboration, LockTarget};
+use crate::api::sockets::{broadcast_and_ack, broadcast_to_all, broadcast_to_room_and_ack};
+use crate::extra::media::MediaLibrary;
+use crate::hardware::device::Device;
+use crate::utils::database::{ArcDb, Database};
 use crate::utils::entity::Id;
 
+/// The interval (in ms) between two progress events of a playing animation.
+const PROGRESS_INTERVAL: u64 = 100;
+
 pub fn register_animation_events(socket: &SocketRef) {
     socket.on(
         "animation:list",
@@ -53,6 +66,7 @@ pub fn register_animation_events(socket: &SocketRef) {
         "animation:update",
         |socket: SocketRef,
          State(database): State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
          TryData(animation): TryData<Animation>,
          ack: AckSender| {
             debug!(
@@ -61,16 +75,104 @@ pub fn register_animation_events(socket: &SocketRef) {
             );
 
             let animation = match animation {
-                Ok(animation) => database
-                    .write()
-                    .update(animation)
-                    .and_then(|animation| Ok(AnimationPayload::from(animation))),
+                Ok(mut animation) => {
+                    let collaboration = collaboration.read();
+                    let mut database = database.write();
+                    database
+                        .get::<Animation>(&animation.id)
+                        .and_then(|existing| match existing {
+                            None => bail!("Animation not found"),
+                            Some(existing) => {
+                                // Whole-animation saves are rejected when based on an outdated revision.
+                                existing.check_revision(animation.revision)?;
+                                // They replace all tracks: none may be locked by other editors.
+                                collaboration.check_tracks(
+                                    &socket.id.to_string(),
+                                    animation.id,
+                                    existing.tracks.keys().chain(animation.tracks.keys()),
+                                )?;
+                                animation.revision = existing.revision + 1;
+                                database.update(animation)
+                            }
+                        })
+                        .and_then(|animation| Ok(AnimationPayload::from(animation)))
+                }
                 Err(error) => Err(anyhow!("Invalid animation: {}", error)),
             };
             broadcast_and_ack("animation:updated", animation, &socket, ack);
         },
     );
 
+    socket.on(
+        "animation:edit",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
+         TryData(request): TryData<AnimationEditRequest>,
+         ack: AckSender| {
+            debug!("Event received: [animation:edit]: {:?}", request);
+
+            let request = match request {
+                Ok(request) => request,
+                Err(error) => {
+                    ack.send(&Ack::<()>::from(Err(anyhow!("Invalid edit: {}", error))))
+                        .ok();
+                    return;
+                }
+            };
+
+            // Reject edits of tracks currently locked by other editors.
+            let delta = collaboration
+                .read()
+                .check(
+                    &socket.id.to_string(),
+                    Some(request.animation),
+                    &LockTarget::Track(request.edit.get_track()),
+                )
+                .and_then(|_| {
+                    let mut database = database.write();
+                    database
+                        .get::<Animation>(&request.animation)
+                        .and_then(|animation| match animation {
+                            None => bail!("Animation not found"),
+                            Some(mut animation) => {
+                                let delta = animation.edit(request.revision, request.edit)?;
+                                database.update(animation)?;
+                                Ok(delta)
+                            }
+                        })
+                });
+            broadcast_to_room_and_ack(
+                "animation:edited",
+                room(&request.animation),
+                delta,
+                &socket,
+                ack,
+            );
+        },
+    );
+
+    socket.on(
+        "animation:lipsync",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
+         State(library): State<MediaLibrary>,
+         TryData(request): TryData<LipSyncRequest>,
+         ack: AckSender| async move {
+            debug!("Event received: [animation:lipsync]: {:?}", request);
+
+            let animation = match request {
+                Err(error) => Err(anyhow!("Invalid lip-sync request: {}", error)),
+                Ok(request) => {
+                    let editor = socket.id.to_string();
+                    generate_lipsync(database, collaboration, library, &editor, request).await
+                }
+            };
+            broadcast_and_ack("animation:updated", animation, &socket, ack);
+        },
+    );
+
     socket.on(
         "animation:delete",
         |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
@@ -90,53 +192,65 @@ pub fn register_animation_events(socket: &SocketRef) {
 
     socket.on(
         "animation:play",
+        |socket: SocketRef,
+         io: SocketIo,
+         State(database): State<ArcDb>,
+         State(playback): State<ArcPlayback>,
+         State(executor): State<ArcExecutor>,
+         Data(request): Data<PlayAnimation>,
+         ack: AckSender| {
+            debug!("Event received: [animation:play]: {:?}", request);
+
+            let (id, options) = request.into_parts();
+            let animation = play_animation(&database, &playback, &executor, &id, options, &io);
+            broadcast_and_ack("animation:played", animation, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "animation:pause",
         |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
-            debug!("Event received: [animation:play]: id:{:?}", id);
+            debug!("Event received: [animation:pause]: id:{:?}", id);
 
-            let mut database = database.write();
+            let database = database.write();
             let animation = database
                 .get::<Animation>(&id)
                 .and_then(|animation| match animation {
                     None => bail!("Animation not found"),
                     Some(mut animation) => {
-                        animation.play(&mut database)?;
-                        let animation = database.update(animation)?;
-                        match animation.inner.get_duration() {
-                            0 => bail!("Animation empty: check if it has keyframes or board(s) are connected."),
-                            _ => {
-                                let cloned_socket = socket.clone();
-                                let clone_animation = animation.clone();
-                                animation.inner.on(hermes_five::animation::AnimationEvent::OnComplete, move|animation: hermes_five::animation::Animation| {
-                                    // How to avoid theses double clones ?
-                                    let cloned_socket = cloned_socket.clone();
-                                    let mut clone_animation = clone_animation.clone();
-                                    async move {
-                                        clone_animation.inner = animation;
-                                        broadcast_to_all("animation:stopped", Ok(AnimationPayload::from(clone_animation)), &cloned_socket);
-                                        Ok(())
-                                    }
-                                });
-                                Ok(AnimationPayload::from(animation))
-                            },
-                        }
+                        animation.pause();
+                        Ok(AnimationPayload::from(animation))
                     }
                 });
-            broadcast_and_ack("animation:played", animation, &socket, ack);
+
+            broadcast_and_ack("animation:stopped", animation, &socket, ack);
         },
     );
 
     socket.on(
-        "animation:pause",
-        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
-            debug!("Event received: [animation:pause]: id:{:?}", id);
+        "animation:stop",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         Data(request): Data<StopAnimation>,
+         ack: AckSender| {
+            debug!("Event received: [animation:stop]: {:?}", request);
 
+            let (id, transition) = request.into_parts();
             let database = database.write();
             let animation = database
                 .get::<Animation>(&id)
                 .and_then(|animation| match animation {
                     None => bail!("Animation not found"),
                     Some(mut animation) => {
-                        animation.inner.pause();
+                        animation.stop();
+                        // Bring the devices back to their default state.
+                        if let Some(transition) = transition {
+                            let mutations = transition
+                                .apply(&database, get_defaults(&database, &animation)?)?;
+                            for mutation in mutations {
+                                broadcast_to_all("device:mutated", Ok(mutation), &socket);
+                            }
+                        }
                         Ok(AnimationPayload::from(animation))
                     }
                 });
@@ -146,22 +260,236 @@ pub fn register_animation_events(socket: &SocketRef) {
     );
 
     socket.on(
-        "animation:stop",
-        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
-            debug!("Event received: [animation:stop]: id:{:?}", id);
+        "animation:seek",
+        |socket: SocketRef,
+         io: SocketIo,
+         State(database): State<ArcDb>,
+         Data((id, time)): Data<(Id, u64)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [animation:seek]: id:{:?}, time:{}",
+                id, time
+            );
 
-            let database = database.write();
+            let mut database = database.write();
+            database.set_autosave(false);
             let animation = database
                 .get::<Animation>(&id)
                 .and_then(|animation| match animation {
                     None => bail!("Animation not found"),
                     Some(mut animation) => {
-                        animation.inner.stop();
+                        // Move all devices to their state at the given time.
+                        let playing = animation.inner.is_playing();
+                        animation.stop();
+                        let states = animation.sample(&database, time)?;
+                        let mutations = Animation::apply_states(&mut database, states, true)?;
+                        for mutation in mutations {
+                            broadcast_to_all("device:mutated", Ok(mutation), &socket);
+                        }
+
+                        // Resume playing from the given time if needed.
+                        if playing {
+                            animation.play_from(&database, time)?;
+                            watch_playback(&animation, &io);
+                        }
+                        let animation = database.update(animation)?;
                         Ok(AnimationPayload::from(animation))
                     }
                 });
+            database.set_autosave(true);
 
-            broadcast_and_ack("animation:stopped", animation, &socket, ack);
+            if let Ok(animation) = &animation {
+                broadcast_to_all("animation:progress", Ok((id, time)), &socket);
+                socket.broadcast().emit("animation:updated", animation).ok();
+            }
+            ack.send(&Ack::from(animation)).ok();
+        },
+    );
+
+    socket.on(
+        "animation:scrub",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         Data((id, time)): Data<(Id, u64)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [animation:scrub]: id:{:?}, time:{}",
+                id, time
+            );
+
+            // Scrubbing is a preview only: devices are moved but their state is not persisted.
+            let mut database = database.write();
+            let mutations = database
+                .get::<Animation>(&id)
+                .and_then(|animation| match animation {
+                    None => bail!("Animation not found"),
+                    Some(animation) if animation.inner.is_playing() => {
+                        bail!("Animation is playing: use seek instead")
+                    }
+                    Some(animation) => {
+                        let states = animation.sample(&database, time)?;
+                        Animation::apply_states(&mut database, states, false)
+                    }
+                });
+
+            if let Ok(mutations) = &mutations {
+                for mutation in mutations {
+                    socket.broadcast().emit("device:mutated", mutation).ok();
+                }
+                socket
+                    .broadcast()
+                    .emit("animation:progress", &(id, time))
+                    .ok();
+            }
+            ack.send(&Ack::from(mutations)).ok();
         },
     );
 }
+
+/// Plays (a selection of) an animation and streams its progress to all clients.
+/// An animation with events plays as a layer of the playback engine, which executes them.
+pub(crate) fn play_animation(
+    database: &ArcDb,
+    playback: &ArcPlayback,
+    executor: &ArcExecutor,
+    id: &Id,
+    options: PlayOptions,
+    io: &SocketIo,
+) -> Result<AnimationPayload> {
+    let mut animation = match database.read().get::<Animation>(id)? {
+        None => bail!("Animation not found"),
+        Some(animation) => animation,
+    };
+
+    // The animation is validated (and built) before stopping the animations it replaces.
+    if !animation.events.is_empty() {
+        let options = LayerOptions {
+            play: options,
+            ..Default::default()
+        };
+        {
+            // The new layer only drives the devices from the next tick of the engine.
+            let mut playback = playback.write();
+            playback.start(animation.clone(), options)?;
+            stop_conflicting(&database.read(), &animation, io)?;
+        }
+        Playback::run(playback, database, executor, io);
+        if let Some(socket) = io.of("/ws") {
+            socket
+                .emit("playback:status", &playback.read().get_layers())
+                .ok();
+        }
+        return Ok(AnimationPayload::from(animation));
+    }
+
+    let mut database = database.write();
+    animation.prepare(&database, options)?;
+    if animation.inner.get_duration() == 0 {
+        bail!("Animation empty: check if it has keyframes or board(s) are connected.");
+    }
+    stop_conflicting(&database, &animation, io)?;
+    animation.start();
+    let animation = database.update(animation)?;
+    watch_playback(&animation, io);
+    Ok(AnimationPayload::from(animation))
+}
+
+/// (private)
+/// Stops the animations playing on the devices of the given one: it replaces them.
+fn stop_conflicting(database: &Database, animation: &Animation, io: &SocketIo) -> Result<()> {
+    let devices = animation.get_devices();
+    for (_, mut other) in database.list::<Animation>()? {
+        if other.id != animation.id
+            && other.inner.is_playing()
+            && !other.get_devices().is_disjoint(&devices)
+        {
+            other.stop();
+            if let Some(socket) = io.of("/ws") {
+                socket
+                    .emit("animation:stopped", &AnimationPayload::from(other))
+                    .ok();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// (private)
+/// Retrieves the default state of every device animated by the animation.
+fn get_defaults(
+    database: &Database,
+    animation: &Animation,
+) -> Result<Vec<(Id, hermes_five::utils::State)>> {
+    let mut defaults = vec![];
+    for device_id in animation.get_devices() {
+        if let Some(device) = database.get::<Device>(&device_id)? {
+            defaults.push((device_id, device.inner.get_default()));
+        }
+    }
+    Ok(defaults)
+}
+
+/// (private)
+/// Generates a lip-sync track: the audio file is decoded (which takes a while) without locking the database.
+/// The track must not be locked by other editors, neither before decoding nor when inserting it.
+async fn generate_lipsync(
+    database: ArcDb,
+    collaboration: ArcCollaboration,
+    library: MediaLibrary,
+    editor: &str,
+    request: LipSyncRequest,
+) -> Result<AnimationPayload> {
+    let track = request.check(&database.read())?;
+    collaboration
+        .read()
+        .check_tracks(editor, request.animation, [&track])?;
+    let (analyzed, analyzed_library) = (request.clone(), library.clone());
+    let envelope =
+        tokio::task::spawn_blocking(move || analyzed.analyze(&analyzed_library)).await??;
+
+    let collaboration = collaboration.read();
+    let mut database = database.write();
+    collaboration.check_tracks(editor, request.animation, [&track])?;
+    let animation = request.apply(&database, &library, &envelope)?;
+    database.update(animation).map(AnimationPayload::from)
+}
+
+/// (private)
+/// Streams the progress of a playing animation to all clients and notifies them when it completes.
+fn watch_playback(animation: &Animation, io: &SocketIo) {
+    let cloned_io = io.clone();
+    let clone_animation = animation.clone();
+    animation.inner.on(
+        AnimationEvent::OnComplete,
+        move |animation: hermes_five::animation::Animation| {
+            // How to avoid theses double clones ?
+            let cloned_io = cloned_io.clone();
+            let mut clone_animation = clone_animation.clone();
+            async move {
+                clone_animation.inner = animation;
+                clone_animation.audio_player.stop();
+                if let Some(socket) = cloned_io.of("/ws") {
+                    let animation = AnimationPayload::from(clone_animation);
+                    socket.emit("animation:stopped", &animation).ok();
+                }
+                Ok(())
+            }
+        },
+    );
+
+    let io = io.clone();
+    let animation = animation.clone();
+    tokio::spawn(async move {
+        loop {
+            pause!(PROGRESS_INTERVAL);
+            if !animation.inner.is_playing() {
+                break;
+            }
+            animation.sync_audio();
+            if let Some(socket) = io.of("/ws") {
+                let progress = (animation.id, animation.get_progress());
+                socket.emit("animation:progress", &progress).ok();
+            }
+        }
+    });
+}
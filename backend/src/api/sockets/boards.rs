@@ -1,13 +1,18 @@
 use anyhow::{anyhow, bail};
-use hermes_five::pause_sync;
 use log::debug;
 use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+use socketioxide::SocketIo;
 
 use crate::animation::group::Group;
+use crate::api::payloads::board::CreateBoard;
 use crate::api::sockets::ack::Ack;
 use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
 use crate::hardware::board::Board;
 use crate::hardware::device::Device;
+use crate::hardware::flasher::Flasher;
+use crate::hardware::pins::PinMap;
+use crate::hardware::power::PowerModel;
+use crate::hardware::profile::BoardProfile;
 use crate::utils::database::ArcDb;
 use crate::utils::entity::{Entity, Id};
 
@@ -21,14 +26,51 @@ pub fn register_board_events(socket: &SocketRef) {
         },
     );
 
+    socket.on("board:profiles", |ack: AckSender| {
+        debug!("Event received: [board:profiles]");
+        ack.send(&Ack::Success {
+            success: BoardProfile::list(),
+        })
+        .ok();
+    });
+
+    socket.on(
+        "board:pins",
+        |State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+            debug!("Event received: [board:pins]: board:{}", id);
+            let pins = Board::get(&database, &id).and_then(|board| match board {
+                None => bail!("Board not found"),
+                Some(board) => match PinMap::get(&board) {
+                    None => bail!("Pin capabilities of board [{}] are unknown", id),
+                    Some(pins) => Ok(pins),
+                },
+            });
+            ack.send(&Ack::from(pins)).ok();
+        },
+    );
+
     socket.on(
         "board:open",
-        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(flasher): State<Flasher>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
             debug!("Event received: [board:open]: board:{}", id);
             let board = Board::get(&database, &id).and_then(|board| match board {
                 None => bail!("Board not found"),
+                Some(_) if flasher.is_flashing(&id) => bail!("Board [{}] is being flashed", id),
                 Some(board) => board.open(&database)?.save(&database),
             });
+            if let Ok(Board {
+                firmware: Some(check),
+                ..
+            }) = &board
+            {
+                if check.outdated {
+                    broadcast_to_all("board:firmware:warning", Ok((id, check)), &socket);
+                }
+            }
             broadcast_and_ack("board:updated", board, &socket, ack);
         },
     );
@@ -45,25 +87,28 @@ pub fn register_board_events(socket: &SocketRef) {
         },
     );
 
+    socket.on(
+        "board:flash",
+        |socket: SocketRef,
+         io: SocketIo,
+         State(database): State<ArcDb>,
+         State(flasher): State<Flasher>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [board:flash]: board:{}", id);
+            let board = Board::get(&database, &id).and_then(|board| match board {
+                None => bail!("Board not found"),
+                Some(board) => flasher.start(board, &database, &io),
+            });
+            broadcast_and_ack("board:updated", board, &socket, ack);
+        },
+    );
+
     socket.on(
         "board:reset",
         |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>| {
             debug!("Event received: [board:reset]: board:{}", id);
-
-            database.write().set_autosave(false);
-            let _ = database.read().list::<Device>().and_then(|mut devices| {
-                for (_, device) in &mut devices {
-                    if device.bid == id {
-                        device.inner.reset().and_then(|mutation| {
-                            broadcast_to_all("device:mutated", Ok((device.id, mutation)), &socket);
-                            Ok(())
-                        })?;
-                    }
-                }
-
-                Ok(devices)
-            });
-            database.write().set_autosave(true);
+            reset_devices(&socket, &database, Some(id), 0);
         },
     );
 
@@ -71,34 +116,23 @@ pub fn register_board_events(socket: &SocketRef) {
         "board:reset_all",
         |socket: SocketRef, State(database): State<ArcDb>| {
             debug!("Event received: [board:reset_all]");
-
-            database.write().set_autosave(false);
-            let _ = database.read().list::<Device>().and_then(|mut devices| {
-                for (_, device) in &mut devices {
-                    device.inner.reset().and_then(|mutation| {
-                        broadcast_to_all("device:mutated", Ok((device.id, mutation)), &socket);
-                        Ok(())
-                    })?;
-                    pause_sync!(100);
-                }
-
-                Ok(devices)
-            });
-            database.write().set_autosave(true);
+            reset_devices(&socket, &database, None, 100);
         },
     );
 
     socket.on(
         "board:create",
         |socket: SocketRef,
-         TryData(new_board): TryData<Board>,
+         TryData(new_board): TryData<CreateBoard>,
          database: State<ArcDb>,
          ack: AckSender| {
             debug!("Event received: [board:create]: board:{:#?}", new_board);
 
             let board = match new_board {
                 Err(error) => Err(anyhow!("Invalid board: {}", error)),
-                Ok(new_board) => database.write().insert(new_board),
+                Ok(new_board) => {
+                    Board::try_from(new_board).and_then(|board| database.write().insert(board))
+                }
             };
             broadcast_and_ack("board:updated", board, &socket, ack);
         },
@@ -109,6 +143,7 @@ pub fn register_board_events(socket: &SocketRef) {
         |socket: SocketRef,
          TryData(board): TryData<Board>,
          database: State<ArcDb>,
+         State(flasher): State<Flasher>,
          ack: AckSender| {
             debug!("Event received: [board:update]: board:{:#?}", board);
 
@@ -118,6 +153,9 @@ pub fn register_board_events(socket: &SocketRef) {
                     Board::get(&database, &board.id).and_then(|existing_board| match existing_board
                     {
                         None => bail!("Board [{}] not found", board.id),
+                        Some(_) if flasher.is_flashing(&board.id) => {
+                            bail!("Board [{}] is being flashed", board.id)
+                        }
                         Some(_) => database.write().update(board),
                     })
                 }
@@ -146,3 +184,22 @@ pub fn register_board_events(socket: &SocketRef) {
         },
     );
 }
+
+/// Resets the devices of the given board (all devices if none), as scheduled by the power model.
+/// The `fallback` delay (in ms) between two resets applies when the power model is disabled.
+fn reset_devices(socket: &SocketRef, database: &ArcDb, board: Option<Id>, fallback: u64) {
+    database.write().set_autosave(false);
+    let devices: Vec<Device> = database.read().list::<Device>().map_or(vec![], |devices| {
+        devices
+            .into_values()
+            .filter(|device| board.is_none_or(|id| device.bid == id))
+            .collect()
+    });
+    let schedule = PowerModel::load(&database.read()).schedule_resets(&devices, fallback);
+    let _ = schedule.run(devices, |mut device, _| {
+        let mutation = device.inner.reset()?;
+        broadcast_to_all("device:mutated", Ok((device.id, mutation)), socket);
+        Ok(())
+    });
+    database.write().set_autosave(true);
+}
@@ -0,0 +1,181 @@
+//! This file contains the servo calibration sessions.
+//!
+//! A session holds a draft [`Calibration`] for a device while the user measures it: the servo is moved to raw
+//! (commanded) angles until it physically reaches a logical angle, which is then recorded as a point. The
+//! draft can be previewed, and is only applied to the device (and persisted) on save.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use log::debug;
+use parking_lot::RwLock;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::broadcast_and_ack;
+use crate::extra::servo::Calibration;
+use crate::hardware::device::Device;
+use crate::hardware::servo::Servo;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::{Entity, Id};
+
+/// The draft calibrations being measured, keyed by device.
+pub type ArcCalibration = Arc<RwLock<HashMap<Id, Calibration>>>;
+
+pub fn register_calibration_events(socket: &SocketRef) {
+    socket.on(
+        "servo:calibration:start",
+        |State(database): State<ArcDb>,
+         State(sessions): State<ArcCalibration>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [servo:calibration:start]: device:{}", id);
+            let calibration = get_device(&database, &id).and_then(|mut device| {
+                let calibration = get_servo(&mut device)?.get_calibration().clone();
+                sessions.write().insert(id, calibration.clone());
+                Ok(calibration)
+            });
+            ack.send(&Ack::from(calibration)).ok();
+        },
+    );
+
+    socket.on(
+        "servo:calibration:move",
+        |State(database): State<ArcDb>,
+         State(sessions): State<ArcCalibration>,
+         Data((id, angle)): Data<(Id, u16)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [servo:calibration:move]: device:{}, angle:{}",
+                id, angle
+            );
+            let state = get_draft(&sessions, &id)
+                .and_then(|_| get_device(&database, &id))
+                .and_then(|mut device| get_servo(&mut device)?.move_commanded(angle));
+            ack.send(&Ack::from(state)).ok();
+        },
+    );
+
+    socket.on(
+        "servo:calibration:preview",
+        |State(database): State<ArcDb>,
+         State(sessions): State<ArcCalibration>,
+         Data((id, angle)): Data<(Id, f32)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [servo:calibration:preview]: device:{}, angle:{}",
+                id, angle
+            );
+            let state = get_draft(&sessions, &id).and_then(|draft| {
+                let commanded = draft.to_commanded(angle).round().max(0.0) as u16;
+                get_servo(&mut get_device(&database, &id)?)?.move_commanded(commanded)
+            });
+            ack.send(&Ack::from(state)).ok();
+        },
+    );
+
+    socket.on(
+        "servo:calibration:point",
+        |State(sessions): State<ArcCalibration>,
+         Data((id, logical, commanded)): Data<(Id, f32, f32)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [servo:calibration:point]: device:{}, logical:{}, commanded:{}",
+                id, logical, commanded
+            );
+            let draft = update_draft(&sessions, &id, |draft| draft.set_point(logical, commanded));
+            ack.send(&Ack::from(draft)).ok();
+        },
+    );
+
+    socket.on(
+        "servo:calibration:trim",
+        |State(sessions): State<ArcCalibration>,
+         Data((id, trim)): Data<(Id, f32)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [servo:calibration:trim]: device:{}, trim:{}",
+                id, trim
+            );
+            let draft = update_draft(&sessions, &id, |draft| draft.trim = trim);
+            ack.send(&Ack::from(draft)).ok();
+        },
+    );
+
+    socket.on(
+        "servo:calibration:save",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(sessions): State<ArcCalibration>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [servo:calibration:save]: device:{}", id);
+            let device = get_draft(&sessions, &id).and_then(|draft| {
+                let mut device = get_device(&database, &id)?;
+                get_servo(&mut device)?.calibrate(draft)?;
+                let device = device.save(&database)?;
+                sessions.write().remove(&id);
+                Ok(device)
+            });
+            broadcast_and_ack("device:updated", device, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "servo:calibration:cancel",
+        |State(database): State<ArcDb>,
+         State(sessions): State<ArcCalibration>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [servo:calibration:cancel]: device:{}", id);
+            sessions.write().remove(&id);
+            // Moves the servo back to its (unchanged) position.
+            let state = get_device(&database, &id).and_then(|mut device| {
+                let state = device.inner.get_state();
+                device.inner.set_state(state)
+            });
+            ack.send(&Ack::from(state)).ok();
+        },
+    );
+}
+
+/// (private)
+fn get_device(database: &ArcDb, id: &Id) -> Result<Device> {
+    match Device::get(database, id)? {
+        None => bail!("Device not found"),
+        Some(device) => Ok(device),
+    }
+}
+
+/// (private)
+/// Retrieves the servo of a device: only servos support calibration.
+fn get_servo(device: &mut Device) -> Result<&mut Servo> {
+    let name = device.name.clone();
+    match device.downcast_mut::<Servo>() {
+        None => bail!("Device [{}] does not support calibration", name),
+        Some(servo) => Ok(servo),
+    }
+}
+
+/// (private)
+fn get_draft(sessions: &ArcCalibration, id: &Id) -> Result<Calibration> {
+    match sessions.read().get(id) {
+        None => bail!("No calibration in progress for device [{}]", id),
+        Some(draft) => Ok(draft.clone()),
+    }
+}
+
+/// (private)
+fn update_draft(
+    sessions: &ArcCalibration,
+    id: &Id,
+    update: impl FnOnce(&mut Calibration),
+) -> Result<Calibration> {
+    match sessions.write().get_mut(id) {
+        None => bail!("No calibration in progress for device [{}]", id),
+        Some(draft) => {
+            update(draft);
+            Ok(draft.clone())
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! This file contains the real-time collaboration features: presence and soft locks.
+//!
+//! - Each animation has its own room (see [`room`]): editors join it to receive the small edit deltas.
+//! - Presence lets every client know who is connected and which animation they are editing.
+//! - Soft locks are taken on tracks or groups while they are being dragged: edits of a locked
+//!   target by other editors are rejected. Locks expire by themselves if not renewed.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use log::debug;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
+use crate::utils::entity::Id;
+
+pub type ArcCollaboration = Arc<RwLock<Collaboration>>;
+
+/// The duration (in ms) after which a non-renewed lock is released.
+const LOCK_TIMEOUT: i64 = 30_000;
+
+/// Returns the room name associated with an animation.
+pub fn room(animation: &Id) -> String {
+    format!("animation:{}", animation)
+}
+
+/// An editor currently connected to the studio.
+#[derive(Clone, Debug, Serialize)]
+pub struct Editor {
+    /// The editor socket id.
+    pub id: String,
+    /// The editor display name.
+    pub name: String,
+    /// The animation currently edited (if any).
+    pub animation: Option<Id>,
+}
+
+/// The target of a soft lock.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "lowercase")]
+pub enum LockTarget {
+    /// A track (keyed by `Group` id) of an animation.
+    Track(Id),
+    /// A group of the configuration panel.
+    Group(Id),
+}
+
+/// A soft lock held by an editor.
+#[derive(Clone, Debug, Serialize)]
+pub struct Lock {
+    /// The locked target.
+    pub target: LockTarget,
+    /// The animation the lock applies to (`None` for groups).
+    pub animation: Option<Id>,
+    /// The socket id of the editor holding the lock.
+    pub owner: String,
+    /// The timestamp (in ms since epoch) of the last renewal.
+    pub renewed_at: i64,
+}
+
+/// The collaboration state shared by all sockets.
+#[derive(Clone, Debug, Default)]
+pub struct Collaboration {
+    editors: HashMap<String, Editor>,
+    locks: Vec<Lock>,
+}
+
+/// The presence information sent to the clients.
+#[derive(Clone, Debug, Serialize)]
+pub struct Presence {
+    pub editors: Vec<Editor>,
+    pub locks: Vec<Lock>,
+}
+
+impl Collaboration {
+    /// Retrieves the current presence information.
+    pub fn get_presence(&mut self) -> Presence {
+        self.purge_expired_locks();
+        Presence {
+            editors: self.editors.values().cloned().collect(),
+            locks: self.locks.clone(),
+        }
+    }
+
+    /// Registers an editor as editing the given animation (or nothing).
+    pub fn join(&mut self, editor: &str, name: String, animation: Option<Id>) {
+        self.release_all(editor);
+        self.editors.insert(
+            editor.to_string(),
+            Editor {
+                id: editor.to_string(),
+                name,
+                animation,
+            },
+        );
+    }
+
+    /// Unregisters an editor and releases all its locks.
+    pub fn leave(&mut self, editor: &str) -> Option<Editor> {
+        self.release_all(editor);
+        self.editors.remove(editor)
+    }
+
+    /// Acquires (or renews) a lock for the given editor.
+    /// A track lock applies to the animation edited by the editor: it must have joined its room beforehand.
+    pub fn acquire(&mut self, editor: &str, target: LockTarget) -> Result<Lock> {
+        self.purge_expired_locks();
+        let animation = match target {
+            LockTarget::Track(id) => {
+                match self.editors.get(editor).and_then(|editor| editor.animation) {
+                    None => bail!("Track [{}] cannot be locked outside of an animation", id),
+                    Some(animation) => Some(animation),
+                }
+            }
+            LockTarget::Group(_) => None,
+        };
+        self.check(editor, animation, &target)?;
+
+        let lock = Lock {
+            target,
+            animation,
+            owner: editor.to_string(),
+            renewed_at: Utc::now().timestamp_millis(),
+        };
+        self.locks
+            .retain(|existing| existing.target != lock.target || existing.animation != animation);
+        self.locks.push(lock.clone());
+        Ok(lock)
+    }
+
+    /// Releases a lock held by the given editor.
+    pub fn release(&mut self, editor: &str, target: &LockTarget) {
+        self.locks
+            .retain(|lock| lock.owner != editor || &lock.target != target);
+    }
+
+    /// Releases all locks held by the given editor.
+    pub fn release_all(&mut self, editor: &str) {
+        self.locks.retain(|lock| lock.owner != editor);
+    }
+
+    /// Ensures the given editor is allowed to modify the target (ie: it is not locked by someone else).
+    pub fn check(&self, editor: &str, animation: Option<Id>, target: &LockTarget) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let lock = self.locks.iter().find(|lock| {
+            &lock.target == target
+                && lock.animation == animation
+                && lock.owner != editor
+                && now - lock.renewed_at < LOCK_TIMEOUT
+        });
+        match lock {
+            None => Ok(()),
+            Some(lock) => {
+                let owner = self
+                    .editors
+                    .get(&lock.owner)
+                    .map_or(lock.owner.clone(), |owner| owner.name.clone());
+                match target {
+                    LockTarget::Track(id) => bail!("Track [{}] is being edited by {}", id, owner),
+                    LockTarget::Group(id) => bail!("Group [{}] is being edited by {}", id, owner),
+                }
+            }
+        }
+    }
+
+    /// Ensures the given editor is allowed to replace the given tracks of an animation (see [`Collaboration::check`]).
+    pub fn check_tracks<'a, I: IntoIterator<Item = &'a Id>>(
+        &self,
+        editor: &str,
+        animation: Id,
+        tracks: I,
+    ) -> Result<()> {
+        tracks
+            .into_iter()
+            .try_for_each(|track| self.check(editor, Some(animation), &LockTarget::Track(*track)))
+    }
+
+    /// (private)
+    /// Removes the locks that have not been renewed in time.
+    fn purge_expired_locks(&mut self) {
+        let now = Utc::now().timestamp_millis();
+        self.locks
+            .retain(|lock| now - lock.renewed_at < LOCK_TIMEOUT);
+    }
+}
+
+pub fn register_collaboration_events(socket: &SocketRef) {
+    socket.on(
+        "presence:list",
+        |State(collaboration): State<ArcCollaboration>, ack: AckSender| {
+            debug!("Event received: [presence:list]");
+            let presence = collaboration.write().get_presence();
+            ack.send(&Ack::Success { success: presence }).ok();
+        },
+    );
+
+    socket.on(
+        "presence:join",
+        |socket: SocketRef,
+         State(collaboration): State<ArcCollaboration>,
+         TryData(data): TryData<(String, Option<Id>)>,
+         ack: AckSender| {
+            debug!("Event received: [presence:join]: {:?}", data);
+
+            let presence = match data {
+                Err(error) => Err(anyhow!("Invalid presence: {}", error)),
+                Ok((name, animation)) => {
+                    socket.leave_all().ok();
+                    if let Some(animation) = animation {
+                        socket.join(room(&animation)).ok();
+                    }
+                    let mut collaboration = collaboration.write();
+                    collaboration.join(&socket.id.to_string(), name, animation);
+                    Ok(collaboration.get_presence())
+                }
+            };
+
+            broadcast_and_ack("presence:updated", presence, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "presence:leave",
+        |socket: SocketRef, State(collaboration): State<ArcCollaboration>| {
+            debug!("Event received: [presence:leave]");
+            socket.leave_all().ok();
+            let presence = {
+                let mut collaboration = collaboration.write();
+                collaboration.leave(&socket.id.to_string());
+                collaboration.get_presence()
+            };
+            broadcast_to_all("presence:updated", Ok(presence), &socket);
+        },
+    );
+
+    socket.on(
+        "lock:acquire",
+        |socket: SocketRef,
+         State(collaboration): State<ArcCollaboration>,
+         Data(target): Data<LockTarget>,
+         ack: AckSender| {
+            debug!("Event received: [lock:acquire]: {:?}", target);
+            let (lock, presence) = {
+                let mut collaboration = collaboration.write();
+                let lock = collaboration.acquire(&socket.id.to_string(), target);
+                (lock, collaboration.get_presence())
+            };
+            if lock.is_ok() {
+                broadcast_to_all("presence:updated", Ok(presence), &socket);
+            }
+            ack.send(&Ack::from(lock)).ok();
+        },
+    );
+
+    socket.on(
+        "lock:release",
+        |socket: SocketRef,
+         State(collaboration): State<ArcCollaboration>,
+         Data(target): Data<LockTarget>| {
+            debug!("Event received: [lock:release]: {:?}", target);
+            let presence = {
+                let mut collaboration = collaboration.write();
+                collaboration.release(&socket.id.to_string(), &target);
+                collaboration.get_presence()
+            };
+            broadcast_to_all("presence:updated", Ok(presence), &socket);
+        },
+    );
+
+    socket.on_disconnect(
+        |socket: SocketRef, State(collaboration): State<ArcCollaboration>| {
+            debug!("Socket.IO disconnected: {:?}", socket.id);
+            let presence = {
+                let mut collaboration = collaboration.write();
+                collaboration.leave(&socket.id.to_string());
+                collaboration.get_presence()
+            };
+            socket.broadcast().emit("presence:updated", &presence).ok();
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locks() {
+        let mut collaboration = Collaboration::default();
+        collaboration.join("alice", String::from("Alice"), Some(1));
+        collaboration.join("bob", String::from("Bob"), Some(1));
+
+        // Alice locks the track: Bob cannot lock nor edit it.
+        assert!(collaboration.acquire("alice", LockTarget::Track(3)).is_ok());
+        assert!(collaboration.acquire("bob", LockTarget::Track(3)).is_err());
+        assert!(collaboration
+            .check("bob", Some(1), &LockTarget::Track(3))
+            .is_err());
+        assert!(collaboration
+            .check("alice", Some(1), &LockTarget::Track(3))
+            .is_ok());
+
+        assert!(collaboration.check_tracks("bob", 1, &[2, 3]).is_err());
+        assert!(collaboration.check_tracks("bob", 1, &[2, 4]).is_ok());
+
+        // The same track of another animation is not locked.
+        assert!(collaboration
+            .check("bob", Some(2), &LockTarget::Track(3))
+            .is_ok());
+
+        // A track cannot be locked before joining an animation.
+        collaboration.join("carol", String::from("Carol"), None);
+        assert!(collaboration
+            .acquire("carol", LockTarget::Track(4))
+            .is_err());
+        assert!(collaboration.acquire("dave", LockTarget::Track(4)).is_err());
+        assert!(collaboration.acquire("carol", LockTarget::Group(4)).is_ok());
+        collaboration.leave("carol");
+
+        // Locks are released when leaving.
+        collaboration.leave("alice");
+        assert!(collaboration.acquire("bob", LockTarget::Track(3)).is_ok());
+        assert_eq!(collaboration.get_presence().editors.len(), 1);
+    }
+}
@@ -1,9 +1,11 @@
+use anyhow::anyhow;
 use log::debug;
 use serde_json::Value;
-use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
 
 use crate::api::sockets::ack::Ack;
 use crate::api::sockets::broadcast_and_ack;
+use crate::hardware::power::PowerModel;
 use crate::utils::database::ArcDb;
 use crate::utils::interface::Interface;
 
@@ -28,4 +30,28 @@ pub fn register_config_events(socket: &SocketRef) {
             broadcast_and_ack("config:updated", config, &socket, ack);
         },
     );
+
+    socket.on(
+        "power:get",
+        |ack: AckSender, State(database): State<ArcDb>| {
+            debug!("Event received: [power:get]");
+            let model = PowerModel::load(&database.read());
+            ack.send(&Ack::Success { success: model }).ok();
+        },
+    );
+
+    socket.on(
+        "power:set",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         TryData(model): TryData<PowerModel>,
+         ack: AckSender| {
+            debug!("Event received: [power:set]: {:?}", model);
+            let model = match model {
+                Err(error) => Err(anyhow!("Invalid power model: {}", error)),
+                Ok(model) => model.save(&database.read()).map(|_| model),
+            };
+            broadcast_and_ack("power:updated", model, &socket, ack);
+        },
+    );
 }
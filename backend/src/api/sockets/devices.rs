@@ -1,6 +1,7 @@
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Result};
 use hermes_five::utils::Easing;
 use log::debug;
+use serde_json::{Map, Value};
 use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
 
 use crate::animation::group::Group;
@@ -8,6 +9,8 @@ use crate::api::sockets::ack::Ack;
 use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
 use crate::hardware::board::Board;
 use crate::hardware::device::Device;
+use crate::hardware::template::DeviceTemplate;
+use crate::hardware::{pca9685, pins};
 use crate::utils::database::ArcDb;
 use crate::utils::entity::{Entity, Id};
 
@@ -137,17 +140,7 @@ pub fn register_device_events(socket: &SocketRef) {
 
             let device = match new_device {
                 Err(error) => Err(anyhow!("Invalid device: {}", error)),
-                Ok(mut new_device) => {
-                    Board::get(&database, &new_device.bid).and_then(|board| match board {
-                        None => bail!("Board [{}] not found", new_device.bid),
-                        Some(board) => {
-                            if board.connected {
-                                new_device.inner.set_board(&board)?;
-                            }
-                            database.write().insert(new_device)
-                        }
-                    })
-                }
+                Ok(new_device) => insert_device(&database, new_device),
             };
 
             broadcast_to_all("group:list", database.read().list::<Group>(), &socket);
@@ -155,6 +148,32 @@ pub fn register_device_events(socket: &SocketRef) {
         },
     );
 
+    socket.on(
+        "device:from_template",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         Data((name, bid, device_name, overrides)): Data<(
+            String,
+            Id,
+            String,
+            Map<String, Value>,
+        )>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [device:from_template]: template={}, board={}, overrides={:?}",
+                name, bid, overrides
+            );
+
+            let template = DeviceTemplate::find(&database.read(), &name);
+            let device = template
+                .and_then(|template| template.instantiate(bid, &device_name, overrides))
+                .and_then(|new_device| insert_device(&database, new_device));
+
+            broadcast_to_all("group:list", database.read().list::<Group>(), &socket);
+            broadcast_and_ack("device:updated", device, &socket, ack);
+        },
+    );
+
     socket.on(
         "device:update",
         |socket: SocketRef,
@@ -169,6 +188,11 @@ pub fn register_device_events(socket: &SocketRef) {
                     Board::get(&database, &device.bid).and_then(|board| match board {
                         None => bail!("Board [{}] not found", device.bid),
                         Some(board) => {
+                            let devices = database.read().list::<Device>()?;
+                            let devices: Vec<Device> = devices.into_values().collect();
+                            device.inner.validate()?;
+                            pins::validate(&device, &board, &devices)?;
+                            pca9685::validate(&device, &devices)?;
                             if board.connected {
                                 device.inner.set_board(&board)?;
                             }
@@ -202,3 +226,22 @@ pub fn register_device_events(socket: &SocketRef) {
         },
     );
 }
+
+/// (private)
+/// Inserts a new device: its configuration and pins are validated and it is attached to its board (if open).
+fn insert_device(database: &ArcDb, mut new_device: Device) -> Result<Device> {
+    Board::get(database, &new_device.bid).and_then(|board| match board {
+        None => bail!("Board [{}] not found", new_device.bid),
+        Some(board) => {
+            let devices = database.read().list::<Device>()?;
+            let devices: Vec<Device> = devices.into_values().collect();
+            new_device.inner.validate()?;
+            pins::validate(&new_device, &board, &devices)?;
+            pca9685::validate(&new_device, &devices)?;
+            if board.connected {
+                new_device.inner.set_board(&board)?;
+            }
+            database.write().insert(new_device)
+        }
+    })
+}
@@ -6,6 +6,7 @@ use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
 
 use crate::animation::group::Group;
 use crate::api::sockets::ack::Ack;
+use crate::api::sockets::collaboration::{ArcCollaboration, LockTarget};
 use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
 use crate::utils::database::ArcDb;
 use crate::utils::entity::{Entity, Id};
@@ -41,19 +42,24 @@ pub fn register_group_events(socket: &SocketRef) {
         |socket: SocketRef,
          TryData(data): TryData<(Id, String)>,
          database: State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
          ack: AckSender| {
             debug!("Event received: [group:update]: group:{:#?}", data);
 
             let group = match data {
                 Ok(data) => {
                     let (id, name) = data;
-                    Group::get(&database, &id).and_then(|group| match group {
-                        None => bail!("Group not found"),
-                        Some(mut group) => {
-                            group.name = Some(name);
-                            database.write().update(group)
-                        }
-                    })
+                    collaboration
+                        .read()
+                        .check(&socket.id.to_string(), None, &LockTarget::Group(id))
+                        .and_then(|_| Group::get(&database, &id))
+                        .and_then(|group| match group {
+                            None => bail!("Group not found"),
+                            Some(mut group) => {
+                                group.name = Some(name);
+                                database.write().update(group)
+                            }
+                        })
                 }
                 Err(error) => Err(anyhow!("Invalid group: {}", error)),
             };
@@ -99,11 +105,16 @@ pub fn register_group_events(socket: &SocketRef) {
 
     socket.on(
         "group:delete",
-        |socket: SocketRef, database: State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+        |socket: SocketRef,
+         database: State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
             debug!("Event received: [group:delete]: id:{:?}", id);
-            let group = database
-                .write()
-                .delete::<Group>(id)
+            let group = collaboration
+                .read()
+                .check(&socket.id.to_string(), None, &LockTarget::Group(id))
+                .and_then(|_| database.write().delete::<Group>(id))
                 .and_then(|group| match group {
                     None => bail!("Group not found"),
                     Some(group) => Ok(group),
@@ -6,18 +6,30 @@ use socketioxide::extract::{AckSender, SocketRef};
 use crate::api::sockets::ack::Ack;
 use crate::api::sockets::animations::register_animation_events;
 use crate::api::sockets::boards::register_board_events;
+use crate::api::sockets::calibration::register_calibration_events;
+use crate::api::sockets::collaboration::register_collaboration_events;
 use crate::api::sockets::config::register_config_events;
 use crate::api::sockets::devices::register_device_events;
 use crate::api::sockets::groups::register_group_events;
+use crate::api::sockets::playback::register_playback_events;
 use crate::api::sockets::postures::register_posture_events;
+use crate::api::sockets::shows::register_show_events;
+use crate::api::sockets::templates::register_template_events;
+use crate::api::sockets::versions::register_version_events;
 
 pub mod ack;
-mod animations;
+pub mod animations;
 mod boards;
+pub mod calibration;
+pub mod collaboration;
 mod config;
 mod devices;
 mod groups;
+mod playback;
 mod postures;
+mod shows;
+mod templates;
+mod versions;
 
 /// Helper function: broadcast the value and send ack.
 pub fn broadcast_and_ack<T: Serialize>(
@@ -32,6 +44,20 @@ pub fn broadcast_and_ack<T: Serialize>(
     ack.send(&Ack::from(data)).ok();
 }
 
+/// Helper function: broadcast the value to the other members of a room and send ack.
+pub fn broadcast_to_room_and_ack<T: Serialize>(
+    event: &'static str,
+    room: String,
+    data: anyhow::Result<T>,
+    socket: &SocketRef,
+    ack: AckSender,
+) {
+    if data.is_ok() {
+        socket.to(room).emit(event, data.as_ref().unwrap()).ok();
+    }
+    ack.send(&Ack::from(data)).ok();
+}
+
 /// Helper function: broadcast some event/value to everyone.
 pub fn broadcast_to_all<T: Serialize>(
     event: &'static str,
@@ -49,11 +75,17 @@ pub fn register_socket_events(
     custom_register_callbacks: Vec<fn(socket: &SocketRef)>,
 ) {
     register_config_events(&socket);
+    register_collaboration_events(&socket);
     register_board_events(&socket);
     register_device_events(&socket);
+    register_calibration_events(&socket);
     register_group_events(&socket);
     register_posture_events(&socket);
     register_animation_events(&socket);
+    register_playback_events(&socket);
+    register_show_events(&socket);
+    register_template_events(&socket);
+    register_version_events(&socket);
 
     for custom_register in &custom_register_callbacks {
         custom_register(&socket);
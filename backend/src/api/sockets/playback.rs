@@ -0,0 +1,92 @@
+use anyhow::{anyhow, bail};
+use log::debug;
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+use socketioxide::SocketIo;
+
+use crate::animation::animation::Animation;
+use crate::animation::executor::ArcExecutor;
+use crate::animation::playback::{ArcPlayback, LayerOptions, Playback};
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::broadcast_to_all;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+pub fn register_playback_events(socket: &SocketRef) {
+    socket.on(
+        "playback:status",
+        |State(playback): State<ArcPlayback>, ack: AckSender| {
+            debug!("Event received: [playback:status]");
+            let layers = playback.read().get_layers();
+            ack.send(&Ack::Success { success: layers }).ok();
+        },
+    );
+
+    socket.on(
+        "playback:variables",
+        |State(playback): State<ArcPlayback>, ack: AckSender| {
+            debug!("Event received: [playback:variables]");
+            let variables = playback.read().get_variables();
+            ack.send(&Ack::Success { success: variables }).ok();
+        },
+    );
+
+    socket.on(
+        "playback:start",
+        |socket: SocketRef,
+         io: SocketIo,
+         State(database): State<ArcDb>,
+         State(playback): State<ArcPlayback>,
+         State(executor): State<ArcExecutor>,
+         TryData(data): TryData<(Id, LayerOptions)>,
+         ack: AckSender| {
+            debug!("Event received: [playback:start]: {:?}", data);
+
+            let layer = match data {
+                Err(error) => Err(anyhow!("Invalid layer: {}", error)),
+                Ok((id, options)) => {
+                    let animation = database.read().get::<Animation>(&id);
+                    animation.and_then(|animation| match animation {
+                        None => bail!("Animation not found"),
+                        Some(animation) => playback.write().start(animation, options),
+                    })
+                }
+            };
+
+            if layer.is_ok() {
+                Playback::run(&playback, &database, &executor, &io);
+                broadcast_to_all("playback:status", Ok(playback.read().get_layers()), &socket);
+            }
+            ack.send(&Ack::from(layer)).ok();
+        },
+    );
+
+    socket.on(
+        "playback:stop",
+        |socket: SocketRef,
+         State(playback): State<ArcPlayback>,
+         Data(id): Data<u64>,
+         ack: AckSender| {
+            debug!("Event received: [playback:stop]: layer:{}", id);
+
+            let layer = playback.write().stop(id);
+            if layer.is_ok() {
+                broadcast_to_all("playback:status", Ok(playback.read().get_layers()), &socket);
+            }
+            ack.send(&Ack::from(layer)).ok();
+        },
+    );
+
+    socket.on(
+        "playback:stop_all",
+        |socket: SocketRef, State(playback): State<ArcPlayback>| {
+            debug!("Event received: [playback:stop_all]");
+
+            let layers = {
+                let mut playback = playback.write();
+                playback.stop_all();
+                playback.get_layers()
+            };
+            broadcast_to_all("playback:status", Ok(layers), &socket);
+        },
+    );
+}
@@ -3,6 +3,7 @@ use log::debug;
 use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
 
 use crate::animation::posture::Posture;
+use crate::api::payloads::posture::PlayPosture;
 use crate::api::sockets::ack::Ack;
 use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
 use crate::hardware::device::Device;
@@ -75,20 +76,24 @@ pub fn register_posture_events(socket: &SocketRef) {
 
     socket.on(
         "posture:play",
-        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
-            debug!("Event received: [posture:play]: id:{:?}", id);
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         Data(request): Data<PlayPosture>,
+         ack: AckSender| {
+            debug!("Event received: [posture:play]: {:?}", request);
 
-            let mut database = database.write();
-            let posture = database
+            let (id, transition) = request.into_parts();
+            let database = database.read();
+            let mutations = database
                 .get::<Posture>(&id)
                 .and_then(|posture| match posture {
                     None => bail!("Posture not found"),
-                    Some(mut posture) => posture.play(&mut database),
+                    Some(mut posture) => posture.play(&database, transition),
                 });
 
             let devices = database.list::<Device>();
             broadcast_to_all("device:list", devices, &socket);
-            ack.send(&Ack::from(posture)).ok();
+            ack.send(&Ack::from(mutations)).ok();
         },
     );
 }
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail};
+use log::debug;
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+use socketioxide::SocketIo;
+
+use crate::animation::executor::{ArcExecutor, ShowExecutor};
+use crate::animation::playback::ArcPlayback;
+use crate::animation::show::Show;
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::{broadcast_and_ack, broadcast_to_all};
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+pub fn register_show_events(socket: &SocketRef) {
+    socket.on(
+        "show:list",
+        |ack: AckSender, State(database): State<ArcDb>| {
+            debug!("Event received: [show:list]");
+            let shows = database.read().list::<Show>();
+            ack.send(&Ack::from(shows)).ok();
+        },
+    );
+
+    socket.on(
+        "show:create",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         TryData(new_show): TryData<Show>,
+         ack: AckSender| {
+            debug!("Event received: [show:create]: show:{:#?}", new_show);
+
+            let show = match new_show {
+                Ok(show) => show.validate().and_then(|_| database.write().insert(show)),
+                Err(error) => Err(anyhow!("Invalid show: {}", error)),
+            };
+
+            broadcast_and_ack("show:updated", show, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "show:update",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         TryData(show): TryData<Show>,
+         ack: AckSender| {
+            debug!("Event received: [show:update]: show:{:#?}", show);
+
+            let show = match show {
+                Ok(show) => show.validate().and_then(|_| database.write().update(show)),
+                Err(error) => Err(anyhow!("Invalid show: {}", error)),
+            };
+            broadcast_and_ack("show:updated", show, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "show:delete",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(executor): State<ArcExecutor>,
+         State(playback): State<ArcPlayback>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [show:delete]: id:{:?}", id);
+
+            executor.write().stop(id, &mut playback.write()).ok();
+            let show = database
+                .write()
+                .delete::<Show>(id)
+                .and_then(|show| match show {
+                    None => bail!("Show not found"),
+                    Some(show) => Ok(show),
+                });
+
+            broadcast_and_ack("show:deleted", show, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "show:status",
+        |State(executor): State<ArcExecutor>, ack: AckSender| {
+            debug!("Event received: [show:status]");
+            let runs = executor.read().get_runs();
+            ack.send(&Ack::Success { success: runs }).ok();
+        },
+    );
+
+    socket.on(
+        "show:start",
+        |socket: SocketRef,
+         io: SocketIo,
+         State(database): State<ArcDb>,
+         State(executor): State<ArcExecutor>,
+         State(playback): State<ArcPlayback>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [show:start]: id:{:?}", id);
+
+            let show = database.read().get::<Show>(&id);
+            let run = show.and_then(|show| match show {
+                None => bail!("Show not found"),
+                Some(show) => ShowExecutor::start(&executor, show, &database, &playback, &io),
+            });
+
+            if let Ok(run) = &run {
+                broadcast_to_all("show:status", Ok(run), &socket);
+            }
+            ack.send(&Ack::from(run)).ok();
+        },
+    );
+
+    socket.on(
+        "show:stop",
+        |socket: SocketRef,
+         State(executor): State<ArcExecutor>,
+         State(playback): State<ArcPlayback>,
+         Data(id): Data<Id>,
+         ack: AckSender| {
+            debug!("Event received: [show:stop]: id:{:?}", id);
+
+            let run = executor.write().stop(id, &mut playback.write());
+            if let Ok(run) = &run {
+                broadcast_to_all("show:status", Ok(run), &socket);
+            }
+            ack.send(&Ack::from(run)).ok();
+        },
+    );
+
+    socket.on(
+        "show:event",
+        |State(executor): State<ArcExecutor>, Data(name): Data<String>, ack: AckSender| {
+            debug!("Event received: [show:event]: name:{}", name);
+            executor.write().notify(&name);
+            ack.send(&Ack::Success { success: name }).ok();
+        },
+    );
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, bail};
+use log::debug;
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::broadcast_and_ack;
+use crate::hardware::device::Device;
+use crate::hardware::template::DeviceTemplate;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+pub fn register_template_events(socket: &SocketRef) {
+    socket.on(
+        "template:list",
+        |ack: AckSender, State(database): State<ArcDb>| {
+            debug!("Event received: [template:list]");
+            let templates = DeviceTemplate::list(&database.read());
+            ack.send(&Ack::from(templates)).ok();
+        },
+    );
+
+    socket.on(
+        "template:create",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         TryData(new_template): TryData<DeviceTemplate>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [template:create]: template:{:#?}",
+                new_template
+            );
+
+            let template = match new_template {
+                Err(error) => Err(anyhow!("Invalid template: {}", error)),
+                Ok(template) => {
+                    let template = DeviceTemplate {
+                        builtin: false,
+                        ..template
+                    };
+                    let mut database = database.write();
+                    template
+                        .validate(&database)
+                        .and_then(|_| database.insert(template))
+                }
+            };
+            broadcast_and_ack("template:updated", template, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "template:from_device",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         Data((id, name, category)): Data<(Id, String, String)>,
+         ack: AckSender| {
+            debug!(
+                "Event received: [template:from_device]: device:{}, name:{}",
+                id, name
+            );
+
+            let mut database = database.write();
+            let template = database.get::<Device>(&id).and_then(|device| match device {
+                None => bail!("Device not found"),
+                Some(device) => {
+                    let template = DeviceTemplate::from_device(&device, name, category)?;
+                    template.validate(&database)?;
+                    database.insert(template)
+                }
+            });
+            broadcast_and_ack("template:updated", template, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "template:delete",
+        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+            debug!("Event received: [template:delete]: id:{:?}", id);
+            let template = database
+                .write()
+                .delete::<DeviceTemplate>(id)
+                .and_then(|template| match template {
+                    None => bail!("Template not found (built-in templates cannot be deleted)"),
+                    Some(template) => Ok(template),
+                });
+            broadcast_and_ack("template:deleted", template, &socket, ack);
+        },
+    );
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, bail};
+use log::debug;
+use socketioxide::extract::{AckSender, Data, SocketRef, State, TryData};
+
+use crate::animation::animation::Animation;
+use crate::animation::version::AnimationVersion;
+use crate::api::payloads::animation::AnimationPayload;
+use crate::api::sockets::ack::Ack;
+use crate::api::sockets::broadcast_and_ack;
+use crate::api::sockets::collaboration::ArcCollaboration;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::Id;
+
+pub fn register_version_events(socket: &SocketRef) {
+    socket.on(
+        "version:list",
+        |State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+            debug!("Event received: [version:list]: animation:{}", id);
+            let versions = AnimationVersion::list_for(&database.read(), &id);
+            ack.send(&Ack::from(versions)).ok();
+        },
+    );
+
+    socket.on(
+        "version:create",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         TryData(data): TryData<(Id, String)>,
+         ack: AckSender| {
+            debug!("Event received: [version:create]: {:?}", data);
+
+            let version = match data {
+                Err(error) => Err(anyhow!("Invalid version: {}", error)),
+                Ok((id, name)) => {
+                    let mut database = database.write();
+                    database
+                        .get::<Animation>(&id)
+                        .and_then(|animation| match animation {
+                            None => bail!("Animation not found"),
+                            Some(animation) => {
+                                database.insert(AnimationVersion::new(&animation, name))
+                            }
+                        })
+                }
+            };
+
+            broadcast_and_ack("version:updated", version, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "version:restore",
+        |socket: SocketRef,
+         State(database): State<ArcDb>,
+         State(collaboration): State<ArcCollaboration>,
+         TryData(data): TryData<(Id, Id)>,
+         ack: AckSender| {
+            debug!("Event received: [version:restore]: {:?}", data);
+
+            let animation = match data {
+                Err(error) => Err(anyhow!("Invalid version: {}", error)),
+                Ok((id, version)) => {
+                    let collaboration = collaboration.read();
+                    let mut database = database.write();
+                    database
+                        .get::<AnimationVersion>(&version)
+                        .and_then(|version| match version {
+                            // The version must belong to the animation being restored.
+                            Some(version) if version.animation == id => {
+                                // Restoring replaces all tracks: none may be locked by other editors.
+                                collaboration.check_tracks(
+                                    &socket.id.to_string(),
+                                    id,
+                                    &version.get_replaced_tracks(&database)?,
+                                )?;
+                                version.restore(&mut database)
+                            }
+                            _ => bail!("Version not found"),
+                        })
+                        .map(AnimationPayload::from)
+                }
+            };
+
+            broadcast_and_ack("animation:updated", animation, &socket, ack);
+        },
+    );
+
+    socket.on(
+        "version:diff",
+        |State(database): State<ArcDb>,
+         TryData(data): TryData<(Id, Option<Id>)>,
+         ack: AckSender| {
+            debug!("Event received: [version:diff]: {:?}", data);
+
+            let database = database.read();
+            let diff = match data {
+                Err(error) => Err(anyhow!("Invalid diff request: {}", error)),
+                Ok((id, other)) => {
+                    database
+                        .get::<AnimationVersion>(&id)
+                        .and_then(|version| match version {
+                            None => bail!("Version not found"),
+                            Some(version) => version.diff(&database, other),
+                        })
+                }
+            };
+            ack.send(&Ack::from(diff)).ok();
+        },
+    );
+
+    socket.on(
+        "version:delete",
+        |socket: SocketRef, State(database): State<ArcDb>, Data(id): Data<Id>, ack: AckSender| {
+            debug!("Event received: [version:delete]: id:{}", id);
+
+            let version = database
+                .write()
+                .delete::<AnimationVersion>(id)
+                .and_then(|version| match version {
+                    None => bail!("Version not found"),
+                    Some(version) => Ok(version),
+                });
+
+            broadcast_and_ack("version:deleted", version, &socket, ack);
+        },
+    );
+}
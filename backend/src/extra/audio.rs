@@ -0,0 +1,224 @@
+//! This file contains the audio helpers shared by the audio related features.
+//!
+//! - A single audio output is opened for the whole application (see [`output`]): the underlying
+//!   stream cannot be moved between threads and is therefore kept alive on a dedicated thread.
+//! - An `AudioPlayer` plays an audio file placed on a timeline (ie: the audio lane of an animation).
+//! - A `Waveform` summarizes an audio file so that it can be drawn by the UI.
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{mpsc, Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use serde::Serialize;
+
+/// The maximum gap (in ms) tolerated between the audio and the timeline before the audio is resynchronized.
+const MAX_DRIFT: u64 = 150;
+
+static OUTPUT: OnceLock<OutputStreamHandle> = OnceLock::new();
+
+/// Retrieves the handle to the default audio output (opened on first use).
+pub fn output() -> Result<&'static OutputStreamHandle> {
+    if let Some(handle) = OUTPUT.get() {
+        return Ok(handle);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || match OutputStream::try_default() {
+        Err(err) => {
+            let _ = sender.send(Err(err.to_string()));
+        }
+        Ok((_stream, handle)) => {
+            let _ = sender.send(Ok(handle));
+            // Keep the stream alive for the whole application lifetime.
+            loop {
+                std::thread::park();
+            }
+        }
+    });
+    let handle = receiver
+        .recv()?
+        .map_err(|err| anyhow!("No audio output available: {}", err))?;
+    Ok(OUTPUT.get_or_init(|| handle))
+}
+
+/// Opens and decodes an audio file.
+pub fn decode(path: &str) -> Result<Decoder<BufReader<File>>> {
+    let file = BufReader::new(File::open(path)?);
+    Decoder::new(file).map_err(|err| anyhow!("Cannot decode audio file {}: {}", path, err))
+}
+
+/// Decodes an audio file entirely into mono samples (from -1.0 to 1.0).
+/// Returns the samples along with the sample rate.
+pub fn decode_mono(path: &str) -> Result<(Vec<f32>, u32)> {
+    let decoder = decode(path)?;
+    let channels = decoder.channels().max(1) as usize;
+    let sample_rate = decoder.sample_rate();
+    let samples = decoder.convert_samples::<f32>().collect::<Vec<f32>>();
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok((mono, sample_rate))
+}
+
+/// Retrieves the duration (in ms) of an audio file.
+/// When the format does not expose it, the file is decoded entirely to count its samples.
+pub fn get_duration(path: &str) -> Result<u64> {
+    let decoder = decode(path)?;
+    if let Some(duration) = decoder.total_duration() {
+        return Ok(duration.as_millis() as u64);
+    }
+    let rate = decoder.sample_rate().max(1) as u64 * decoder.channels().max(1) as u64;
+    Ok(decoder.count() as u64 * 1000 / rate)
+}
+
+// ########################################
+// Player
+
+/// Plays an audio file placed at a given offset on a timeline.
+#[derive(Clone, Default)]
+pub struct AudioPlayer {
+    sink: Arc<RwLock<Option<Sink>>>,
+    /// The instant the playback started along with the timeline position (in ms) at that time.
+    anchor: Arc<RwLock<Option<(Instant, u64)>>>,
+}
+
+impl AudioPlayer {
+    /// Plays the file so that it follows the timeline from the given position (in ms).
+    ///
+    /// # Parameters
+    /// * `path`: the audio file path.
+    /// * `offset`: the position (in ms) of the beginning of the file on the timeline.
+    /// * `position`: the current position (in ms) on the timeline.
+    /// * `delay`: a delay (in ms) before the timeline actually starts.
+    /// * `speed`: the speed of the timeline (1.0 = normal speed).
+    pub fn play(
+        &self,
+        path: &str,
+        offset: u64,
+        position: u64,
+        delay: u64,
+        speed: f32,
+    ) -> Result<()> {
+        self.stop();
+        let speed = speed.max(0.01);
+
+        // Either skip the beginning of the file or wait for the timeline to reach it.
+        let source = decode(path)?;
+        let (skip, wait) = match position >= offset {
+            true => (position - offset, 0),
+            false => (0, ((offset - position) as f32 / speed) as u64),
+        };
+        let source = source
+            .skip_duration(Duration::from_millis(skip))
+            .delay(Duration::from_millis(delay + wait))
+            .speed(speed);
+
+        let sink = Sink::try_new(output()?)?;
+        sink.append(source);
+        *self.sink.write() = Some(sink);
+        *self.anchor.write() = Some((Instant::now() + Duration::from_millis(delay), position));
+        Ok(())
+    }
+
+    /// Pauses the playback.
+    pub fn pause(&self) {
+        if let Some(sink) = self.sink.read().as_ref() {
+            sink.pause();
+        }
+        *self.anchor.write() = None;
+    }
+
+    /// Stops the playback.
+    pub fn stop(&self) {
+        if let Some(sink) = self.sink.write().take() {
+            sink.stop();
+        }
+        *self.anchor.write() = None;
+    }
+
+    /// Checks whether the audio has drifted from the given timeline position (in ms).
+    pub fn has_drifted(&self, position: u64, speed: f32) -> bool {
+        match *self.anchor.read() {
+            None => false,
+            Some((started_at, start)) => {
+                let elapsed = Instant::now().saturating_duration_since(started_at);
+                let expected = start + (elapsed.as_millis() as f32 * speed) as u64;
+                expected.abs_diff(position) > MAX_DRIFT
+            }
+        }
+    }
+}
+
+impl Debug for AudioPlayer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AudioPlayer")
+            .field("playing", &self.anchor.read().is_some())
+            .finish()
+    }
+}
+
+// ########################################
+// Waveform
+
+/// A summary of an audio file: its duration and its peaks.
+#[derive(Clone, Debug, Serialize)]
+pub struct Waveform {
+    /// The duration (in ms) of the audio file.
+    pub duration: u64,
+    /// The highest absolute amplitude of the file (from 0.0 to 1.0).
+    pub peak: f32,
+    /// The (min, max) amplitudes of each bucket of the file (from -1.0 to 1.0).
+    pub peaks: Vec<(f32, f32)>,
+}
+
+impl Waveform {
+    /// Computes the waveform of an audio file split in the given number of buckets.
+    pub fn from_file(path: &str, buckets: usize) -> Result<Self> {
+        let (samples, sample_rate) = decode_mono(path)?;
+        Ok(Self::from_samples(&samples, sample_rate, buckets))
+    }
+
+    /// Computes the waveform of the given mono samples split in the given number of buckets.
+    pub fn from_samples(samples: &[f32], sample_rate: u32, buckets: usize) -> Self {
+        let duration = samples.len() as u64 * 1000 / sample_rate.max(1) as u64;
+        let size = samples.len().div_ceil(buckets.max(1)).max(1);
+        let peaks = samples
+            .chunks(size)
+            .map(|chunk| {
+                chunk.iter().fold((0.0f32, 0.0f32), |(min, max), sample| {
+                    (min.min(*sample), max.max(*sample))
+                })
+            })
+            .collect::<Vec<(f32, f32)>>();
+        let peak = peaks
+            .iter()
+            .fold(0.0f32, |peak, (min, max)| peak.max(-min).max(*max));
+        Self {
+            duration,
+            peak,
+            peaks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waveform_from_samples() {
+        let samples = [0.0, 0.5, -0.25, 0.1, -0.8, 0.2, 0.0, 0.0];
+        let waveform = Waveform::from_samples(&samples, 4, 4);
+        assert_eq!(waveform.duration, 2000);
+        assert_eq!(
+            waveform.peaks,
+            vec![(0.0, 0.5), (-0.25, 0.1), (-0.8, 0.2), (0.0, 0.0)]
+        );
+        assert_eq!(waveform.peak, 0.8);
+    }
+}
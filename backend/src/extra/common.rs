@@ -0,0 +1,20 @@
+//! This file contains the helpers shared by the custom outputs (see [`hermes_five::devices::Output`]).
+use hermes_five::errors::Error;
+use hermes_five::utils::State;
+
+/// Scales the state of a discrete output (a song, a text, a sound effect...): the target is played once, at the
+/// start of its keyframe, and nothing (`State::Null`) is sent for the rest of it.
+pub fn scale_discrete(target: State, progress: f32) -> State {
+    match progress > 0.01 {
+        true => State::Null,
+        _ => target,
+    }
+}
+
+/// Converts any error into a hermes [`Error`].
+pub fn unknown<E: ToString>(err: E) -> Error {
+    hermes_five::errors::Unknown {
+        info: err.to_string(),
+    }
+    .into()
+}
@@ -0,0 +1,451 @@
+//! This file contains the `Display` device: a small I2C screen showing text, bitmaps or sprites.
+//!
+//! Two controllers are supported, both driven through the Firmata I2C messages of the board:
+//! - the HD44780 character LCD (through its PCF8574 I2C backpack, default address 0x27),
+//! - the SSD1306 monochrome OLED (default address 0x3C).
+//!
+//! The display state is its content:
+//! - a text (ie: "Hello\nWorld"): one line per row,
+//! - a sprite index: one of the sprites predefined on the device,
+//! - a bitmap object (ie: `{"bitmap": ["..##..", ".#..#."]}`).
+//!
+//! Sprites and bitmaps are lists of rows: pixel rows on an OLED (`#` pixels are lit), text lines on a LCD. In an
+//! animation, the content changes at the start of its keyframe.
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use font8x8::{UnicodeFonts, BASIC_FONTS};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::utils::{Easing, State};
+use hermes_five::Board;
+use log::warn;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::extra::common::scale_discrete;
+use crate::extra::i2c::{I2cDevice, MAX_WRITE};
+
+// PCF8574 backpack bits.
+const LCD_RS: u8 = 0x01;
+const LCD_EN: u8 = 0x04;
+const LCD_BACKLIGHT: u8 = 0x08;
+// HD44780 commands.
+const LCD_CLEAR: u8 = 0x01;
+const LCD_ENTRY_MODE: u8 = 0x06;
+const LCD_DISPLAY_ON: u8 = 0x0C;
+const LCD_FUNCTION_SET: u8 = 0x28;
+const LCD_SET_DDRAM: u8 = 0x80;
+
+// SSD1306 control bytes.
+const OLED_COMMAND: u8 = 0x00;
+const OLED_DATA: u8 = 0x40;
+
+/// The glyphs size (in pixels) of the OLED text.
+const GLYPH: usize = 8;
+
+/// The screen controller, along with its geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model")]
+pub enum Controller {
+    /// A character LCD.
+    #[serde(rename = "HD44780")]
+    Hd44780 { columns: u8, rows: u8 },
+    /// A monochrome OLED.
+    #[serde(rename = "SSD1306")]
+    Ssd1306 { width: u8, height: u8 },
+}
+
+impl Controller {
+    /// The default I2C address of the controller.
+    pub fn default_address(&self) -> u8 {
+        match self {
+            Controller::Hd44780 { .. } => 0x27,
+            Controller::Ssd1306 { .. } => 0x3C,
+        }
+    }
+
+    /// Checks the geometry is supported: up to 4 rows of 40 characters for a LCD, up to 128x64 pixels for an
+    /// OLED (whose height is made of 8 pixels high pages).
+    pub fn check(&self) -> Result<(), Error> {
+        match *self {
+            Controller::Hd44780 { columns, rows }
+                if columns == 0 || columns > 40 || rows == 0 || rows > 4 =>
+            {
+                Err(display_error(format!(
+                    "Unsupported LCD geometry: {}x{} characters",
+                    columns, rows
+                )))
+            }
+            Controller::Ssd1306 { width, height }
+                if width == 0 || width > 128 || height < 8 || height > 64 || height % 8 != 0 =>
+            {
+                Err(display_error(format!(
+                    "Unsupported OLED geometry: {}x{} pixels",
+                    width, height
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The content of a display.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Sprite(u16),
+    Text(String),
+    Bitmap { bitmap: Vec<String> },
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Text(String::new())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Display {
+    controller: Controller,
+    /// The I2C address (default: the controller one).
+    #[serde(default)]
+    address: Option<u8>,
+    /// The predefined sprites (see the file header).
+    #[serde(default)]
+    sprites: Vec<Vec<String>>,
+    /// The current content.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<Content>>,
+    /// The default content.
+    #[serde(default)]
+    default: Content,
+
+    #[serde(skip)]
+    device: Option<I2cDevice>,
+}
+
+impl Display {
+    /// Attaches the display to a board: the controller is initialized and the current content shown.
+    pub fn attach(mut self, board: &Board) -> Result<Self, Error> {
+        self.controller.check()?;
+        let address = self.address.unwrap_or(self.controller.default_address());
+        let mut device = I2cDevice::new(board, address)?;
+        match self.controller {
+            Controller::Hd44780 { .. } => {
+                // Forces the 4 bits mode, whatever the current mode of the controller.
+                for nibble in [0x03, 0x03, 0x03, 0x02] {
+                    device.write(&lcd_nibble(nibble, 0))?;
+                    sleep(Duration::from_millis(5));
+                }
+                for command in [LCD_FUNCTION_SET, LCD_DISPLAY_ON, LCD_ENTRY_MODE, LCD_CLEAR] {
+                    device.write(&lcd_byte(command, 0))?;
+                }
+                sleep(Duration::from_millis(2));
+            }
+            Controller::Ssd1306 { height, .. } => {
+                for commands in oled_init(height).chunks(MAX_WRITE - 1) {
+                    device.write(&[&[OLED_COMMAND][..], commands].concat())?;
+                }
+            }
+        }
+        self.device = Some(device);
+        let content = self.state.read().clone();
+        self.show(&content)?;
+        Ok(self)
+    }
+
+    pub fn get_controller(&self) -> Controller {
+        self.controller
+    }
+
+    /// Computes the rows of a content (see the file header).
+    pub fn get_rows(&self, content: &Content) -> Result<Vec<String>, Error> {
+        match content {
+            Content::Text(text) => Ok(text.lines().map(String::from).collect()),
+            Content::Bitmap { bitmap } => Ok(bitmap.clone()),
+            Content::Sprite(index) => match self.sprites.get(*index as usize) {
+                None => Err(display_error(format!("Unknown sprite: {}", index))),
+                Some(sprite) => Ok(sprite.clone()),
+            },
+        }
+    }
+
+    /// (private)
+    /// Renders a content and sends it to the screen.
+    fn show(&mut self, content: &Content) -> Result<(), Error> {
+        let rows = self.get_rows(content)?;
+        let device = match self.device.as_mut() {
+            None => return Err(display_error("Display is not attached to a board")),
+            Some(device) => device,
+        };
+        match self.controller {
+            Controller::Hd44780 {
+                columns,
+                rows: lines,
+            } => {
+                for bytes in lcd_frame(&rows, columns, lines).chunks(MAX_WRITE) {
+                    device.write(bytes)?;
+                }
+            }
+            Controller::Ssd1306 { width, height } => {
+                let buffer = match content {
+                    Content::Text(_) => oled_text(&rows, width, height),
+                    _ => oled_bitmap(&rows, width, height),
+                };
+                let pages = height / 8;
+                device.write(&[OLED_COMMAND, 0x21, 0, width - 1, 0x22, 0, pages - 1])?;
+                for data in buffer.chunks(MAX_WRITE - 1) {
+                    device.write(&[&[OLED_DATA][..], data].concat())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// (private)
+fn display_error<S: Into<String>>(info: S) -> Error {
+    hermes_five::errors::Unknown { info: info.into() }.into()
+}
+
+/// Encodes a nibble written to the LCD through its backpack: the enable bit is pulsed to latch it.
+pub fn lcd_nibble(nibble: u8, flags: u8) -> [u8; 2] {
+    let byte = (nibble << 4) | flags | LCD_BACKLIGHT;
+    [byte | LCD_EN, byte]
+}
+
+/// Encodes a byte written to the LCD (high nibble first): a command, or a character when flagged RS.
+pub fn lcd_byte(value: u8, flags: u8) -> [u8; 4] {
+    let [high, high_latch] = lcd_nibble(value >> 4, flags);
+    let [low, low_latch] = lcd_nibble(value & 0x0F, flags);
+    [high, high_latch, low, low_latch]
+}
+
+/// Builds the bytes writing each row of the LCD (padded or truncated to the LCD size).
+pub fn lcd_frame(rows: &[String], columns: u8, lines: u8) -> Vec<u8> {
+    let mut frame = vec![];
+    for line in 0..lines {
+        let offset = match line {
+            0 | 2 => 0,
+            _ => 0x40,
+        } + (line / 2) * columns;
+        frame.extend(lcd_byte(LCD_SET_DDRAM | offset, 0));
+        let text = rows.get(line as usize).map(String::as_str).unwrap_or("");
+        let characters = text.chars().chain(std::iter::repeat(' '));
+        for character in characters.take(columns as usize) {
+            let character = match character.is_ascii() && !character.is_ascii_control() {
+                true => character as u8,
+                false => b'?',
+            };
+            frame.extend(lcd_byte(character, LCD_RS));
+        }
+    }
+    frame
+}
+
+/// Builds the SSD1306 initialization commands.
+pub fn oled_init(height: u8) -> Vec<u8> {
+    let pins = match height {
+        64 => 0x12,
+        _ => 0x02,
+    };
+    vec![
+        0xAE, // Display off
+        0xD5,
+        0x80, // Clock
+        0xA8,
+        height - 1, // Multiplex
+        0xD3,
+        0x00, // Offset
+        0x40, // Start line
+        0x8D,
+        0x14, // Charge pump
+        0x20,
+        0x00, // Horizontal addressing
+        0xA1,
+        0xC8, // Segments and rows remap
+        0xDA,
+        pins, // Com pins
+        0x81,
+        0xCF, // Contrast
+        0xD9,
+        0xF1, // Precharge
+        0xDB,
+        0x40, // Vcom detect
+        0xA4,
+        0xA6, // Resume, normal (not inverted)
+        0xAF, // Display on
+    ]
+}
+
+/// (private)
+/// Lights a pixel of an OLED buffer (made of 8 pixels high pages, one byte per column).
+fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize) {
+    if let Some(byte) = buffer.get_mut((y / 8) * width + x) {
+        *byte |= 1 << (y % 8);
+    }
+}
+
+/// Renders text rows into an OLED buffer.
+pub fn oled_text(rows: &[String], width: u8, height: u8) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut buffer = vec![0; width * height / 8];
+    for (line, text) in rows.iter().enumerate().take(height / GLYPH) {
+        for (column, character) in text.chars().enumerate().take(width / GLYPH) {
+            let glyph = BASIC_FONTS.get(character).unwrap_or_default();
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in (0..GLYPH).filter(|x| (bits >> x) & 1 == 1) {
+                    set_pixel(&mut buffer, width, column * GLYPH + x, line * GLYPH + y);
+                }
+            }
+        }
+    }
+    buffer
+}
+
+/// Renders pixel rows (`#` pixels are lit) into an OLED buffer.
+pub fn oled_bitmap(rows: &[String], width: u8, height: u8) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut buffer = vec![0; width * height / 8];
+    for (y, row) in rows.iter().enumerate().take(height) {
+        for (x, _) in row
+            .chars()
+            .enumerate()
+            .take(width)
+            .filter(|(_, c)| *c == '#')
+        {
+            set_pixel(&mut buffer, width, x, y);
+        }
+    }
+    buffer
+}
+
+#[typetag::serde]
+impl Device for Display {}
+
+#[typetag::serde]
+impl Output for Display {
+    fn animate<S: Into<State>>(&mut self, state: S, _duration: u64, _transition: Easing)
+    where
+        Self: Sized,
+    {
+        if let Err(err) = self.set_state(state.into()) {
+            warn!("Display failed to show: {}", err);
+        }
+    }
+
+    fn stop(&mut self) {}
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        if let State::Null = state {
+            return Ok(self.get_state());
+        }
+        let content: Content = serde_json::to_value(&state)
+            .and_then(serde_json::from_value)
+            .map_err(|err| display_error(format!("Invalid content: {}", err)))?;
+        self.show(&content)?;
+        *self.state.write() = content.clone();
+        Ok(State::into_state(content))
+    }
+
+    fn get_state(&self) -> State {
+        State::into_state(self.state.read().clone())
+    }
+
+    fn get_default(&self) -> State {
+        State::into_state(self.default.clone())
+    }
+
+    fn is_busy(&self) -> bool {
+        false
+    }
+
+    fn scale_state(&mut self, _previous: State, target: State, progress: f32) -> State {
+        scale_discrete(target, progress)
+    }
+}
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Display ({:?}) [state={:?}]",
+            self.controller,
+            self.state.read()
+        )
+    }
+}
+
+impl Debug for Display {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Display")
+            .field("controller", &self.controller)
+            .field("address", &self.address)
+            .field("sprites", &self.sprites.len())
+            .field("state", &self.state.read())
+            .field("default", &self.default)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_content() {
+        let content: Content = serde_json::from_value(json!(2)).unwrap();
+        assert_eq!(content, Content::Sprite(2));
+        let content: Content = serde_json::from_value(json!("Score: 3")).unwrap();
+        assert_eq!(content, Content::Text(String::from("Score: 3")));
+        let content: Content = serde_json::from_value(json!({"bitmap": ["#.#"]})).unwrap();
+        assert_eq!(
+            content,
+            Content::Bitmap {
+                bitmap: vec![String::from("#.#")]
+            }
+        );
+    }
+
+    #[test]
+    fn test_controller_check() {
+        let lcd = |columns, rows| Controller::Hd44780 { columns, rows }.check().is_ok();
+        assert!(lcd(16, 2));
+        assert!(!lcd(0, 2));
+        assert!(!lcd(16, 0));
+        let oled = |width, height| Controller::Ssd1306 { width, height }.check().is_ok();
+        assert!(oled(128, 32));
+        assert!(!oled(0, 32));
+        assert!(!oled(128, 4), "Less than a page");
+        assert!(!oled(128, 36), "Partial page");
+    }
+
+    #[test]
+    fn test_lcd_frame() {
+        assert_eq!(lcd_nibble(0x03, 0), [0x3C, 0x38]);
+        assert_eq!(lcd_byte(b'A', LCD_RS), [0x4D, 0x49, 0x1D, 0x19]);
+
+        let frame = lcd_frame(&[String::from("Hi")], 16, 2);
+        assert_eq!(frame.len(), 2 * (1 + 16) * 4, "Rows are padded");
+        assert_eq!(frame[..4], lcd_byte(0x80, 0));
+        assert_eq!(frame[68..72], lcd_byte(0xC0, 0), "Second row");
+        assert_eq!(lcd_frame(&[], 20, 4)[3 * 84..3 * 84 + 4], lcd_byte(0xD4, 0));
+    }
+
+    #[test]
+    fn test_oled_buffer() {
+        let rows = vec![String::from("#."), String::from(".#")];
+        let buffer = oled_bitmap(&rows, 128, 32);
+        assert_eq!(buffer.len(), 512);
+        assert_eq!(buffer[0..3], [0b01, 0b10, 0]);
+
+        let buffer = oled_text(&[String::from(" I")], 128, 64);
+        assert!(buffer[0..8].iter().all(|byte| *byte == 0), "Space is blank");
+        assert!(buffer[8..16].iter().any(|byte| *byte != 0));
+    }
+}
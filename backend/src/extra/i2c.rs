@@ -0,0 +1,57 @@
+//! This file contains the helpers to drive I2C devices through the Firmata I2C messages of a board.
+use hermes_five::errors::Error;
+use hermes_five::protocols::Protocol;
+use hermes_five::Board;
+
+const START_SYSEX: u8 = 0xF0;
+const END_SYSEX: u8 = 0xF7;
+const I2C_REQUEST: u8 = 0x76;
+const I2C_CONFIG: u8 = 0x78;
+
+/// The maximum number of bytes written at once: the Arduino Wire buffer holds 32 bytes (address included).
+pub const MAX_WRITE: usize = 30;
+
+/// An I2C device on the bus of a board.
+#[derive(Clone)]
+pub struct I2cDevice {
+    address: u8,
+    protocol: Box<dyn Protocol>,
+}
+
+impl I2cDevice {
+    /// Enables the I2C bus of the board (the firmware ignores repeated configurations).
+    pub fn new(board: &Board, address: u8) -> Result<Self, Error> {
+        let mut protocol = board.get_protocol();
+        protocol.write(&[START_SYSEX, I2C_CONFIG, 0, 0, END_SYSEX])?;
+        Ok(Self { address, protocol })
+    }
+
+    /// Writes bytes to the device in a single transaction (see [`MAX_WRITE`]).
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.protocol.write(&write_message(self.address, data))
+    }
+}
+
+/// Builds the Firmata message writing bytes to an I2C device: each byte is sent as two 7-bit bytes.
+pub fn write_message(address: u8, data: &[u8]) -> Vec<u8> {
+    let mut message = vec![START_SYSEX, I2C_REQUEST, address & 0x7F, 0x00];
+    for byte in data {
+        message.push(byte & 0x7F);
+        message.push(byte >> 7);
+    }
+    message.push(END_SYSEX);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_message() {
+        assert_eq!(
+            write_message(0x40, &[0xFE, 0x79]),
+            vec![0xF0, 0x76, 0x40, 0x00, 0x7E, 0x01, 0x79, 0x00, 0xF7]
+        );
+    }
+}
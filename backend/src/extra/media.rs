@@ -0,0 +1,244 @@
+//! This file contains the media library: the audio files shared by all audio related features.
+//!
+//! Files are stored flat under a configurable root directory (see [`Config::media_path`]) and each of them
+//! is registered in the database as a `Media` along with its metadata. Any device (mp3 players, animation
+//! audio lanes, etc.) refers to a file of the library by its path, so files are never duplicated per device.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::extra::audio::get_duration;
+use crate::impl_entity;
+use crate::utils::config::Config;
+use crate::utils::entity::Id;
+
+/// The audio formats (file extensions) accepted by the library.
+pub const SUPPORTED_FORMATS: [&str; 4] = ["mp3", "wav", "ogg", "flac"];
+
+/// An audio file of the media library.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Media {
+    pub id: Id,
+    /// The file name (unique within the library).
+    pub name: String,
+    /// The file full path.
+    pub path: PathBuf,
+    /// The audio format (ie: the file extension).
+    pub format: String,
+    /// The size (in bytes) of the file.
+    pub size: u64,
+    /// The duration (in ms) of the file.
+    pub duration: u64,
+}
+
+impl_entity!(Media);
+
+/// The media library storage settings.
+#[derive(Clone, Debug)]
+pub struct MediaLibrary {
+    /// The directory where the files are stored.
+    pub root: PathBuf,
+    /// The maximum size (in bytes) of a file.
+    pub max_size: usize,
+}
+
+impl From<&Config> for MediaLibrary {
+    fn from(config: &Config) -> Self {
+        Self {
+            root: config.media_path.clone(),
+            max_size: config.media_max_size,
+        }
+    }
+}
+
+impl MediaLibrary {
+    /// Stores a new file into the library and computes its metadata.
+    /// The file name is sanitized and made unique within the library.
+    ///
+    /// # Errors
+    /// * if the file is too large, not a supported format or cannot be decoded.
+    /// * if the file cannot be written.
+    pub fn store(&self, file_name: &str, data: &[u8]) -> Result<Media> {
+        if data.len() > self.max_size {
+            bail!(
+                "File too large: {} bytes (max: {} bytes)",
+                data.len(),
+                self.max_size
+            );
+        }
+        let name = sanitize(file_name);
+        let format = match get_format(&name) {
+            None => bail!(
+                "Unsupported file format: {} (expected: {})",
+                name,
+                SUPPORTED_FORMATS.join(", ")
+            ),
+            Some(format) => format,
+        };
+
+        fs::create_dir_all(&self.root)?;
+        let path = self.get_available_path(&name);
+        fs::write(&path, data)?;
+
+        // Ensures the file is a valid audio file.
+        let duration = match get_duration(&path.to_string_lossy()) {
+            Ok(duration) => duration,
+            Err(err) => {
+                fs::remove_file(&path)?;
+                return Err(err);
+            }
+        };
+
+        Ok(Media {
+            id: 0,
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or(name),
+            path,
+            format,
+            size: data.len() as u64,
+            duration,
+        })
+    }
+
+    /// Removes the file of a media from the library.
+    /// Files outside the library root are never touched.
+    pub fn remove(&self, media: &Media) -> Result<()> {
+        if !media.path.starts_with(&self.root) {
+            bail!("File {:?} is not part of the media library", media.path);
+        }
+        if media.path.exists() {
+            fs::remove_file(&media.path)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the path of a file of the library from its name.
+    ///
+    /// # Errors
+    /// * if the name is not a sanitized file name (see [`sanitize`]): it could lead outside the library root.
+    /// * if the file does not exist.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf> {
+        if sanitize(name) != name {
+            bail!("Invalid media name: {}", name);
+        }
+        let path = self.root.join(name);
+        if !path.is_file() {
+            bail!("Media {} not found in the library", name);
+        }
+        Ok(path)
+    }
+
+    /// (private)
+    /// Finds a path for the given name that does not exist yet: "name.ext", then "name-1.ext", etc.
+    fn get_available_path(&self, name: &str) -> PathBuf {
+        let path = self.root.join(name);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let extension = get_format(name).unwrap_or_default();
+        let mut candidate = path;
+        let mut index = 0;
+        while candidate.exists() {
+            index += 1;
+            candidate = self.root.join(format!("{}-{}.{}", stem, index, extension));
+        }
+        candidate
+    }
+}
+
+/// Sanitizes a file name: only keeps the last path component, replaces characters other than
+/// alphanumerics, '-', '_' and '.' by '_', removes leading dots and lowercases the extension.
+pub fn sanitize(file_name: &str) -> String {
+    let name = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(
+            |char| match char.is_ascii_alphanumeric() || "-_.".contains(char) {
+                true => char,
+                false => '_',
+            },
+        )
+        .collect::<String>();
+    let name = name.trim_start_matches('.');
+    let name = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}.{}", stem, extension.to_lowercase())
+        }
+        _ => name.to_string(),
+    };
+    match name.is_empty() {
+        true => String::from("unnamed"),
+        false => name,
+    }
+}
+
+/// Retrieves the format of a file from its name, if supported.
+pub fn get_format(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .filter(|extension| SUPPORTED_FORMATS.contains(&extension.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("song.MP3"), "song.mp3");
+        assert_eq!(sanitize("../../etc/passwd"), "passwd");
+        assert_eq!(
+            sanitize("C:\\music\\my song (live).ogg"),
+            "my_song__live_.ogg"
+        );
+        assert_eq!(sanitize(".hidden.wav"), "hidden.wav");
+        assert_eq!(sanitize(""), "unnamed");
+    }
+
+    #[test]
+    fn test_get_format() {
+        assert_eq!(get_format("song.mp3"), Some(String::from("mp3")));
+        assert_eq!(get_format("song.FLAC"), Some(String::from("flac")));
+        assert_eq!(get_format("song.exe"), None);
+        assert_eq!(get_format("song"), None);
+    }
+
+    #[test]
+    fn test_store_rejects_invalid_files() {
+        let root = tempfile::tempdir().unwrap();
+        let library = MediaLibrary {
+            root: root.path().to_path_buf(),
+            max_size: 8,
+        };
+        assert!(library.store("song.mp3", &[0; 16]).is_err());
+        assert!(library.store("song.txt", &[0; 4]).is_err());
+        // Not decodable: the file is not kept.
+        assert!(library.store("song.mp3", &[0; 4]).is_err());
+        assert!(!root.path().join("song.mp3").exists());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = tempfile::tempdir().unwrap();
+        let library = MediaLibrary {
+            root: root.path().to_path_buf(),
+            max_size: 8,
+        };
+        fs::write(root.path().join("song.mp3"), [0; 4]).unwrap();
+        assert_eq!(
+            library.resolve("song.mp3").unwrap(),
+            root.path().join("song.mp3")
+        );
+        assert!(library.resolve("other.mp3").is_err());
+        assert!(library.resolve("../song.mp3").is_err());
+        assert!(library.resolve("/etc/passwd").is_err());
+    }
+}
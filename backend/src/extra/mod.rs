@@ -1,2 +1,13 @@
+pub mod audio;
+pub mod common;
+pub mod display;
+pub mod i2c;
+pub mod media;
 pub mod mp3;
+pub mod pca9685;
 pub mod raspi;
+pub mod rgb;
+pub mod sampler;
+pub mod servo;
+pub mod strip;
+pub mod tts;
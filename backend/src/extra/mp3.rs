@@ -1,23 +1,31 @@
 use std::fmt::{Debug, Display, Formatter};
-use std::fs::File;
-use std::io::BufReader;
-use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use hermes_five::devices::{Device, Output};
 use hermes_five::errors::Error;
 use hermes_five::utils::events::{EventHandler, EventManager};
 use hermes_five::utils::{Easing, State};
-use hermes_five::{pause_sync, Board};
+use hermes_five::{pause, pause_sync, Board};
+use log::warn;
 use parking_lot::RwLock;
-use rodio::{Decoder, OutputStream, Sink, Source};
+use rodio::{Sink, Source};
 use serde::{Deserialize, Serialize};
 
+use crate::extra::audio::{decode, output};
+use crate::extra::common::{scale_discrete, unknown};
+
+/// The interval (in ms) between two progress events.
+const PROGRESS_INTERVAL: u64 = 250;
+
 /// Lists all events an Mp3Player device can emit/listen.
 pub enum Mp3PlayerEvent {
     /// Triggered when the song starts.
     OnStart,
+    /// Triggered periodically while the song plays (with the current position in ms).
+    OnProgress,
     /// Triggered when the song stops.
     OnEnd,
 }
@@ -27,6 +35,7 @@ impl Into<String> for Mp3PlayerEvent {
     fn into(self) -> String {
         let event = match self {
             Mp3PlayerEvent::OnStart => "start",
+            Mp3PlayerEvent::OnProgress => "progress",
             Mp3PlayerEvent::OnEnd => "end",
         };
         event.into()
@@ -34,7 +43,7 @@ impl Into<String> for Mp3PlayerEvent {
 }
 
 #[repr(i8)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 enum Mp3Command {
     PLAY = 1,
     PAUSE = 0,
@@ -71,10 +80,37 @@ impl Serialize for Mp3Command {
     }
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 struct Mp3PlayerState {
+    /// The current file path.
     path: String,
     status: Mp3Command,
+    /// The volume in percent (default: 100%).
+    volume: u8,
+    /// The duration (in ms) of the fade in / fade out when playing, pausing or stopping (default: 0).
+    fade: u64,
+    /// The position (in ms) in the current file.
+    position: u64,
+    /// The files to play in sequence.
+    playlist: Vec<String>,
+    /// Determines whether the file (or the playlist) should replay in a loop.
+    #[serde(rename = "loop")]
+    repeat: bool,
+}
+
+impl Default for Mp3PlayerState {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            status: Mp3Command::default(),
+            volume: 100,
+            fade: 0,
+            position: 0,
+            playlist: vec![],
+            repeat: false,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -88,6 +124,9 @@ pub struct Mp3Player {
     /// The mp3 controller (play/pause/stop)
     #[serde(skip)]
     control: Arc<RwLock<Option<Sink>>>,
+    /// A counter incremented each time a song starts: used to retire the monitor of the previous one.
+    #[serde(skip)]
+    session: Arc<AtomicUsize>,
     /// The event manager for the animation.
     #[serde(skip)]
     events: EventManager,
@@ -99,99 +138,245 @@ impl Mp3Player {
             state: Arc::new(Default::default()),
             default: Default::default(),
             control: Arc::new(RwLock::new(None)),
+            session: Arc::new(AtomicUsize::new(0)),
             events: Default::default(),
         })
     }
 
+    /// Copies the settings (path, volume, playlist, etc...) of another player (but not its status).
+    pub fn with_settings_of(mut self, other: &Mp3Player) -> Self {
+        let mut state = other.state.read().clone();
+        state.status = Mp3Command::STOP;
+        state.position = 0;
+        *self.state.write() = state;
+        self.default = other.default.clone();
+        self
+    }
+
     /// Changes the current song (and play it if currently playing).
-    #[allow(dead_code)]
     pub fn change_track<P: Into<String>>(&mut self, path: P) -> Result<(), Error> {
-        self.stop();
-        self.state.write().path = path.into();
-        self.play(u64::MAX)
+        let playing = self.state.read().status == Mp3Command::PLAY;
+        self.halt();
+        {
+            let mut state = self.state.write();
+            state.path = path.into();
+            state.position = 0;
+        }
+        if playing {
+            self.play(u64::MAX)?;
+        }
+        Ok(())
     }
 
-    /// Plays the current song.
+    /// Plays the current song (for the given duration in ms at most).
     pub fn play(&mut self, duration: u64) -> Result<(), Error> {
-        // Resume control if the song is not done.
-        if let Some(control) = self.control.read().as_ref() {
-            if !control.empty() {
+        // Resume control if the song is not done: a fade starts silent, before the sink resumes.
+        let fade = self.state.read().fade;
+        let resumed = match self.control.read().as_ref() {
+            Some(control) if !control.empty() => {
+                if fade > 0 {
+                    control.set_volume(0.0);
+                }
                 control.play();
+                true
+            }
+            _ => false,
+        };
+
+        match resumed {
+            true => {
+                if fade > 0 {
+                    let (control, volume) = (self.control.clone(), self.get_volume());
+                    tokio::task::spawn_blocking(move || ramp(&control, 0.0, volume, fade));
+                }
+            }
+            false => {
+                if self.state.read().path.is_empty() {
+                    return Ok(());
+                }
+                self.start(duration)?;
             }
-        } else if !self.state.read().path.is_empty() {
-            let self_clone = self.clone();
-
-            tokio::task::spawn_blocking(move || {
-                // Start playing the current file
-                // Get an output stream handle to the default physical sound device
-                let (_stream, stream_handle) =
-                    OutputStream::try_default().map_err(|err| hermes_five::errors::Unknown {
-                        info: err.to_string(),
-                    })?;
-
-                // Load a sound from a file, using a path relative to Cargo.toml
-                let path = self_clone.state.read().path.clone();
-                let file = BufReader::new(File::open(path)?);
-
-                // Decode that sound file into a source
-                let source = Decoder::new(file)
-                    .map_err(|err| hermes_five::errors::Unknown {
-                        info: err.to_string(),
-                    })?
-                    .convert_samples::<f32>();
-                let duration = source
-                    .total_duration()
-                    .unwrap()
-                    .as_millis()
-                    .min(duration as u128);
-
-                // Play the sound directly on the device
-                let sink =
-                    Sink::try_new(&stream_handle).map_err(|err| hermes_five::errors::Unknown {
-                        info: err.to_string(),
-                    })?;
-                sink.append(source);
-
-                self_clone
-                    .events
-                    .emit(Mp3PlayerEvent::OnStart, self_clone.clone());
-
-                // Save the sink for later control.
-                *self_clone.control.write() = Some(sink);
-
-                pause_sync!(duration);
-
-                // Delete the sink now that it is not needed anymore.
-                *self_clone.control.write() = None;
-                self_clone
-                    .events
-                    .emit(Mp3PlayerEvent::OnEnd, self_clone.clone());
-
-                Ok::<(), Error>(())
-            });
         }
 
         self.state.write().status = Mp3Command::PLAY;
         Ok(())
     }
 
-    /// Plays the current song.
+    /// Pauses the current song.
     pub fn pause(&mut self) {
-        match self.control.read().deref() {
-            None => {}
-            Some(control) => control.pause(),
+        let fade = self.state.read().fade;
+        match fade {
+            0 => {
+                if let Some(control) = self.control.read().as_ref() {
+                    control.pause();
+                }
+            }
+            fade => {
+                let (control, volume) = (self.control.clone(), self.get_volume());
+                tokio::task::spawn_blocking(move || {
+                    ramp(&control, volume, 0.0, fade);
+                    if let Some(control) = control.read().as_ref() {
+                        control.pause();
+                        control.set_volume(volume);
+                    }
+                });
+            }
         };
         self.state.write().status = Mp3Command::PAUSE;
     }
 
+    /// Moves to the given position (in ms) in the current song.
+    pub fn seek(&mut self, position: u64) -> Result<(), Error> {
+        if let Some(control) = self.control.read().as_ref() {
+            control
+                .try_seek(Duration::from_millis(position))
+                .map_err(unknown)?;
+        }
+        self.state.write().position = position;
+        Ok(())
+    }
+
+    /// Sets the volume (in percent).
+    pub fn set_volume(&mut self, volume: u8) {
+        self.state.write().volume = volume;
+        if let Some(control) = self.control.read().as_ref() {
+            control.set_volume(self.get_volume());
+        }
+    }
+
+    /// Sets the playlist: the current song becomes the first one unless it is part of the playlist.
+    pub fn set_playlist(&mut self, playlist: Vec<String>) -> Result<(), Error> {
+        let first = match playlist.contains(&self.state.read().path) {
+            true => None,
+            false => playlist.first().cloned(),
+        };
+        self.state.write().playlist = playlist;
+        if let Some(first) = first {
+            self.change_track(first)?;
+        }
+        Ok(())
+    }
+
     pub fn get_path(&self) -> String {
         self.state.read().path.clone()
     }
 
-    /// Set the path (this does not change the current playing file).
-    pub fn set_path<P: Into<String>>(self, path: P) -> Self {
-        self.state.write().path = path.into();
-        self
+    /// (private)
+    /// Retrieves the volume as a ratio (1.0 = 100%).
+    fn get_volume(&self) -> f32 {
+        self.state.read().volume as f32 / 100.0
+    }
+
+    /// (private)
+    /// Starts playing the current song from the current position.
+    fn start(&mut self, duration: u64) -> Result<(), Error> {
+        let state = self.state.read().clone();
+
+        // Decode the sound file into a source.
+        let source = decode(&state.path)
+            .map_err(unknown)?
+            .convert_samples::<f32>();
+
+        // Play the sound on the (shared) default physical sound device.
+        let sink = Sink::try_new(output().map_err(unknown)?).map_err(unknown)?;
+        sink.set_volume(self.get_volume());
+        match state.fade {
+            0 => sink.append(source),
+            fade => sink.append(source.fade_in(Duration::from_millis(fade))),
+        };
+        if state.position > 0 {
+            sink.try_seek(Duration::from_millis(state.position))
+                .map_err(unknown)?;
+        }
+
+        // Save the sink for later control.
+        *self.control.write() = Some(sink);
+        self.events.emit(Mp3PlayerEvent::OnStart, self.clone());
+        self.monitor(state.position, duration);
+        Ok(())
+    }
+
+    /// (private)
+    /// Watches the song: reports its progress and handles its end (next song of the playlist, loop, etc...).
+    ///
+    /// The end is detected when the sink runs dry, so formats whose total duration cannot be measured
+    /// are handled as any other.
+    fn monitor(&self, from: u64, duration: u64) {
+        let session = self.session.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut player = self.clone();
+        tokio::spawn(async move {
+            loop {
+                pause!(PROGRESS_INTERVAL);
+                if player.session.load(Ordering::SeqCst) != session {
+                    break;
+                }
+                let (empty, position) = match player.control.read().as_ref() {
+                    None => break,
+                    Some(control) => (control.empty(), control.get_pos().as_millis() as u64),
+                };
+                if player.state.read().status != Mp3Command::PLAY {
+                    continue;
+                }
+
+                player.state.write().position = position;
+                player.events.emit(Mp3PlayerEvent::OnProgress, position);
+
+                if empty {
+                    player.next();
+                    break;
+                }
+                if position.saturating_sub(from) >= duration {
+                    player.stop();
+                    break;
+                }
+            }
+        });
+    }
+
+    /// (private)
+    /// Handles the end of the current song: plays the next song of the playlist (or the same song again
+    /// in loop mode) if any.
+    fn next(&mut self) {
+        self.events.emit(Mp3PlayerEvent::OnEnd, self.clone());
+        let next = {
+            let state = self.state.read();
+            match state.playlist.iter().position(|path| path == &state.path) {
+                Some(index) if index + 1 < state.playlist.len() => {
+                    Some(state.playlist[index + 1].clone())
+                }
+                Some(_) if state.repeat => state.playlist.first().cloned(),
+                None if state.repeat => Some(state.path.clone()),
+                _ => None,
+            }
+        };
+
+        self.halt();
+        match next {
+            None => {
+                let mut state = self.state.write();
+                state.status = Mp3Command::STOP;
+                state.position = 0;
+            }
+            Some(path) => {
+                {
+                    let mut state = self.state.write();
+                    state.path = path;
+                    state.position = 0;
+                }
+                if let Err(err) = self.start(u64::MAX) {
+                    warn!("Mp3Player failed to play the next song: {}", err);
+                    self.state.write().status = Mp3Command::STOP;
+                }
+            }
+        }
+    }
+
+    /// (private)
+    /// Stops the playback immediately (no fade out, status unchanged).
+    fn halt(&mut self) {
+        if let Some(control) = self.control.write().take() {
+            control.stop();
+        }
     }
 
     // ########################################
@@ -201,6 +386,7 @@ impl Mp3Player {
     ///
     /// Available events are:
     /// * `OnStart` | `start`: Triggered when the song starts. To use it, register though the [`Self::on()`] method.
+    /// * `OnProgress` | `progress`: Triggered periodically while the song plays (with the position in ms).
     /// * `OnEnd` | `end`: Triggered when the song ends. To use it, register though the [`Self::on()`] method.
     /// ```
     #[allow(dead_code)]
@@ -215,6 +401,18 @@ impl Mp3Player {
     }
 }
 
+/// Gradually changes the volume of the sink (if any) over the given duration (in ms).
+fn ramp(control: &Arc<RwLock<Option<Sink>>>, from: f32, to: f32, duration: u64) {
+    let steps = (duration / 20).max(1);
+    for step in 1..=steps {
+        pause_sync!(duration / steps);
+        match control.read().as_ref() {
+            None => return,
+            Some(control) => control.set_volume(from + (to - from) * step as f32 / steps as f32),
+        }
+    }
+}
+
 #[typetag::serde]
 impl Device for Mp3Player {}
 
@@ -227,7 +425,7 @@ impl Output for Mp3Player {
         match state.into().clone() {
             State::Object(state) => {
                 if let Some(path) = state.get("path") {
-                    self.state.write().path = path.as_string();
+                    let _ = self.change_track(path.as_string());
                 }
                 let _ = self.play(duration);
             }
@@ -239,12 +437,24 @@ impl Output for Mp3Player {
 
     /// Stops the current song.
     fn stop(&mut self) {
-        // match self.control.read().deref() {
-        //     None => {}
-        //     Some(control) => control.stop(),
-        // };
-        *self.control.write() = None;
-        self.state.write().status = Mp3Command::STOP;
+        let fade = self.state.read().fade;
+        match (fade, self.control.write().take()) {
+            (_, None) => {}
+            (0, Some(control)) => control.stop(),
+            (fade, Some(control)) => {
+                // Fade out on a sink of its own, so that a new song can start meanwhile.
+                let (control, volume) = (Arc::new(RwLock::new(Some(control))), self.get_volume());
+                tokio::task::spawn_blocking(move || {
+                    ramp(&control, volume, 0.0, fade);
+                    if let Some(control) = control.write().take() {
+                        control.stop();
+                    }
+                });
+            }
+        };
+        let mut state = self.state.write();
+        state.status = Mp3Command::STOP;
+        state.position = 0;
     }
 
     fn set_state(&mut self, state: State) -> Result<State, Error> {
@@ -262,11 +472,30 @@ impl Output for Mp3Player {
                 Mp3Command::STOP => self.stop(),
             },
             State::Float(_) => {}
-            State::String(path) => self.state.write().path = path,
-            State::Array(_) => {}
+            State::String(path) => self.change_track(path)?,
+            State::Array(playlist) => {
+                self.set_playlist(playlist.iter().map(|path| path.as_string()).collect())?
+            }
             State::Object(state) => {
+                if let Some(volume) = state.get("volume") {
+                    self.set_volume(volume.as_integer().min(100) as u8);
+                }
+                if let Some(fade) = state.get("fade") {
+                    self.state.write().fade = fade.as_integer();
+                }
+                if let Some(repeat) = state.get("loop") {
+                    self.state.write().repeat = repeat.as_bool();
+                }
+                if let Some(State::Array(playlist)) = state.get("playlist") {
+                    self.set_playlist(playlist.iter().map(|path| path.as_string()).collect())?;
+                }
                 if let Some(path) = state.get("path") {
-                    self.state.write().path = path.as_string();
+                    if path.as_string() != self.get_path() {
+                        self.change_track(path.as_string())?;
+                    }
+                }
+                if let Some(position) = state.get("position") {
+                    self.seek(position.as_integer())?;
                 }
                 if let Some(status) = state.get("status") {
                     let _ = self.set_state(status.clone());
@@ -293,10 +522,7 @@ impl Output for Mp3Player {
     }
 
     fn scale_state(&mut self, _previous: State, target: State, progress: f32) -> State {
-        match progress > 0.01 {
-            true => State::Null,
-            _ => target,
-        }
+        scale_discrete(target, progress)
     }
 }
 
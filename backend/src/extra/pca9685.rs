@@ -0,0 +1,449 @@
+//! This file contains the PCA9685 devices: servos and PWM outputs driven through a PCA9685 I2C expander.
+//!
+//! A PCA9685 provides 16 PWM channels over I2C, several of them can share the bus (one address each). The chip
+//! is driven through the Firmata I2C messages of the board, so that no firmware change is needed. Each channel
+//! is a device of its own (a `PcaServo` or a `PcaPwm`), which makes it usable in groups, postures and animations
+//! as any other servo or output.
+//!
+//! The chip itself is initialized once per board and address, by the first channel attached: its PWM frequency
+//! is shared by all its channels.
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use hermes_five::animation::{Animation, Keyframe, Segment, Track};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::utils::{Easing, State};
+use hermes_five::Board;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::extra::i2c::I2cDevice;
+
+/// The default I2C address of a PCA9685.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+/// The number of channels of a PCA9685.
+pub const CHANNELS: u8 = 16;
+/// The resolution of the PCA9685 PWM (12 bits).
+const RESOLUTION: u32 = 4096;
+/// The frequency of the PCA9685 internal oscillator (in Hz).
+const OSCILLATOR: u32 = 25_000_000;
+
+// PCA9685 registers.
+const MODE1: u8 = 0x00;
+const PRESCALE: u8 = 0xFE;
+const LED0_ON_L: u8 = 0x06;
+// MODE1 bits.
+const RESTART: u8 = 0x80;
+const AUTO_INCREMENT: u8 = 0x20;
+const SLEEP: u8 = 0x10;
+/// The "full on/off" bit of the ON_H and OFF_H registers.
+const FULL: u8 = 0x10;
+
+/// The chips initialized so far, keyed by (board id, address): their PWM frequency.
+static CHIPS: Mutex<BTreeMap<(usize, u8), u16>> = Mutex::new(BTreeMap::new());
+
+/// A channel of a PCA9685 chip, as used by a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcaChannel {
+    /// The I2C address of the chip.
+    pub address: u8,
+    pub channel: u8,
+    /// The PWM frequency (in Hz) the device expects from the chip.
+    pub frequency: u16,
+}
+
+/// A PCA9685 chip, as seen from one of its channels.
+#[derive(Clone)]
+pub struct Pca9685 {
+    device: I2cDevice,
+}
+
+impl Pca9685 {
+    /// Configures the I2C bus of the board (given with its id) and initializes the chip at the given PWM
+    /// frequency, unless it already runs at that frequency.
+    pub fn new(board: &Board, bid: usize, address: u8, frequency: u16) -> Result<Self, Error> {
+        let mut device = I2cDevice::new(board, address)?;
+        let mut chips = CHIPS.lock();
+        if chips.get(&(bid, address)) != Some(&frequency) {
+            device.write(&[MODE1, SLEEP])?;
+            device.write(&[PRESCALE, prescale(frequency)])?;
+            device.write(&[MODE1, AUTO_INCREMENT])?;
+            // The oscillator needs 500µs to stabilize before the restart.
+            sleep(Duration::from_millis(5));
+            device.write(&[MODE1, RESTART | AUTO_INCREMENT])?;
+            chips.insert((bid, address), frequency);
+        }
+        Ok(Self { device })
+    }
+
+    /// Forgets the chips of a board: the next channel attached initializes them again (ie: when the board
+    /// reopens, as the chips may have been power cycled meanwhile).
+    pub fn forget(bid: usize) {
+        CHIPS.lock().retain(|(board, _), _| *board != bid);
+    }
+
+    /// Sets the duty cycle of a channel (in ticks, out of 4096).
+    pub fn set_duty(&mut self, channel: u8, duty: u16) -> Result<(), Error> {
+        self.device.write(&channel_registers(channel, duty))
+    }
+}
+
+/// Computes the prescale value of the chip for a PWM frequency (in Hz).
+pub fn prescale(frequency: u16) -> u8 {
+    let prescale =
+        (OSCILLATOR as f32 / (RESOLUTION as f32 * frequency.max(1) as f32)).round() - 1.0;
+    prescale.clamp(3.0, 255.0) as u8
+}
+
+/// Builds the register writes setting the duty cycle of a channel (in ticks, out of 4096).
+pub fn channel_registers(channel: u8, duty: u16) -> [u8; 5] {
+    let register = LED0_ON_L + 4 * channel;
+    match duty {
+        0 => [register, 0, 0, 0, FULL],
+        duty if duty as u32 >= RESOLUTION => [register, 0, FULL, 0, 0],
+        duty => [register, 0, 0, (duty & 0xFF) as u8, (duty >> 8) as u8],
+    }
+}
+
+/// (private)
+fn not_attached() -> Error {
+    hermes_five::errors::Unknown {
+        info: String::from("PCA9685 channel is not attached to a board"),
+    }
+    .into()
+}
+
+/// (private)
+fn default_address() -> u8 {
+    DEFAULT_ADDRESS
+}
+
+/// (private)
+fn default_frequency() -> u16 {
+    50
+}
+
+/// (private)
+fn default_degree_range() -> (u16, u16) {
+    (0, 180)
+}
+
+/// (private)
+fn default_pwm_range() -> (u16, u16) {
+    (600, 2400)
+}
+
+/// A servo plugged on a channel of a PCA9685.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PcaServo {
+    /// The I2C address of the chip (default: 0x40).
+    #[serde(default = "default_address")]
+    address: u8,
+    /// The chip channel (0-15).
+    channel: u8,
+    /// The PWM frequency of the chip (in Hz, default: 50).
+    #[serde(default = "default_frequency")]
+    frequency: u16,
+    /// The (min, max) angles the servo is allowed to reach.
+    #[serde(default = "default_degree_range")]
+    range: (u16, u16),
+    /// The angles matching the pulse range.
+    #[serde(default = "default_degree_range")]
+    degree_range: (u16, u16),
+    /// The (min, max) pulse widths (in µs).
+    #[serde(default = "default_pwm_range")]
+    pwm_range: (u16, u16),
+    #[serde(default)]
+    inverted: bool,
+    /// The current angle.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<u16>>,
+    /// The default angle.
+    default: u16,
+
+    #[serde(skip)]
+    chip: Option<Pca9685>,
+    /// The animation currently played by the servo (if any).
+    #[serde(skip)]
+    animation: Arc<Option<Animation>>,
+}
+
+impl PcaServo {
+    /// Attaches the servo to a board (given with its id): the servo is moved to its current angle.
+    pub fn attach(mut self, board: &Board, bid: usize) -> Result<Self, Error> {
+        self.chip = Some(Pca9685::new(board, bid, self.address, self.frequency)?);
+        let state = *self.state.read();
+        self.set_state(State::Integer(state as u64))?;
+        Ok(self)
+    }
+
+    pub fn get_channel(&self) -> PcaChannel {
+        PcaChannel {
+            address: self.address,
+            channel: self.channel,
+            frequency: self.frequency,
+        }
+    }
+
+    pub fn get_range(&self) -> (u16, u16) {
+        self.range
+    }
+
+    /// Computes the duty cycle (in ticks) of an angle.
+    pub fn to_duty(&self, angle: u16) -> u16 {
+        let (min, max) = self.degree_range;
+        let (low, high) = self.pwm_range;
+        let ratio = match max > min {
+            true => (angle.clamp(min, max) - min) as f32 / (max - min) as f32,
+            false => 0.0,
+        };
+        let ratio = match self.inverted {
+            true => 1.0 - ratio,
+            false => ratio,
+        };
+        let pulse = low as f32 + ratio * (high as f32 - low as f32);
+        (pulse * self.frequency as f32 * RESOLUTION as f32 / 1_000_000.0).round() as u16
+    }
+}
+
+#[typetag::serde]
+impl Device for PcaServo {}
+
+#[typetag::serde]
+impl Output for PcaServo {
+    fn animate<S: Into<State>>(&mut self, state: S, duration: u64, transition: Easing)
+    where
+        Self: Sized,
+    {
+        let track = Track::new(self.clone())
+            .with_keyframe(Keyframe::new(state, 0, duration).set_transition(transition));
+        let mut animation = Animation::from(Segment::default().with_track(track));
+        animation.play();
+        self.animation = Arc::new(Some(animation));
+    }
+
+    fn stop(&mut self) {
+        if let Some(animation) = Arc::get_mut(&mut self.animation).and_then(Option::as_mut) {
+            animation.stop();
+        }
+        self.animation = Arc::new(None);
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        let angle = (state.as_integer() as u16).clamp(self.range.0, self.range.1);
+        let duty = self.to_duty(angle);
+        match self.chip.as_mut() {
+            None => return Err(not_attached()),
+            Some(chip) => chip.set_duty(self.channel, duty)?,
+        };
+        *self.state.write() = angle;
+        Ok(State::Integer(angle as u64))
+    }
+
+    fn get_state(&self) -> State {
+        State::Integer(*self.state.read() as u64)
+    }
+
+    fn get_default(&self) -> State {
+        State::Integer(self.default as u64)
+    }
+
+    fn is_busy(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        let previous = previous.as_integer() as f32;
+        let target = target.as_integer() as f32;
+        State::Integer((previous + (target - previous) * progress).round() as u64)
+    }
+}
+
+impl Display for PcaServo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PcaServo (0x{:02X}:{}) [state={}, default={}, range={:?}]",
+            self.address,
+            self.channel,
+            self.state.read(),
+            self.default,
+            self.range
+        )
+    }
+}
+
+impl Debug for PcaServo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PcaServo")
+            .field("address", &self.address)
+            .field("channel", &self.channel)
+            .field("state", &self.state.read())
+            .field("default", &self.default)
+            .field("attached", &self.chip.is_some())
+            .finish()
+    }
+}
+
+/// A PWM output (ie: a LED or a motor driver) plugged on a channel of a PCA9685: its state is the duty cycle in
+/// percent.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PcaPwm {
+    /// The I2C address of the chip (default: 0x40).
+    #[serde(default = "default_address")]
+    address: u8,
+    /// The chip channel (0-15).
+    channel: u8,
+    /// The PWM frequency of the chip (in Hz, default: 50).
+    #[serde(default = "default_frequency")]
+    frequency: u16,
+    /// The current duty cycle (in percent).
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<u16>>,
+    /// The default duty cycle (in percent).
+    default: u16,
+
+    #[serde(skip)]
+    chip: Option<Pca9685>,
+    /// The animation currently played by the output (if any).
+    #[serde(skip)]
+    animation: Arc<Option<Animation>>,
+}
+
+impl PcaPwm {
+    /// Attaches the output to a board (given with its id): the output is set to its current value.
+    pub fn attach(mut self, board: &Board, bid: usize) -> Result<Self, Error> {
+        self.chip = Some(Pca9685::new(board, bid, self.address, self.frequency)?);
+        let state = *self.state.read();
+        self.set_state(State::Integer(state as u64))?;
+        Ok(self)
+    }
+
+    pub fn get_channel(&self) -> PcaChannel {
+        PcaChannel {
+            address: self.address,
+            channel: self.channel,
+            frequency: self.frequency,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Device for PcaPwm {}
+
+#[typetag::serde]
+impl Output for PcaPwm {
+    fn animate<S: Into<State>>(&mut self, state: S, duration: u64, transition: Easing)
+    where
+        Self: Sized,
+    {
+        let track = Track::new(self.clone())
+            .with_keyframe(Keyframe::new(state, 0, duration).set_transition(transition));
+        let mut animation = Animation::from(Segment::default().with_track(track));
+        animation.play();
+        self.animation = Arc::new(Some(animation));
+    }
+
+    fn stop(&mut self) {
+        if let Some(animation) = Arc::get_mut(&mut self.animation).and_then(Option::as_mut) {
+            animation.stop();
+        }
+        self.animation = Arc::new(None);
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        let value = match state {
+            State::Boolean(true) => 100,
+            State::Boolean(false) => 0,
+            state => state.as_integer().min(100) as u16,
+        };
+        let duty = (value as u32 * RESOLUTION / 100) as u16;
+        match self.chip.as_mut() {
+            None => return Err(not_attached()),
+            Some(chip) => chip.set_duty(self.channel, duty)?,
+        };
+        *self.state.write() = value;
+        Ok(State::Integer(value as u64))
+    }
+
+    fn get_state(&self) -> State {
+        State::Integer(*self.state.read() as u64)
+    }
+
+    fn get_default(&self) -> State {
+        State::Integer(self.default as u64)
+    }
+
+    fn is_busy(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        let previous = previous.as_integer() as f32;
+        let target = target.as_integer() as f32;
+        State::Integer((previous + (target - previous) * progress).round() as u64)
+    }
+}
+
+impl Display for PcaPwm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PcaPwm (0x{:02X}:{}) [state={}%, default={}%]",
+            self.address,
+            self.channel,
+            self.state.read(),
+            self.default
+        )
+    }
+}
+
+impl Debug for PcaPwm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PcaPwm")
+            .field("address", &self.address)
+            .field("channel", &self.channel)
+            .field("state", &self.state.read())
+            .field("default", &self.default)
+            .field("attached", &self.chip.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prescale() {
+        assert_eq!(prescale(50), 121);
+        assert_eq!(prescale(1526), 3);
+        assert_eq!(prescale(20), 255, "Clamped");
+    }
+
+    #[test]
+    fn test_channel_registers() {
+        assert_eq!(channel_registers(0, 0), [0x06, 0, 0, 0, 0x10]);
+        assert_eq!(channel_registers(1, 4096), [0x0A, 0, 0x10, 0, 0]);
+        assert_eq!(channel_registers(15, 307), [0x42, 0, 0, 0x33, 0x01]);
+    }
+
+    #[test]
+    fn test_servo_duty() {
+        let servo: PcaServo = serde_json::from_value(serde_json::json!({
+            "channel": 3,
+            "state": 90,
+            "default": 90,
+        }))
+        .unwrap();
+        assert_eq!(servo.address, DEFAULT_ADDRESS);
+        assert_eq!(servo.to_duty(0), 123);
+        assert_eq!(servo.to_duty(90), 307);
+        assert_eq!(servo.to_duty(180), 492);
+        assert_eq!(servo.to_duty(250), 492, "Clamped");
+    }
+}
@@ -0,0 +1,309 @@
+//! This file contains the `Color` used by the RGB devices, and the `RgbLed` device.
+//!
+//! A color state can be given as a hex string (ie: "#FF8800"), an `[r, g, b]` array or an `{r, g, b}` object; it
+//! is always exposed as a hex string. Animating a color interpolates each component, so that keyframes fade from
+//! one color to the next.
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use hermes_five::animation::{Animation, Keyframe, Segment, Track};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::protocols::{PinModeId, Protocol};
+use hermes_five::utils::{Easing, State};
+use hermes_five::Board;
+use parking_lot::RwLock;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A 24 bits RGB color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Parses a hex color (ie: "#FF8800", "ff8800" or "#F80").
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim().trim_start_matches('#');
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 => hex.to_string(),
+            _ => return None,
+        };
+        let value = u32::from_str_radix(&hex, 16).ok()?;
+        Some(Self::from_u32(value))
+    }
+
+    /// Builds a color from its 24 bits value (0xRRGGBB).
+    pub fn from_u32(value: u32) -> Self {
+        Self::new((value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+
+    /// Returns the 24 bits value of the color (0xRRGGBB).
+    pub fn to_u32(&self) -> u32 {
+        ((self.r as u32) << 16) | ((self.g as u32) << 8) | self.b as u32
+    }
+
+    /// Builds a fully saturated color from a hue (in degrees).
+    pub fn from_hue(hue: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+        let (r, g, b) = match hue as u8 {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        let channel = |value: f32| (value * 255.0).round() as u8;
+        Self::new(channel(r), channel(g), channel(b))
+    }
+
+    /// Interpolates linearly between two colors (progress from 0 to 1).
+    pub fn lerp(&self, target: &Color, progress: f32) -> Self {
+        let channel = |from: u8, to: u8| {
+            (from as f32 + (to as f32 - from as f32) * progress)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+        Self::new(
+            channel(self.r, target.r),
+            channel(self.g, target.g),
+            channel(self.b, target.b),
+        )
+    }
+
+    /// Scales the color intensity (level from 0 to 1).
+    pub fn scale(&self, level: f32) -> Self {
+        Self::BLACK.lerp(self, level)
+    }
+
+    /// Parses a color state (see the file header for the supported formats).
+    pub fn from_state(state: &State) -> Option<Self> {
+        serde_json::to_value(state)
+            .ok()
+            .and_then(|value| serde_json::from_value(value).ok())
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+impl From<Color> for State {
+    fn from(color: Color) -> Self {
+        State::String(color.to_string())
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Format {
+            Hex(String),
+            Array([u8; 3]),
+            Object { r: u8, g: u8, b: u8 },
+        }
+        match Format::deserialize(deserializer)? {
+            Format::Hex(hex) => Color::from_hex(&hex)
+                .ok_or_else(|| serde::de::Error::custom(format!("Invalid color: {}", hex))),
+            Format::Array([r, g, b]) | Format::Object { r, g, b } => Ok(Color::new(r, g, b)),
+        }
+    }
+}
+
+/// A RGB LED driven by three PWM pins.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RgbLed {
+    /// The (red, green, blue) pins.
+    pins: [u16; 3],
+    /// Whether the LED shares its anode (the pins then sink the current: levels are inverted).
+    #[serde(default)]
+    common_anode: bool,
+    /// The current color.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<Color>>,
+    /// The default color.
+    #[serde(default)]
+    default: Color,
+
+    #[serde(skip)]
+    protocol: Option<Box<dyn Protocol>>,
+    /// The animation currently played by the LED (if any).
+    #[serde(skip)]
+    animation: Arc<Option<Animation>>,
+}
+
+impl RgbLed {
+    /// Attaches the LED to a board: its pins are set to PWM mode and the LED set to its current color.
+    pub fn attach(mut self, board: &Board) -> Result<Self, Error> {
+        let mut protocol = board.get_protocol();
+        for pin in self.pins {
+            protocol.set_pin_mode(pin, PinModeId::PWM)?;
+        }
+        self.protocol = Some(protocol);
+        let color = *self.state.read();
+        self.set_state(color.into())?;
+        Ok(self)
+    }
+
+    pub fn get_pins(&self) -> [u16; 3] {
+        self.pins
+    }
+}
+
+#[typetag::serde]
+impl Device for RgbLed {}
+
+#[typetag::serde]
+impl Output for RgbLed {
+    fn animate<S: Into<State>>(&mut self, state: S, duration: u64, transition: Easing)
+    where
+        Self: Sized,
+    {
+        let track = Track::new(self.clone())
+            .with_keyframe(Keyframe::new(state, 0, duration).set_transition(transition));
+        let mut animation = Animation::from(Segment::default().with_track(track));
+        animation.play();
+        self.animation = Arc::new(Some(animation));
+    }
+
+    fn stop(&mut self) {
+        if let Some(animation) = Arc::get_mut(&mut self.animation).and_then(Option::as_mut) {
+            animation.stop();
+        }
+        self.animation = Arc::new(None);
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        let color = match state {
+            State::Boolean(true) => self.default,
+            State::Boolean(false) | State::Null => Color::BLACK,
+            state => Color::from_state(&state).ok_or_else(|| invalid_color(&state))?,
+        };
+        let protocol = match self.protocol.as_mut() {
+            None => return Err(not_attached("RGB LED")),
+            Some(protocol) => protocol,
+        };
+        for (pin, level) in self.pins.into_iter().zip([color.r, color.g, color.b]) {
+            let level = match self.common_anode {
+                true => 255 - level,
+                false => level,
+            };
+            protocol.analog_write(pin, level as u16)?;
+        }
+        *self.state.write() = color;
+        Ok(color.into())
+    }
+
+    fn get_state(&self) -> State {
+        (*self.state.read()).into()
+    }
+
+    fn get_default(&self) -> State {
+        self.default.into()
+    }
+
+    fn is_busy(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        match (Color::from_state(&previous), Color::from_state(&target)) {
+            (Some(previous), Some(target)) => previous.lerp(&target, progress).into(),
+            _ => target,
+        }
+    }
+}
+
+impl Display for RgbLed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "RgbLed (pins={:?}) [state={}, default={}]",
+            self.pins,
+            self.state.read(),
+            self.default
+        )
+    }
+}
+
+impl Debug for RgbLed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RgbLed")
+            .field("pins", &self.pins)
+            .field("common_anode", &self.common_anode)
+            .field("state", &self.state.read())
+            .field("default", &self.default)
+            .field("protocol", &"[Box<dyn Protocol>]")
+            .finish()
+    }
+}
+
+/// Builds the error of a state which is not a color.
+pub fn invalid_color(state: &State) -> Error {
+    hermes_five::errors::Unknown {
+        info: format!("Invalid color: {:?}", state),
+    }
+    .into()
+}
+
+/// Builds the error of a device used before being attached to a board.
+pub fn not_attached(device: &str) -> Error {
+    hermes_five::errors::Unknown {
+        info: format!("{} is not attached to a board", device),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        let orange = Color::new(255, 136, 0);
+        assert_eq!(Color::from_hex("#FF8800"), Some(orange));
+        assert_eq!(Color::from_hex("f80"), Some(orange));
+        assert_eq!(Color::from_hex("#FF88"), None);
+        assert_eq!(orange.to_string(), "#FF8800");
+        assert_eq!(Color::from_u32(orange.to_u32()), orange);
+
+        let color: Color = serde_json::from_value(json!([255, 136, 0])).unwrap();
+        assert_eq!(color, orange);
+        let color: Color = serde_json::from_value(json!({"r": 255, "g": 136, "b": 0})).unwrap();
+        assert_eq!(color, orange);
+        assert!(serde_json::from_value::<Color>(json!("orange")).is_err());
+    }
+
+    #[test]
+    fn test_color_transitions() {
+        let white = Color::new(255, 255, 255);
+        assert_eq!(Color::BLACK.lerp(&white, 0.5), Color::new(128, 128, 128));
+        assert_eq!(white.scale(0.0), Color::BLACK);
+        assert_eq!(Color::from_hue(0.0), Color::new(255, 0, 0));
+        assert_eq!(Color::from_hue(120.0), Color::new(0, 255, 0));
+        assert_eq!(Color::from_hue(600.0), Color::new(0, 0, 255));
+        assert_eq!(Color::from_hue(60.0), Color::new(255, 255, 0));
+    }
+}
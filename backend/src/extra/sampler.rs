@@ -0,0 +1,414 @@
+//! This file contains the `Sampler` device: it plays short sound effects (beeps, motor whirs, laughs, etc...).
+//!
+//! Unlike the `Mp3Player` (a single song at a time), the sampler preloads a bank of short clips in memory
+//! and triggers them by name: each trigger plays on a voice of its own so that sounds overlap (on top of
+//! the background music if any). When the maximum polyphony is reached, the oldest voice is stolen.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::utils::{Easing, State};
+use hermes_five::Board;
+use log::warn;
+use parking_lot::RwLock;
+use rodio::{Sink, Source};
+use serde::{Deserialize, Serialize};
+
+use crate::extra::audio::{decode, output};
+use crate::extra::common::{scale_discrete, unknown};
+
+/// A clip of the bank.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// The audio file path.
+    pub path: String,
+    /// The clip own volume in percent (default: 100%).
+    #[serde(default = "default_volume")]
+    pub volume: u8,
+}
+
+fn default_volume() -> u8 {
+    100
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct SamplerState {
+    /// The clips available, keyed by name.
+    bank: BTreeMap<String, Sample>,
+    /// The master volume in percent (default: 100%).
+    volume: u8,
+    /// The maximum number of clips played at once (default: 8).
+    polyphony: u8,
+}
+
+impl Default for SamplerState {
+    fn default() -> Self {
+        Self {
+            bank: BTreeMap::new(),
+            volume: 100,
+            polyphony: 8,
+        }
+    }
+}
+
+/// A clip decoded in memory: its samples are shared by all the voices playing it.
+#[derive(Clone)]
+struct Clip {
+    channels: u16,
+    sample_rate: u32,
+    samples: Arc<[f32]>,
+}
+
+impl Clip {
+    /// Decodes an audio file into memory.
+    fn load(path: &str) -> Result<Self> {
+        let decoder = decode(path)?;
+        let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+        Ok(Self {
+            channels,
+            sample_rate,
+            samples: decoder.convert_samples::<f32>().collect(),
+        })
+    }
+}
+
+/// The source of a voice: it reads the samples of its clip in place.
+struct ClipSource {
+    clip: Clip,
+    position: usize,
+}
+
+impl Iterator for ClipSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.clip.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.clip.samples.len().saturating_sub(self.position);
+        (remaining, Some(remaining))
+    }
+}
+
+impl Source for ClipSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.clip.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.clip.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = self.clip.samples.len() / self.clip.channels.max(1) as usize;
+        Some(Duration::from_secs_f64(
+            frames as f64 / self.clip.sample_rate.max(1) as f64,
+        ))
+    }
+}
+
+/// A clip being played.
+struct Voice {
+    name: String,
+    sink: Sink,
+    started_at: Instant,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sampler {
+    /// The current Sampler state.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<SamplerState>>,
+    /// The Sampler default value.
+    default: SamplerState,
+
+    /// The preloaded clips, keyed by name.
+    #[serde(skip)]
+    clips: Arc<RwLock<HashMap<String, Clip>>>,
+    /// The voices currently playing.
+    #[serde(skip)]
+    voices: Arc<RwLock<Vec<Voice>>>,
+}
+
+impl Sampler {
+    pub fn new(_board: &Board) -> Result<Self, Error> {
+        Ok(Self {
+            state: Arc::new(Default::default()),
+            default: Default::default(),
+            clips: Arc::new(RwLock::new(HashMap::new())),
+            voices: Arc::new(RwLock::new(vec![])),
+        })
+    }
+
+    /// Copies the settings (bank, volume, polyphony) of another sampler and preloads its bank.
+    pub fn with_settings_of(mut self, other: &Sampler) -> Self {
+        let mut state = other.state.read().clone();
+        let bank = std::mem::take(&mut state.bank);
+        *self.state.write() = state;
+        self.default = other.default.clone();
+        self.set_bank(bank);
+        self
+    }
+
+    /// Replaces the bank of clips: new or modified clips are (re)loaded in memory.
+    /// Clips are decoded in the background (decoding takes a while): they cannot be triggered until loaded, nor
+    /// if they cannot be loaded at all (they are kept in the bank though).
+    pub fn set_bank(&mut self, bank: BTreeMap<String, Sample>) {
+        let previous = std::mem::replace(&mut self.state.write().bank, bank.clone());
+        let missing: Vec<(String, String)> = {
+            let mut clips = self.clips.write();
+            clips.retain(|name, _| {
+                bank.get(name).map(|sample| &sample.path)
+                    == previous.get(name).map(|sample| &sample.path)
+            });
+            bank.iter()
+                .filter(|(name, _)| !clips.contains_key(*name))
+                .map(|(name, sample)| (name.clone(), sample.path.clone()))
+                .collect()
+        };
+        if missing.is_empty() {
+            return;
+        }
+
+        let (state, clips) = (self.state.clone(), self.clips.clone());
+        tokio::task::spawn_blocking(move || {
+            for (name, path) in missing {
+                match Clip::load(&path) {
+                    Err(err) => warn!("Sampler failed to load clip [{}]: {}", name, err),
+                    Ok(clip) => {
+                        // The bank may have changed meanwhile.
+                        let mut clips = clips.write();
+                        let current =
+                            state.read().bank.get(&name).map(|sample| &sample.path) == Some(&path);
+                        if current {
+                            clips.insert(name, clip);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Triggers a clip by name, with an optional volume (in percent) for this voice only.
+    pub fn trigger(&mut self, name: &str, volume: Option<u8>) -> Result<(), Error> {
+        let clip = match self.clips.read().get(name) {
+            None => return Err(unknown(format!("Unknown (or not loaded) clip: {}", name))),
+            Some(clip) => clip.clone(),
+        };
+        let (master, own, polyphony) = {
+            let state = self.state.read();
+            let own = state
+                .bank
+                .get(name)
+                .map(|sample| sample.volume)
+                .unwrap_or(100);
+            (state.volume, own, state.polyphony.max(1) as usize)
+        };
+
+        let mut voices = self.voices.write();
+        voices.retain(|voice| !voice.sink.empty());
+        // Steal the oldest voices when the polyphony is reached.
+        while voices.len() >= polyphony {
+            let oldest = voices
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, voice)| voice.started_at)
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+            voices.remove(oldest).sink.stop();
+        }
+
+        let sink = Sink::try_new(output().map_err(unknown)?).map_err(unknown)?;
+        let volume = volume.unwrap_or(100) as f32 / 100.0;
+        sink.set_volume(master as f32 / 100.0 * own as f32 / 100.0 * volume);
+        sink.append(ClipSource { clip, position: 0 });
+        voices.push(Voice {
+            name: name.to_string(),
+            sink,
+            started_at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Stops all voices playing the given clip.
+    pub fn release(&mut self, name: &str) {
+        let mut voices = self.voices.write();
+        voices.retain(|voice| match voice.name == name {
+            true => {
+                voice.sink.stop();
+                false
+            }
+            false => !voice.sink.empty(),
+        });
+    }
+
+    /// (private)
+    /// Triggers the clip(s) described by a state: a name, a list of names or an object {name, volume}.
+    fn trigger_state(&mut self, state: &State) -> Result<(), Error> {
+        match state {
+            State::String(name) => self.trigger(name, None),
+            State::Array(names) => {
+                for name in names {
+                    self.trigger_state(name)?;
+                }
+                Ok(())
+            }
+            State::Object(voice) => match voice.get("name") {
+                None => Ok(()),
+                Some(name) => self.trigger(
+                    &name.as_string(),
+                    voice
+                        .get("volume")
+                        .map(|volume| volume.as_integer().min(100) as u8),
+                ),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses a bank out of a state: {name: path} or {name: {path, volume}}.
+fn parse_bank(state: &State) -> BTreeMap<String, Sample> {
+    let mut bank = BTreeMap::new();
+    if let State::Object(entries) = state {
+        for (name, entry) in entries {
+            let sample = match entry {
+                State::String(path) => Sample {
+                    path: path.clone(),
+                    volume: default_volume(),
+                },
+                State::Object(sample) => Sample {
+                    path: sample
+                        .get("path")
+                        .map(|path| path.as_string())
+                        .unwrap_or_default(),
+                    volume: sample
+                        .get("volume")
+                        .map(|volume| volume.as_integer().min(100) as u8)
+                        .unwrap_or(default_volume()),
+                },
+                _ => continue,
+            };
+            bank.insert(name.clone(), sample);
+        }
+    }
+    bank
+}
+
+#[typetag::serde]
+impl Device for Sampler {}
+
+#[typetag::serde]
+impl Output for Sampler {
+    fn animate<S: Into<State>>(&mut self, state: S, _duration: u64, _transition: Easing)
+    where
+        Self: Sized,
+    {
+        if let Err(err) = self.set_state(state.into()) {
+            warn!("Sampler failed to play: {}", err);
+        }
+    }
+
+    /// Stops all voices.
+    fn stop(&mut self) {
+        for voice in self.voices.write().drain(..) {
+            voice.sink.stop();
+        }
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        match state.clone() {
+            State::Integer(0) | State::Signed(0) | State::Boolean(false) => self.stop(),
+            State::Object(state) => {
+                if let Some(volume) = state.get("volume") {
+                    self.state.write().volume = volume.as_integer().min(100) as u8;
+                }
+                if let Some(polyphony) = state.get("polyphony") {
+                    self.state.write().polyphony = polyphony.as_integer().clamp(1, 32) as u8;
+                }
+                if let Some(bank) = state.get("bank") {
+                    self.set_bank(parse_bank(bank));
+                }
+                if let Some(State::String(name)) = state.get("stop") {
+                    self.release(name);
+                }
+                if let Some(play) = state.get("play") {
+                    self.trigger_state(play)?;
+                }
+            }
+            state => self.trigger_state(&state)?,
+        };
+        let new_state = self.state.read().clone();
+        Ok(State::into_state(new_state))
+    }
+
+    fn get_state(&self) -> State {
+        State::into_state(self.state.read().clone())
+    }
+
+    fn get_default(&self) -> State {
+        State::into_state(self.default.clone())
+    }
+
+    fn is_busy(&self) -> bool {
+        self.voices.read().iter().any(|voice| !voice.sink.empty())
+    }
+
+    fn scale_state(&mut self, _previous: State, target: State, progress: f32) -> State {
+        scale_discrete(target, progress)
+    }
+}
+
+impl Display for Sampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sampler [clips={}]", self.state.read().bank.len())
+    }
+}
+
+impl Debug for Sampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sampler")
+            .field("state", &self.state)
+            .field("default", &self.default)
+            .field("voices", &self.voices.read().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_bank() {
+        let state = State::into_state(json!({
+            "beep": "beep.wav",
+            "laugh": {"path": "laugh.ogg", "volume": 40},
+            "invalid": 3,
+        }));
+        let bank = parse_bank(&state);
+        assert_eq!(bank.len(), 2);
+        assert_eq!(bank["beep"].volume, 100);
+        assert_eq!(
+            bank["laugh"],
+            Sample {
+                path: String::from("laugh.ogg"),
+                volume: 40
+            }
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! This file contains the `CalibratedServo`: a hermes-five `Servo` driven in logical angles.
+//!
+//! Physical servos rarely match their nominal angles: a `Calibration` maps the logical angles used by the
+//! postures and the animations onto the angles actually commanded to the servo. It is made of a piecewise
+//! linear mapping (built from measured points) and a trim offset. The mapping is applied on each state change,
+//! including the animation frames, and the states exposed (serialized or returned) stay in logical units.
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use hermes_five::animation::{Animation, Keyframe, Segment, Track};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::utils::{Easing, State};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// A measured point: the angle to command for the servo to physically reach the logical angle.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationPoint {
+    pub logical: f32,
+    pub commanded: f32,
+}
+
+/// The calibration of a servo: logical angles are mapped through the points then shifted by the trim.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// The offset (in degrees) added to all commanded angles.
+    pub trim: f32,
+    /// The measured points (sorted by logical angle): less than two points means no mapping.
+    pub points: Vec<CalibrationPoint>,
+}
+
+impl Calibration {
+    /// Adds a measured point (replacing any point for the same logical angle).
+    pub fn set_point(&mut self, logical: f32, commanded: f32) {
+        self.points.retain(|point| point.logical != logical);
+        self.points.push(CalibrationPoint { logical, commanded });
+        self.points.sort_by(|a, b| a.logical.total_cmp(&b.logical));
+    }
+
+    /// Checks the mapping can be inverted: commanded angles must be strictly monotonic.
+    pub fn validate(&self) -> Result<()> {
+        let increasing = self
+            .points
+            .windows(2)
+            .all(|pair| pair[0].commanded < pair[1].commanded);
+        let decreasing = self
+            .points
+            .windows(2)
+            .all(|pair| pair[0].commanded > pair[1].commanded);
+        if !increasing && !decreasing {
+            bail!("Calibration points must be strictly increasing or decreasing");
+        }
+        Ok(())
+    }
+
+    /// Converts a logical angle into the angle to command.
+    pub fn to_commanded(&self, logical: f32) -> f32 {
+        let points: Vec<(f32, f32)> = self
+            .points
+            .iter()
+            .map(|point| (point.logical, point.commanded))
+            .collect();
+        interpolate(&points, logical) + self.trim
+    }
+
+    /// Converts a commanded angle back into its logical angle.
+    pub fn to_logical(&self, commanded: f32) -> f32 {
+        let mut points: Vec<(f32, f32)> = self
+            .points
+            .iter()
+            .map(|point| (point.commanded, point.logical))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        interpolate(&points, commanded - self.trim)
+    }
+
+    /// (private)
+    /// Maps a state through the given conversion (non numeric states are left untouched).
+    fn map_state(&self, state: State, convert: fn(&Self, f32) -> f32) -> State {
+        let angle = match state {
+            State::Integer(angle) => angle as f32,
+            State::Signed(angle) => angle as f32,
+            State::Float(angle) => angle as f32,
+            state => return state,
+        };
+        State::Integer(convert(self, angle).round().max(0.0) as u64)
+    }
+}
+
+/// (private)
+/// Interpolates linearly through sorted (x, y) points, extrapolating from the edge segments.
+fn interpolate(points: &[(f32, f32)], x: f32) -> f32 {
+    if points.len() < 2 {
+        return x;
+    }
+    let index = points
+        .iter()
+        .position(|point| point.0 > x)
+        .unwrap_or(points.len())
+        .clamp(1, points.len() - 1);
+    let ((x0, y0), (x1, y1)) = (points[index - 1], points[index]);
+    match x1 == x0 {
+        true => y0,
+        false => y0 + (x - x0) * (y1 - y0) / (x1 - x0),
+    }
+}
+
+#[derive(Clone)]
+pub struct CalibratedServo {
+    /// The servo: driven in commanded angles.
+    servo: hermes_five::devices::Servo,
+    calibration: Calibration,
+    /// The animation currently played by the servo (if any).
+    animation: Arc<Option<Animation>>,
+}
+
+impl CalibratedServo {
+    pub fn new(servo: hermes_five::devices::Servo, calibration: Calibration) -> Self {
+        Self {
+            servo,
+            calibration,
+            animation: Arc::new(None),
+        }
+    }
+
+    pub fn get_calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Replaces the calibration: the servo keeps its logical position (moving to its new commanded angle).
+    pub fn set_calibration(&mut self, calibration: Calibration) -> Result<(), Error> {
+        let state = self.get_state();
+        self.calibration = calibration;
+        self.set_state(state)?;
+        Ok(())
+    }
+
+    /// Moves the servo to a commanded angle, bypassing the calibration (ie: to measure points).
+    pub fn set_commanded(&mut self, angle: u16) -> Result<State, Error> {
+        self.servo.set_state(State::Integer(angle as u64))
+    }
+}
+
+impl Deref for CalibratedServo {
+    type Target = hermes_five::devices::Servo;
+
+    fn deref(&self) -> &Self::Target {
+        &self.servo
+    }
+}
+
+impl DerefMut for CalibratedServo {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.servo
+    }
+}
+
+// The servo is (de)serialized flat, with its calibration and its state and default in logical units.
+impl Serialize for CalibratedServo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.servo).map_err(S::Error::custom)?;
+        if let Value::Object(fields) = &mut value {
+            let state = serde_json::to_value(self.get_state()).map_err(S::Error::custom)?;
+            let default = serde_json::to_value(self.get_default()).map_err(S::Error::custom)?;
+            let calibration = serde_json::to_value(&self.calibration).map_err(S::Error::custom)?;
+            fields.insert(String::from("state"), state);
+            fields.insert(String::from("default"), default);
+            fields.insert(String::from("calibration"), calibration);
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CalibratedServo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let calibration = match value
+            .as_object_mut()
+            .and_then(|fields| fields.remove("calibration"))
+        {
+            None => Calibration::default(),
+            Some(calibration) => serde_json::from_value(calibration).map_err(D::Error::custom)?,
+        };
+        for field in ["state", "default"] {
+            if let Some(angle) = value.get(field).and_then(Value::as_f64) {
+                let angle = calibration.to_commanded(angle as f32).round().max(0.0);
+                value[field] = Value::from(angle as u64);
+            }
+        }
+        let servo = serde_json::from_value(value).map_err(D::Error::custom)?;
+        Ok(Self::new(servo, calibration))
+    }
+}
+
+#[typetag::serde]
+impl Device for CalibratedServo {}
+
+#[typetag::serde]
+impl Output for CalibratedServo {
+    /// Animates the servo in logical units: each frame goes through the calibration.
+    fn animate<S: Into<State>>(&mut self, state: S, duration: u64, transition: Easing)
+    where
+        Self: Sized,
+    {
+        let track = Track::new(self.clone())
+            .with_keyframe(Keyframe::new(state, 0, duration).set_transition(transition));
+        let mut animation = Animation::from(Segment::default().with_track(track));
+        animation.play();
+        self.animation = Arc::new(Some(animation));
+    }
+
+    fn stop(&mut self) {
+        if let Some(animation) = Arc::get_mut(&mut self.animation).and_then(Option::as_mut) {
+            animation.stop();
+        }
+        self.animation = Arc::new(None);
+        self.servo.stop();
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        let commanded = self
+            .calibration
+            .map_state(state.clone(), Calibration::to_commanded);
+        self.servo.set_state(commanded)?;
+        Ok(state)
+    }
+
+    fn get_state(&self) -> State {
+        self.calibration
+            .map_state(self.servo.get_state(), Calibration::to_logical)
+    }
+
+    fn get_default(&self) -> State {
+        self.calibration
+            .map_state(self.servo.get_default(), Calibration::to_logical)
+    }
+
+    fn is_busy(&self) -> bool {
+        self.animation.is_some() || self.servo.is_busy()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        self.servo.scale_state(previous, target, progress)
+    }
+}
+
+impl Display for CalibratedServo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [trim={}, points={}]",
+            self.servo,
+            self.calibration.trim,
+            self.calibration.points.len()
+        )
+    }
+}
+
+impl Debug for CalibratedServo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CalibratedServo")
+            .field("servo", &self.servo)
+            .field("calibration", &self.calibration)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration() {
+        let mut calibration = Calibration::default();
+        assert_eq!(calibration.to_commanded(42.0), 42.0, "Identity");
+
+        calibration.trim = 3.0;
+        calibration.set_point(180.0, 170.0);
+        calibration.set_point(0.0, 10.0);
+        calibration.set_point(90.0, 95.0);
+        assert!(calibration.validate().is_ok());
+        assert_eq!(calibration.points[1].logical, 90.0, "Sorted points");
+        assert_eq!(calibration.to_commanded(90.0), 98.0);
+        assert_eq!(calibration.to_commanded(45.0), 55.5);
+        assert_eq!(calibration.to_commanded(270.0), 248.0, "Extrapolated");
+        assert_eq!(calibration.to_logical(98.0), 90.0);
+        assert_eq!(calibration.to_logical(55.5), 45.0);
+
+        calibration.set_point(90.0, 5.0);
+        assert!(calibration.validate().is_err(), "Not monotonic");
+    }
+
+    #[test]
+    fn test_inverted_calibration() {
+        let mut calibration = Calibration::default();
+        calibration.set_point(0.0, 180.0);
+        calibration.set_point(180.0, 0.0);
+        assert!(calibration.validate().is_ok());
+        assert_eq!(calibration.to_commanded(30.0), 150.0);
+        assert_eq!(calibration.to_logical(150.0), 30.0);
+    }
+}
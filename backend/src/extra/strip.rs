@@ -0,0 +1,462 @@
+//! This file contains the `PixelStrip` device: an addressable RGB LED strip (WS2812 / NeoPixel).
+//!
+//! The strip is driven through the Firmata pixel extension (sysex `0x51`, as defined by the node-pixel firmware):
+//! the board is told the pin and the length of the strip once, then each change sends the modified pixels before
+//! latching them all at once.
+//!
+//! The strip state is the color of each pixel. It can be set as:
+//! - a single color (see [`Color`]): applied to the whole strip,
+//! - an array of colors: one per pixel (the missing ones are turned off),
+//! - an effect object (ie: `{"effect": "rainbow", "cycles": 2}`, see [`Effect`]).
+//!
+//! Effects are rendered by `scale_state`: an animation keyframe targeting an effect plays it over the keyframe
+//! duration, starting from the pixels reached by the previous keyframe.
+use std::f32::consts::PI;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use hermes_five::animation::{Animation, Keyframe, Segment, Track};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::protocols::Protocol;
+use hermes_five::utils::{Easing, State};
+use hermes_five::Board;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::extra::rgb::{invalid_color, not_attached, Color};
+
+// Firmata pixel extension messages.
+const START_SYSEX: u8 = 0xF0;
+const END_SYSEX: u8 = 0xF7;
+const PIXEL_COMMAND: u8 = 0x51;
+const PIXEL_CONFIG: u8 = 0x01;
+const PIXEL_SHOW: u8 = 0x02;
+const PIXEL_SET_PIXEL: u8 = 0x03;
+const PIXEL_SET_STRIP: u8 = 0x04;
+
+/// The highest data pin of a strip: the config message holds the pin on 5 bits.
+pub const MAX_PIN: u16 = 31;
+
+/// The order in which the strip expects the color components.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ColorOrder {
+    #[default]
+    Grb,
+    Rgb,
+    Brg,
+}
+
+/// A built-in strip effect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "lowercase")]
+pub enum Effect {
+    /// Fades all pixels to a color.
+    Fade { color: Color },
+    /// Runs a lit segment along the strip (over the previous pixels).
+    Chase {
+        color: Color,
+        #[serde(default = "default_width")]
+        width: u16,
+        #[serde(default = "default_cycles")]
+        cycles: u16,
+    },
+    /// Scrolls a rainbow along the strip.
+    Rainbow {
+        #[serde(default = "default_cycles")]
+        cycles: u16,
+    },
+    /// Pulses all pixels with a color.
+    Breathe {
+        color: Color,
+        #[serde(default = "default_cycles")]
+        cycles: u16,
+    },
+}
+
+/// (private)
+fn default_width() -> u16 {
+    3
+}
+
+/// (private)
+fn default_cycles() -> u16 {
+    1
+}
+
+impl Effect {
+    /// Renders the effect at the given progress (from 0 to 1), starting from the given pixels.
+    pub fn render(&self, pixels: &[Color], progress: f32) -> Vec<Color> {
+        let length = pixels.len();
+        match self {
+            Effect::Fade { color } => pixels
+                .iter()
+                .map(|pixel| pixel.lerp(color, progress))
+                .collect(),
+            Effect::Chase {
+                color,
+                width,
+                cycles,
+            } => {
+                let head = (progress * *cycles as f32 * length as f32) as usize;
+                pixels
+                    .iter()
+                    .enumerate()
+                    .map(|(index, pixel)| {
+                        let distance = (head + length - index) % length;
+                        match progress < 1.0 && distance < *width as usize {
+                            true => *color,
+                            false => *pixel,
+                        }
+                    })
+                    .collect()
+            }
+            Effect::Rainbow { cycles } => (0..length)
+                .map(|index| {
+                    let offset = index as f32 / length as f32 + progress * *cycles as f32;
+                    Color::from_hue(offset * 360.0)
+                })
+                .collect(),
+            Effect::Breathe { color, cycles } => {
+                let level = (1.0 - (2.0 * PI * progress * *cycles as f32).cos()) / 2.0;
+                vec![color.scale(level); length]
+            }
+        }
+    }
+}
+
+/// A strip state, as given to `set_state`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum StripState {
+    Color(Color),
+    Pixels(Vec<Color>),
+    Effect(Effect),
+}
+
+impl StripState {
+    /// (private)
+    fn from_state(state: &State) -> Option<Self> {
+        serde_json::to_value(state)
+            .ok()
+            .and_then(|value| serde_json::from_value(value).ok())
+    }
+
+    /// (private)
+    /// Computes the pixels of a strip of the given length, starting from the given pixels (effects are rendered
+    /// at their end).
+    fn to_pixels(&self, current: &[Color], length: usize) -> Vec<Color> {
+        let mut pixels = match self {
+            StripState::Color(color) => vec![*color; length],
+            StripState::Pixels(pixels) => pixels.clone(),
+            StripState::Effect(effect) => effect.render(current, 1.0),
+        };
+        pixels.resize(length, Color::BLACK);
+        pixels
+    }
+}
+
+/// An addressable RGB LED strip.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PixelStrip {
+    /// The data pin of the strip.
+    pin: u16,
+    /// The number of pixels.
+    length: u16,
+    #[serde(default)]
+    color_order: ColorOrder,
+    /// The current pixels.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<Vec<Color>>>,
+    /// The default color (of all pixels).
+    #[serde(default)]
+    default: Color,
+
+    #[serde(skip)]
+    protocol: Option<Box<dyn Protocol>>,
+    /// The pixels last sent to the board: only the changed ones are sent.
+    #[serde(skip)]
+    sent: Arc<RwLock<Vec<Color>>>,
+    /// The animation currently played by the strip (if any).
+    #[serde(skip)]
+    animation: Arc<Option<Animation>>,
+}
+
+impl PixelStrip {
+    /// Attaches the strip to a board: the board is configured for the strip, which is set to its current pixels.
+    pub fn attach(mut self, board: &Board) -> Result<Self, Error> {
+        self.check_pin()?;
+        let mut protocol = board.get_protocol();
+        protocol.write(&config_message(self.pin, self.length, self.color_order))?;
+        self.protocol = Some(protocol);
+        self.sent = Arc::new(RwLock::new(vec![]));
+        let pixels = self.state.read().clone();
+        self.set_state(State::into_state(pixels))?;
+        Ok(self)
+    }
+
+    pub fn get_pin(&self) -> u16 {
+        self.pin
+    }
+
+    /// Checks the data pin can be configured on the board (see [`MAX_PIN`]).
+    pub fn check_pin(&self) -> Result<(), Error> {
+        match self.pin > MAX_PIN {
+            true => Err(hermes_five::errors::Unknown {
+                info: format!("Strip pin {} is out of range (0-{})", self.pin, MAX_PIN),
+            }
+            .into()),
+            false => Ok(()),
+        }
+    }
+
+    /// (private)
+    /// Sends the changed pixels to the board, then latches them.
+    fn show(&mut self, pixels: &[Color]) -> Result<(), Error> {
+        let protocol = match self.protocol.as_mut() {
+            None => return Err(not_attached("Pixel strip")),
+            Some(protocol) => protocol,
+        };
+        let mut sent = self.sent.write();
+        if sent.as_slice() == pixels {
+            return Ok(());
+        }
+        match pixels.first() {
+            Some(first) if pixels.iter().all(|pixel| pixel == first) => {
+                protocol.write(&strip_message(first))?;
+            }
+            _ => {
+                for (index, pixel) in pixels.iter().enumerate() {
+                    if sent.get(index) != Some(pixel) {
+                        protocol.write(&pixel_message(index as u16, pixel))?;
+                    }
+                }
+            }
+        }
+        protocol.write(&[START_SYSEX, PIXEL_COMMAND, PIXEL_SHOW, END_SYSEX])?;
+        *sent = pixels.to_vec();
+        Ok(())
+    }
+}
+
+/// Builds the message configuring a strip on the board.
+pub fn config_message(pin: u16, length: u16, order: ColorOrder) -> Vec<u8> {
+    vec![
+        START_SYSEX,
+        PIXEL_COMMAND,
+        PIXEL_CONFIG,
+        ((order as u8) << 5) | (pin as u8 & 0x1F),
+        (length & 0x7F) as u8,
+        ((length >> 7) & 0x7F) as u8,
+        END_SYSEX,
+    ]
+}
+
+/// Builds the message setting the color of a pixel (not shown until latched).
+pub fn pixel_message(index: u16, color: &Color) -> Vec<u8> {
+    let mut message = vec![
+        START_SYSEX,
+        PIXEL_COMMAND,
+        PIXEL_SET_PIXEL,
+        (index & 0x7F) as u8,
+        ((index >> 7) & 0x7F) as u8,
+    ];
+    message.extend(encode_color(color));
+    message.push(END_SYSEX);
+    message
+}
+
+/// Builds the message setting the color of all pixels (not shown until latched).
+pub fn strip_message(color: &Color) -> Vec<u8> {
+    let mut message = vec![START_SYSEX, PIXEL_COMMAND, PIXEL_SET_STRIP];
+    message.extend(encode_color(color));
+    message.push(END_SYSEX);
+    message
+}
+
+/// (private)
+/// Encodes a 24 bits color into four 7-bit bytes.
+fn encode_color(color: &Color) -> [u8; 4] {
+    let value = color.to_u32();
+    [0, 7, 14, 21].map(|shift| ((value >> shift) & 0x7F) as u8)
+}
+
+#[typetag::serde]
+impl Device for PixelStrip {}
+
+#[typetag::serde]
+impl Output for PixelStrip {
+    fn animate<S: Into<State>>(&mut self, state: S, duration: u64, transition: Easing)
+    where
+        Self: Sized,
+    {
+        let track = Track::new(self.clone())
+            .with_keyframe(Keyframe::new(state, 0, duration).set_transition(transition));
+        let mut animation = Animation::from(Segment::default().with_track(track));
+        animation.play();
+        self.animation = Arc::new(Some(animation));
+    }
+
+    fn stop(&mut self) {
+        if let Some(animation) = Arc::get_mut(&mut self.animation).and_then(Option::as_mut) {
+            animation.stop();
+        }
+        self.animation = Arc::new(None);
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        let length = self.length as usize;
+        let pixels = match state {
+            State::Boolean(true) => vec![self.default; length],
+            State::Boolean(false) | State::Null => vec![Color::BLACK; length],
+            state => StripState::from_state(&state)
+                .ok_or_else(|| invalid_color(&state))?
+                .to_pixels(&self.state.read(), length),
+        };
+        self.show(&pixels)?;
+        *self.state.write() = pixels.clone();
+        Ok(State::into_state(pixels))
+    }
+
+    fn get_state(&self) -> State {
+        State::into_state(self.state.read().clone())
+    }
+
+    fn get_default(&self) -> State {
+        self.default.into()
+    }
+
+    fn is_busy(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        let length = self.length as usize;
+        let current = self.state.read().clone();
+        let mut previous = match StripState::from_state(&previous) {
+            None => current,
+            Some(previous) => previous.to_pixels(&current, length),
+        };
+        previous.resize(length, Color::BLACK);
+        let pixels = match StripState::from_state(&target) {
+            None => return target,
+            Some(StripState::Effect(effect)) => effect.render(&previous, progress),
+            Some(target) => previous
+                .iter()
+                .zip(target.to_pixels(&previous, length))
+                .map(|(from, to)| from.lerp(&to, progress))
+                .collect(),
+        };
+        State::into_state(pixels)
+    }
+}
+
+impl Display for PixelStrip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PixelStrip (pin={}) [length={}, default={}]",
+            self.pin, self.length, self.default
+        )
+    }
+}
+
+impl Debug for PixelStrip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PixelStrip")
+            .field("pin", &self.pin)
+            .field("length", &self.length)
+            .field("color_order", &self.color_order)
+            .field("default", &self.default)
+            .field("protocol", &"[Box<dyn Protocol>]")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            config_message(6, 144, ColorOrder::Grb),
+            vec![0xF0, 0x51, 0x01, 0x06, 0x10, 0x01, 0xF7]
+        );
+        assert_eq!(
+            pixel_message(130, &Color::new(0xFF, 0x88, 0x00)),
+            vec![0xF0, 0x51, 0x03, 0x02, 0x01, 0x00, 0x10, 0x7E, 0x07, 0xF7]
+        );
+        assert_eq!(
+            strip_message(&Color::BLACK),
+            vec![0xF0, 0x51, 0x04, 0, 0, 0, 0, 0xF7]
+        );
+    }
+
+    #[test]
+    fn test_check_pin() {
+        let strip: PixelStrip =
+            serde_json::from_value(json!({"pin": 31, "length": 8, "state": []})).unwrap();
+        assert!(strip.check_pin().is_ok());
+        let strip: PixelStrip =
+            serde_json::from_value(json!({"pin": 32, "length": 8, "state": []})).unwrap();
+        assert!(strip.check_pin().is_err());
+    }
+
+    #[test]
+    fn test_strip_state() {
+        let red = Color::new(255, 0, 0);
+        let state: StripState = serde_json::from_value(json!("#FF0000")).unwrap();
+        assert_eq!(state.to_pixels(&[], 3), vec![red; 3]);
+        let state: StripState = serde_json::from_value(json!(["#FF0000"])).unwrap();
+        assert_eq!(state.to_pixels(&[], 2), vec![red, Color::BLACK]);
+        let state: StripState =
+            serde_json::from_value(json!({"effect": "chase", "color": "#FF0000"})).unwrap();
+        assert_eq!(
+            state,
+            StripState::Effect(Effect::Chase {
+                color: red,
+                width: 3,
+                cycles: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_effects() {
+        let red = Color::new(255, 0, 0);
+        let pixels = vec![Color::BLACK; 10];
+
+        let fade = Effect::Fade { color: red };
+        assert_eq!(fade.render(&pixels, 0.0), pixels);
+        assert_eq!(fade.render(&pixels, 1.0), vec![red; 10]);
+
+        let chase = Effect::Chase {
+            color: red,
+            width: 2,
+            cycles: 1,
+        };
+        let frame = chase.render(&pixels, 0.5);
+        let lit: Vec<usize> = (0..10).filter(|index| frame[*index] == red).collect();
+        assert_eq!(lit, vec![4, 5]);
+        assert_eq!(
+            chase.render(&pixels, 1.0),
+            pixels,
+            "Chase ends on the background"
+        );
+
+        let rainbow = Effect::Rainbow { cycles: 1 };
+        assert_eq!(rainbow.render(&pixels, 0.0)[0], red);
+        assert_eq!(rainbow.render(&pixels, 1.0), rainbow.render(&pixels, 0.0));
+
+        let breathe = Effect::Breathe {
+            color: red,
+            cycles: 1,
+        };
+        assert_eq!(breathe.render(&pixels, 0.0), pixels);
+        assert_eq!(breathe.render(&pixels, 0.5), vec![red; 10]);
+    }
+}
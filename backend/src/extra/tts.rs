@@ -0,0 +1,408 @@
+//! This file contains the `TextToSpeech` device: it speaks the text it is given through the shared audio output.
+//!
+//! The speech is synthesized by a local (offline) engine: an `espeak-ng` process is spawned for each
+//! sentence, the text is sent through its standard input and the WAV data is read from its standard output.
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use hermes_five::devices::{Device, Output};
+use hermes_five::errors::Error;
+use hermes_five::utils::events::{EventHandler, EventManager};
+use hermes_five::utils::{Easing, State};
+use hermes_five::{pause, Board};
+use log::warn;
+use parking_lot::RwLock;
+use rodio::{Decoder, Sink};
+use serde::{Deserialize, Serialize};
+
+use crate::extra::audio::output;
+use crate::extra::common::scale_discrete;
+
+/// The speech engine executable.
+const ENGINE: &str = "espeak-ng";
+
+/// The interval (in ms) between two checks of the end of the speech.
+const MONITOR_INTERVAL: u64 = 50;
+
+/// Lists all events a TextToSpeech device can emit/listen.
+pub enum TextToSpeechEvent {
+    /// Triggered when the speech starts (with the spoken text).
+    OnStart,
+    /// Triggered when the speech ends or is stopped (with the spoken text).
+    OnEnd,
+}
+
+/// Convert events to string to facilitate usage with [`EventManager`].
+impl Into<String> for TextToSpeechEvent {
+    fn into(self) -> String {
+        let event = match self {
+            TextToSpeechEvent::OnStart => "start",
+            TextToSpeechEvent::OnEnd => "end",
+        };
+        event.into()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct TextToSpeechState {
+    /// The last spoken text.
+    text: String,
+    /// The engine voice (default: "en").
+    voice: String,
+    /// The speed in words per minute (default: 175).
+    rate: u16,
+    /// The pitch from 0 to 99 (default: 50).
+    pitch: u8,
+    /// The volume in percent (default: 100%).
+    volume: u8,
+    /// Determines whether the device is currently speaking.
+    speaking: bool,
+}
+
+impl Default for TextToSpeechState {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            voice: String::from("en"),
+            rate: 175,
+            pitch: 50,
+            volume: 100,
+            speaking: false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TextToSpeech {
+    /// The current TextToSpeech state.
+    #[serde(with = "hermes_five::devices::arc_rwlock_serde")]
+    state: Arc<RwLock<TextToSpeechState>>,
+    /// The TextToSpeech default value.
+    default: TextToSpeechState,
+
+    /// The speech controller.
+    #[serde(skip)]
+    control: Arc<RwLock<Option<Sink>>>,
+    /// A counter incremented each time a sentence is spoken: used to retire the previous one.
+    #[serde(skip)]
+    session: Arc<AtomicUsize>,
+    /// The event manager for the device.
+    #[serde(skip)]
+    events: EventManager,
+}
+
+impl TextToSpeech {
+    pub fn new(_board: &Board) -> Result<Self, Error> {
+        Ok(Self {
+            state: Arc::new(Default::default()),
+            default: Default::default(),
+            control: Arc::new(RwLock::new(None)),
+            session: Arc::new(AtomicUsize::new(0)),
+            events: Default::default(),
+        })
+    }
+
+    /// Copies the settings (voice, rate, etc...) of another device (but not its status).
+    pub fn with_settings_of(mut self, other: &TextToSpeech) -> Self {
+        let mut state = other.state.read().clone();
+        state.speaking = false;
+        *self.state.write() = state;
+        self.default = other.default.clone();
+        self
+    }
+
+    /// Speaks the given text (the current speech, if any, is interrupted).
+    ///
+    /// The synthesis runs in the background: the `OnStart` event is emitted once the speech actually starts.
+    pub fn speak<S: Into<String>>(&mut self, text: S) {
+        let text = text.into();
+        self.halt();
+        let session = self.session.fetch_add(1, Ordering::SeqCst) + 1;
+        {
+            let mut state = self.state.write();
+            state.text = text.clone();
+            state.speaking = !text.trim().is_empty();
+        }
+        if text.trim().is_empty() {
+            return;
+        }
+
+        let device = self.clone();
+        tokio::spawn(async move {
+            let state = device.state.read().clone();
+            let synthesized = tokio::task::spawn_blocking(move || synthesize(&state))
+                .await
+                .map_err(|err| anyhow!(err))
+                .and_then(|result| result);
+            let data = match synthesized {
+                Ok(data) => data,
+                Err(err) => {
+                    warn!("TextToSpeech failed to synthesize [{}]: {}", text, err);
+                    device.state.write().speaking = false;
+                    return;
+                }
+            };
+            // Another sentence was requested meanwhile.
+            if device.session.load(Ordering::SeqCst) != session {
+                return;
+            }
+            if let Err(err) = device.start(data) {
+                warn!("TextToSpeech failed to play [{}]: {}", text, err);
+                device.state.write().speaking = false;
+                return;
+            }
+            device.monitor(session).await;
+        });
+    }
+
+    /// Sets the volume (in percent).
+    pub fn set_volume(&mut self, volume: u8) {
+        self.state.write().volume = volume;
+        if let Some(control) = self.control.read().as_ref() {
+            control.set_volume(self.get_volume());
+        }
+    }
+
+    pub fn get_text(&self) -> String {
+        self.state.read().text.clone()
+    }
+
+    /// (private)
+    /// Retrieves the volume as a ratio (1.0 = 100%).
+    fn get_volume(&self) -> f32 {
+        self.state.read().volume as f32 / 100.0
+    }
+
+    /// (private)
+    /// Plays the synthesized speech (WAV data) on the (shared) default physical sound device.
+    fn start(&self, data: Vec<u8>) -> Result<()> {
+        let source = Decoder::new(Cursor::new(data))?;
+        let sink = Sink::try_new(output()?)?;
+        sink.set_volume(self.get_volume());
+        sink.append(source);
+        *self.control.write() = Some(sink);
+        self.events
+            .emit(TextToSpeechEvent::OnStart, self.get_text());
+        Ok(())
+    }
+
+    /// (private)
+    /// Waits for the end of the speech.
+    async fn monitor(&self, session: usize) {
+        loop {
+            pause!(MONITOR_INTERVAL);
+            if self.session.load(Ordering::SeqCst) != session {
+                break;
+            }
+            let empty = match self.control.read().as_ref() {
+                None => break,
+                Some(control) => control.empty(),
+            };
+            if empty {
+                self.control.write().take();
+                self.state.write().speaking = false;
+                self.events.emit(TextToSpeechEvent::OnEnd, self.get_text());
+                break;
+            }
+        }
+    }
+
+    /// (private)
+    /// Interrupts the current speech (if any).
+    fn halt(&mut self) {
+        let interrupted = self.state.read().speaking;
+        self.session.fetch_add(1, Ordering::SeqCst);
+        if let Some(control) = self.control.write().take() {
+            control.stop();
+        }
+        if interrupted {
+            self.state.write().speaking = false;
+            self.events.emit(TextToSpeechEvent::OnEnd, self.get_text());
+        }
+    }
+
+    // ########################################
+    // Event related functions
+
+    /// Registers a callback to be executed on a given event.
+    ///
+    /// Available events are:
+    /// * `OnStart` | `start`: Triggered when the speech starts (with the spoken text).
+    /// * `OnEnd` | `end`: Triggered when the speech ends or is interrupted (with the spoken text).
+    #[allow(dead_code)]
+    pub fn on<S, F, T, Fut>(&self, event: S, callback: F) -> EventHandler
+    where
+        S: Into<String>,
+        T: 'static + Send + Sync + Clone,
+        F: FnMut(T) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = std::result::Result<(), Error>> + Send + 'static,
+    {
+        self.events.on(event, callback)
+    }
+}
+
+/// Synthesizes the text of the given state into WAV data using the speech engine.
+fn synthesize(state: &TextToSpeechState) -> Result<Vec<u8>> {
+    let mut process = Command::new(ENGINE)
+        .args(get_args(state))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| anyhow!("Cannot start the speech engine ({}): {}", ENGINE, err))?;
+
+    // The text goes through stdin so that it is never interpreted as an option. It is written from another
+    // thread: the engine fills its stdout while reading, which would block forever once the pipe is full.
+    let writer = process.stdin.take().map(|mut stdin| {
+        let text = state.text.clone();
+        std::thread::spawn(move || stdin.write_all(text.as_bytes()))
+    });
+    let result = process.wait_with_output()?;
+    if !result.status.success() {
+        bail!(
+            "The speech engine failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| anyhow!("The speech engine input failed"))??;
+    }
+    Ok(result.stdout)
+}
+
+/// Retrieves the arguments of the speech engine for the given state (the text excluded).
+fn get_args(state: &TextToSpeechState) -> Vec<String> {
+    vec![
+        String::from("--stdout"),
+        String::from("-v"),
+        state.voice.clone(),
+        String::from("-s"),
+        state.rate.to_string(),
+        String::from("-p"),
+        state.pitch.min(99).to_string(),
+    ]
+}
+
+#[typetag::serde]
+impl Device for TextToSpeech {}
+
+#[typetag::serde]
+impl Output for TextToSpeech {
+    fn animate<S: Into<State>>(&mut self, state: S, _duration: u64, _transition: Easing)
+    where
+        Self: Sized,
+    {
+        let _ = self.set_state(state.into());
+    }
+
+    /// Stops the current speech.
+    fn stop(&mut self) {
+        self.halt();
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State, Error> {
+        match state.clone() {
+            State::String(text) => self.speak(text),
+            State::Integer(0) | State::Signed(0) | State::Boolean(false) => self.halt(),
+            State::Object(state) => {
+                {
+                    let mut current = self.state.write();
+                    if let Some(voice) = state.get("voice") {
+                        current.voice = voice.as_string();
+                    }
+                    if let Some(rate) = state.get("rate") {
+                        current.rate = rate.as_integer().clamp(80, 500) as u16;
+                    }
+                    if let Some(pitch) = state.get("pitch") {
+                        current.pitch = pitch.as_integer().min(99) as u8;
+                    }
+                }
+                if let Some(volume) = state.get("volume") {
+                    self.set_volume(volume.as_integer().min(100) as u8);
+                }
+                if let Some(text) = state.get("text") {
+                    self.speak(text.as_string());
+                }
+            }
+            _ => {}
+        };
+        let new_state = self.state.read().clone();
+        Ok(State::into_state(new_state))
+    }
+
+    fn get_state(&self) -> State {
+        State::into_state(self.state.read().clone())
+    }
+
+    fn get_default(&self) -> State {
+        State::into_state(self.default.clone())
+    }
+
+    fn is_busy(&self) -> bool {
+        self.state.read().speaking
+    }
+
+    fn scale_state(&mut self, _previous: State, target: State, progress: f32) -> State {
+        scale_discrete(target, progress)
+    }
+}
+
+impl Display for TextToSpeech {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TextToSpeech [voice={}]", self.state.read().voice)
+    }
+}
+
+impl Debug for TextToSpeech {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextToSpeech")
+            .field("state", &self.state)
+            .field("default", &self.default)
+            .field("controls", &"[audio control]")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_get_args() {
+        let state = TextToSpeechState {
+            pitch: 120,
+            ..Default::default()
+        };
+        assert_eq!(
+            get_args(&state),
+            vec!["--stdout", "-v", "en", "-s", "175", "-p", "99"]
+        );
+    }
+
+    #[test]
+    fn test_set_state() {
+        let mut tts: TextToSpeech =
+            serde_json::from_value(json!({"state": {"voice": "fr"}, "default": {}})).unwrap();
+        let settings = State::into_state(json!({"rate": 1000, "pitch": 150, "volume": 150}));
+        tts.set_state(settings).unwrap();
+        {
+            let state = tts.state.read();
+            assert_eq!(state.voice, "fr");
+            assert_eq!((state.rate, state.pitch, state.volume), (500, 99, 100));
+        }
+
+        // Stopping a silent device does nothing.
+        tts.set_state(State::Integer(0)).unwrap();
+        assert!(!tts.is_busy());
+        assert_eq!(tts.get_text(), "");
+    }
+}
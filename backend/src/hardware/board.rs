@@ -1,8 +1,12 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use hermes_five::protocols::Protocol;
 use hermes_five::Board as InnerBoard;
+use log::warn;
 use serde::{Deserialize, Serialize};
 
+use crate::extra::pca9685::Pca9685;
 use crate::hardware::device::Device;
+use crate::hardware::firmware::FirmwareCheck;
 use crate::impl_entity;
 use crate::utils::database::{ArcDb, Database};
 use crate::utils::entity::{Entity, Id};
@@ -15,12 +19,16 @@ pub struct Board {
     #[serde(flatten)]
     pub inner: InnerBoard,
     pub connected: bool,
+    /// The firmware compatibility check (computed when the board opens).
+    #[serde(default, skip_deserializing)]
+    pub firmware: Option<FirmwareCheck>,
 }
 
 impl_entity!(Board, {
     fn post_load(&mut self, _: &Database) -> Result<()> {
         // Reset connection state on load.
         self.connected = false;
+        self.firmware = None;
         Ok(())
     }
     // Delete all associated devices.
@@ -40,9 +48,27 @@ impl Board {
         self.inner = self.inner.blocking_open()?;
         self.connected = self.inner.is_connected();
 
+        // Check the firmware reported by the board during the handshake.
+        let (name, version) = {
+            let protocol = self.inner.get_protocol();
+            let hardware = protocol.get_hardware().read();
+            (
+                hardware.firmware_name.clone(),
+                hardware.firmware_version.clone(),
+            )
+        };
+        self.firmware = FirmwareCheck::new(&self.model, &name, &version);
+        if let Some(check) = self.firmware.as_ref().filter(|check| check.outdated) {
+            warn!(
+                "Board [{}] runs an incompatible firmware: {} {} (expected: {})",
+                self.id, check.name, check.version, check.expected
+            );
+        }
+
         // Initialize properly the inner device value because now that board is open(), the
         // handshake as given us the hardware board configuration, which lets us properly initialize
         // our devices.
+        Pca9685::forget(self.id);
         let devices = database.write().list::<Device>()?;
         for (_, mut device) in devices {
             if device.bid == self.id {
@@ -56,8 +82,18 @@ impl Board {
     pub fn close(mut self) -> Result<Self> {
         self.inner = self.inner.close();
         self.connected = false;
+        self.firmware = None;
         Ok(self)
     }
+
+    /// Retrieves the serial port the board is connected to (for boards using a serial protocol).
+    pub fn get_port(&self) -> Result<String> {
+        let inner = serde_json::to_value(&self.inner)?;
+        match inner["protocol"]["port"].as_str() {
+            None => bail!("Board [{}] is not connected to a serial port", self.id),
+            Some(port) => Ok(port.to_string()),
+        }
+    }
 }
 
 // ########################################
@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::fmt::Debug;
 
 use anyhow::Result;
@@ -8,6 +9,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::animation::group::Group;
 use crate::hardware::board::Board;
+use crate::hardware::pins::PinUsage;
 use crate::impl_entity;
 use crate::utils::database::Database;
 use crate::utils::entity::Id;
@@ -58,27 +60,81 @@ impl_entity!(Device, {
 });
 
 #[typetag::serde(tag = "type")]
-pub trait DeviceType: DynClone + Debug + Send + Sync {
+pub trait DeviceType: AsAny + DynClone + Debug + Send + Sync {
     fn reset(&mut self) -> Result<State>;
     fn set_board(&mut self, board: &Board) -> Result<()>;
     fn set_state(&mut self, state: State) -> Result<State>;
     fn animate(&mut self, state: State, duration: u64, transition: Easing) -> Result<State>;
     fn into_track(&self) -> Result<Track>;
+    fn get_default(&self) -> State;
+    fn get_state(&self) -> State;
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State;
+    /// Retrieves the (min, max) range of the positions the device can reach (if relevant).
+    fn get_range(&self) -> Option<(u16, u16)> {
+        None
+    }
+    /// Retrieves the pins used by the device, along with the capability each of them needs.
+    fn get_pins(&self) -> Vec<PinUsage> {
+        vec![]
+    }
+    /// Checks the configuration of the device before it is saved (any configuration is valid by default).
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Whether the device plays discrete actions (a sound, a text...): its keyframes are triggered when they
+    /// start rather than interpolated.
+    fn is_discrete(&self) -> bool {
+        false
+    }
 }
 dyn_clone::clone_trait_object!(DeviceType);
 
+/// Gives access to the concrete type of a device (see [`Device::downcast_ref`]).
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Device {
+    /// Retrieves the device as the given device type, if it is one: the features specific to some devices
+    /// (a PCA9685 channel, a calibration...) are not part of [`DeviceType`].
+    pub fn downcast_ref<T: DeviceType + 'static>(&self) -> Option<&T> {
+        (*self.inner).as_any().downcast_ref()
+    }
+
+    /// Mutable version of [`Device::downcast_ref`].
+    pub fn downcast_mut<T: DeviceType + 'static>(&mut self) -> Option<&mut T> {
+        (*self.inner).as_any_mut().downcast_mut()
+    }
+}
+
 /// Helper macro to implement a [`Device`] for a given hermes_five device type.
+/// The wrapped type defaults to the hermes_five device of the same name, another output can be given
+/// (ie: `impl_device!(PcaServo => crate::extra::pca9685::PcaServo)`).
 #[macro_export]
 macro_rules! impl_device {
     ($struct_name:ident $(, { $($additional_impl:item)* })?) => {
+        $crate::impl_device!($struct_name => hermes_five::devices::$struct_name $(, { $($additional_impl)* })?);
+    };
+    ($struct_name:ident => $inner:ty $(, { $($additional_impl:item)* })?) => {
         #[derive(Clone, Debug, Serialize, Deserialize)]
         pub struct $struct_name {
             #[serde(flatten)]
-            pub inner: hermes_five::devices::$struct_name,
+            pub inner: $inner,
         }
 
         impl Deref for $struct_name {
-            type Target = hermes_five::devices::$struct_name;
+            type Target = $inner;
 
             fn deref(&self) -> &Self::Target {
                 &self.inner
@@ -109,6 +165,18 @@ macro_rules! impl_device {
                 Ok(Track::new(device))
             }
 
+            fn get_default(&self) -> hermes_five::utils::State {
+                self.inner.get_default()
+            }
+
+            fn get_state(&self) -> hermes_five::utils::State {
+                self.inner.get_state()
+            }
+
+            fn scale_state(&mut self, previous: hermes_five::utils::State, target: hermes_five::utils::State, progress: f32) -> hermes_five::utils::State {
+                self.inner.scale_state(previous, target, progress)
+            }
+
             fn reset(&mut self) -> Result<hermes_five::utils::State> {
                 let state = self.animate(self.inner.get_default(), 500, hermes_five::utils::Easing::SineInOut)?;
                 Ok(state)
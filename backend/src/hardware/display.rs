@@ -0,0 +1,28 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Result;
+use hermes_five::animation::Track;
+use hermes_five::devices::Output;
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::board::Board;
+use crate::hardware::device::DeviceType;
+use crate::impl_device;
+
+// The displays use the I2C bus of the board, shared with other I2C devices: they use no pin of their own.
+
+impl_device!(Display => crate::extra::display::Display, {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        self.inner = self.inner.clone().attach(&board.inner)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        Ok(self.inner.get_controller().check()?)
+    }
+
+    fn is_discrete(&self) -> bool {
+        true
+    }
+});
@@ -0,0 +1,188 @@
+//! This file contains the firmware compatibility check run when a board opens.
+//!
+//! Once connected, the board reports the name and the version of its firmware (the Firmata sketch). The
+//! `COMPATIBILITY` matrix defines which firmwares (and from which version) each backend release supports: a board
+//! running anything else is flagged as outdated, and can be fixed by flashing the bundled firmware (see
+//! [`crate::hardware::flasher`]). Extension firmwares (ie: node-pixel, needed by the LED strips) are supported
+//! as well, but never offered to be overwritten.
+use log::warn;
+use serde::Serialize;
+
+use crate::hardware::board::BoardType;
+use crate::hardware::flasher::Target;
+
+/// The firmware expected by a backend release.
+pub struct Compatibility {
+    /// The backend release (major.minor) the entry applies to.
+    pub backend: &'static str,
+    /// The expected firmware name.
+    pub firmware: &'static str,
+    /// The minimum firmware version supported.
+    pub min_version: &'static str,
+    /// The version of the bundled firmware (see the `firmwares` folder): none for the extension firmwares.
+    pub bundled: Option<&'static str>,
+}
+
+/// The compatibility matrix between the backend releases and the firmwares: the first entry of a release is
+/// its bundled firmware.
+pub const COMPATIBILITY: &[Compatibility] = &[
+    Compatibility {
+        backend: "0.1",
+        firmware: "StandardFirmataPlus",
+        min_version: "2.5",
+        bundled: Some("2.5"),
+    },
+    // The Firmata pixel extension (see [`crate::extra::strip`]).
+    Compatibility {
+        backend: "0.1",
+        firmware: "node_pixel_firmata",
+        min_version: "2.5",
+        bundled: None,
+    },
+];
+
+/// The result of the firmware compatibility check of a board (see `Board::firmware`).
+#[derive(Clone, Debug, Serialize)]
+pub struct FirmwareCheck {
+    /// The firmware name reported by the board.
+    pub name: String,
+    /// The firmware version reported by the board.
+    pub version: String,
+    /// The expected firmware (name and minimum version).
+    pub expected: String,
+    /// The version of the bundled firmware.
+    pub bundled: String,
+    /// Whether the firmware is missing, unknown or too old for this backend.
+    pub outdated: bool,
+    /// The socket event fixing the firmware (ie: `board:flash`) when a bundled firmware exists for the board.
+    pub action: Option<String>,
+}
+
+impl FirmwareCheck {
+    /// Checks the firmware reported by a board against the compatibility matrix of the current backend.
+    /// Returns none for boards which do not run a firmware (ie: non Arduino boards), or if the current release
+    /// is missing from the matrix (which is logged).
+    pub fn new(model: &BoardType, name: &str, version: &str) -> Option<Self> {
+        if !matches!(model, BoardType::Arduino(_)) {
+            return None;
+        }
+        let entries = get_compatibility(env!("CARGO_PKG_VERSION"));
+        let bundled = match entries.first() {
+            None => {
+                warn!(
+                    "No firmware compatibility entry for release {}: firmware not checked",
+                    env!("CARGO_PKG_VERSION")
+                );
+                return None;
+            }
+            Some(bundled) => bundled,
+        };
+        let name = normalize(name);
+        let entry = entries
+            .iter()
+            .find(|entry| name.eq_ignore_ascii_case(entry.firmware))
+            .unwrap_or(bundled);
+        let outdated = !name.eq_ignore_ascii_case(entry.firmware)
+            || parse_version(version) < parse_version(entry.min_version);
+        // Flashing an extension firmware would replace it with the bundled one: it is not offered.
+        let flashable = entry.bundled.is_some() && Target::from_model(model).is_ok();
+        let action = match outdated && flashable {
+            true => Some(String::from("board:flash")),
+            false => None,
+        };
+        Some(Self {
+            name,
+            version: version.to_string(),
+            expected: format!("{} >= {}", entry.firmware, entry.min_version),
+            bundled: bundled.bundled.unwrap_or_default().to_string(),
+            outdated,
+            action,
+        })
+    }
+}
+
+/// Retrieves the compatibility entries of a backend release (its bundled firmware first).
+pub fn get_compatibility(backend: &str) -> Vec<&'static Compatibility> {
+    let release = parse_version(backend);
+    COMPATIBILITY
+        .iter()
+        .filter(|entry| release.starts_with(&parse_version(entry.backend)))
+        .collect()
+}
+
+/// (private)
+/// Normalizes a firmware name: Firmata reports the sketch file, possibly with its path and extension.
+fn normalize(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let name = name.strip_suffix(".ino").unwrap_or(name);
+    name.trim().to_string()
+}
+
+/// (private)
+/// Parses a version (ie: "2.5.1", "0.1.0-beta") into its numeric parts; pre-release tags are ignored.
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map_while(|part| part.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hardware::board::{ArduinoType, RaspberryType};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("2.5"), vec![2, 5]);
+        assert_eq!(parse_version("0.1.0-beta"), vec![0, 1, 0]);
+        assert!(parse_version("2.5") < parse_version("2.10"));
+        assert!(parse_version("") < parse_version("2.5"));
+        assert_eq!(get_compatibility("0.1.0-beta").len(), 2);
+        assert!(get_compatibility("0.10.0").is_empty());
+    }
+
+    #[test]
+    fn test_current_release_compatibility() {
+        let entries = get_compatibility(env!("CARGO_PKG_VERSION"));
+        assert!(
+            entries.first().is_some_and(|entry| entry.bundled.is_some()),
+            "COMPATIBILITY must define the bundled firmware of release {}",
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    #[test]
+    fn test_firmware_check() {
+        let mega = BoardType::Arduino(ArduinoType::MEGA);
+        let check = FirmwareCheck::new(&mega, "StandardFirmataPlus.ino", "2.5").unwrap();
+        assert_eq!(check.name, "StandardFirmataPlus");
+        assert!(!check.outdated);
+        assert_eq!(check.action, None);
+
+        let check =
+            FirmwareCheck::new(&mega, "C:\\sketches\\StandardFirmataPlus.ino", "2.3").unwrap();
+        assert!(check.outdated);
+        assert_eq!(check.action.as_deref(), Some("board:flash"));
+
+        // The pixel extension is supported, but never offered to be overwritten.
+        let check = FirmwareCheck::new(&mega, "node_pixel_firmata.ino", "2.5").unwrap();
+        assert!(!check.outdated);
+        let check = FirmwareCheck::new(&mega, "node_pixel_firmata.ino", "2.3").unwrap();
+        assert!(check.outdated);
+        assert_eq!(check.expected, "node_pixel_firmata >= 2.5");
+        assert_eq!(check.action, None);
+
+        let other = BoardType::Arduino(ArduinoType::OTHER);
+        let check = FirmwareCheck::new(&other, "Blink", "1.0").unwrap();
+        assert!(check.outdated);
+        assert_eq!(check.action, None);
+
+        let raspberry = BoardType::RaspberryPi(RaspberryType::FOUR);
+        assert!(FirmwareCheck::new(&raspberry, "No firmware", "1.0.0").is_none());
+    }
+}
@@ -0,0 +1,671 @@
+//! This file contains the firmware `Flasher`: it uploads the bundled Firmata firmwares onto Arduino boards.
+//!
+//! The firmware images (Intel HEX files) live in a configurable directory (see [`Config::firmware_path`]).
+//! The image and the bootloader protocol are chosen from the board model: the Nano and Uno bootloaders speak
+//! STK500v1 while the Mega one speaks STK500v2. Both protocols are implemented natively over the serial port
+//! of the board, which is closed during the upload and reopened afterwards.
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use hermes_five::pause_sync;
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serialport::{ClearBuffer, SerialPort};
+use socketioxide::SocketIo;
+
+use crate::hardware::board::{ArduinoType, Board, BoardType};
+use crate::hardware::profile::BoardProfile;
+use crate::utils::config::Config;
+use crate::utils::database::ArcDb;
+use crate::utils::entity::{Entity, Id};
+
+/// The maximum time (in ms) to wait for an answer of the bootloader.
+const READ_TIMEOUT: u64 = 1000;
+/// The number of attempts to synchronize with the bootloader.
+const SYNC_ATTEMPTS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Stk500v1,
+    Stk500v2,
+}
+
+/// The flashing characteristics of a board model.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub protocol: Protocol,
+    /// The baud rates the bootloader may listen to (tried in order).
+    pub bauds: &'static [u32],
+    /// The flash page size (in bytes).
+    pub page_size: usize,
+    /// The flash size (in bytes) available to the firmware (ie: without the bootloader).
+    pub flash_size: usize,
+    /// The firmware file name.
+    pub firmware: &'static str,
+}
+
+impl Target {
+    /// Retrieves the flashing characteristics of a board model.
+    ///
+    /// # Errors
+    /// * if the model has no bundled firmware (see [`BoardProfile::firmware`]).
+    pub fn from_model(model: &BoardType) -> Result<Self> {
+        let firmware = match BoardProfile::get(model).and_then(|profile| profile.firmware) {
+            None => bail!("No firmware available for board model {:?}", model),
+            Some(firmware) => firmware,
+        };
+        match model {
+            BoardType::Arduino(ArduinoType::MEGA) => Ok(Self {
+                protocol: Protocol::Stk500v2,
+                bauds: &[115200],
+                page_size: 256,
+                flash_size: 256 * 1024 - 8 * 1024,
+                firmware,
+            }),
+            // Nano clones ship with either the old bootloader (57600) or optiboot (115200).
+            BoardType::Arduino(ArduinoType::NANO) => Ok(Self {
+                protocol: Protocol::Stk500v1,
+                bauds: &[115200, 57600],
+                page_size: 128,
+                flash_size: 32 * 1024 - 2 * 1024,
+                firmware,
+            }),
+            // The Uno shares the Nano microcontroller (atmega328p).
+            BoardType::Arduino(ArduinoType::UNO) => Ok(Self {
+                protocol: Protocol::Stk500v1,
+                bauds: &[115200],
+                page_size: 128,
+                flash_size: 32 * 1024 - 512,
+                firmware,
+            }),
+            model => bail!("No bootloader known for board model {:?}", model),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashStage {
+    Connecting,
+    Writing,
+    Verifying,
+    Done,
+    Failed,
+}
+
+/// The progress of a firmware upload (streamed as `board:flash:progress`).
+#[derive(Clone, Debug, Serialize)]
+pub struct FlashProgress {
+    pub stage: FlashStage,
+    /// The number of bytes processed within the current stage.
+    pub done: usize,
+    /// The number of bytes to process within the current stage.
+    pub total: usize,
+    /// The reason of the failure (if any).
+    pub error: Option<String>,
+}
+
+impl FlashProgress {
+    fn new(stage: FlashStage, done: usize, total: usize) -> Self {
+        Self {
+            stage,
+            done,
+            total,
+            error: None,
+        }
+    }
+}
+
+/// The firmware flasher settings shared by all sockets.
+#[derive(Clone, Debug)]
+pub struct Flasher {
+    /// The directory where the firmware images are stored.
+    pub root: PathBuf,
+    /// The boards currently being flashed.
+    flashing: Arc<Mutex<HashSet<Id>>>,
+}
+
+impl From<&Config> for Flasher {
+    fn from(config: &Config) -> Self {
+        Self {
+            root: config.firmware_path.clone(),
+            flashing: Default::default(),
+        }
+    }
+}
+
+impl Flasher {
+    /// Loads the firmware image for the given target.
+    ///
+    /// # Errors
+    /// * if the image cannot be read, is not a valid Intel HEX file or is too large for the target.
+    pub fn get_firmware(&self, target: &Target) -> Result<Vec<u8>> {
+        let path = self.root.join(target.firmware);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|err| anyhow!("Cannot read firmware {:?}: {}", path, err))?;
+        parse_hex(&content, target.flash_size)
+            .map_err(|err| anyhow!("Invalid firmware {}: {}", target.firmware, err))
+    }
+
+    /// Checks whether the given board is currently being flashed.
+    pub fn is_flashing(&self, id: &Id) -> bool {
+        self.flashing.lock().contains(id)
+    }
+
+    /// Starts flashing the bundled firmware onto a board: the board is closed (and returned as such) and the
+    /// upload runs in the background, streaming its progress. The board is reopened once done.
+    ///
+    /// # Errors
+    /// * if the board model has no firmware, or the board has no serial port.
+    /// * if the board is already being flashed.
+    pub fn start(&self, board: Board, database: &ArcDb, io: &SocketIo) -> Result<Board> {
+        let target = Target::from_model(&board.model)?;
+        let port = board.get_port()?;
+        let image = self.get_firmware(&target)?;
+        let id = board.id;
+        if !self.flashing.lock().insert(id) {
+            bail!("Board [{}] is already being flashed", id);
+        }
+        let board = match board.connected {
+            true => match board.close().and_then(|board| board.save(database)) {
+                Ok(board) => board,
+                Err(err) => {
+                    self.flashing.lock().remove(&id);
+                    return Err(err);
+                }
+            },
+            false => board,
+        };
+
+        let (flashing, database, io, closed) = (
+            self.flashing.clone(),
+            database.clone(),
+            io.clone(),
+            board.clone(),
+        );
+        tokio::task::spawn_blocking(move || {
+            let emit = |progress: &FlashProgress| {
+                if let Some(socket) = io.of("/ws") {
+                    socket.emit("board:flash:progress", &(id, progress)).ok();
+                }
+            };
+
+            info!("Flashing board [{}] on port {}", id, port);
+            let progress = match flash(&port, &target, &image, |progress| emit(&progress)) {
+                Ok(_) => FlashProgress::new(FlashStage::Done, image.len(), image.len()),
+                Err(err) => {
+                    warn!("Flashing board [{}] failed: {}", id, err);
+                    FlashProgress {
+                        error: Some(err.to_string()),
+                        ..FlashProgress::new(FlashStage::Failed, 0, image.len())
+                    }
+                }
+            };
+            emit(&progress);
+            flashing.lock().remove(&id);
+
+            // Let the board restart on its new firmware before reopening it.
+            pause_sync!(1000);
+            match board
+                .open(&database)
+                .and_then(|board| board.save(&database))
+            {
+                Err(err) => warn!("Board [{}] could not be reopened: {}", id, err),
+                Ok(board) => {
+                    if let Some(socket) = io.of("/ws") {
+                        if let Some(check) = board.firmware.as_ref().filter(|check| check.outdated)
+                        {
+                            socket.emit("board:firmware:warning", &(id, check)).ok();
+                        }
+                        socket.emit("board:updated", &board).ok();
+                    }
+                }
+            }
+        });
+        Ok(closed)
+    }
+}
+
+/// Uploads an image onto the board connected to the given serial port, then verifies it.
+pub fn flash<F: FnMut(FlashProgress)>(
+    port: &str,
+    target: &Target,
+    image: &[u8],
+    mut progress: F,
+) -> Result<()> {
+    progress(FlashProgress::new(FlashStage::Connecting, 0, image.len()));
+    let mut programmer = connect(port, target)?;
+    programmer.enter()?;
+
+    // Pad the image to a whole number of pages.
+    let mut padded = image.to_vec();
+    padded.resize(
+        image.len().div_ceil(target.page_size) * target.page_size,
+        0xFF,
+    );
+
+    let total = padded.len();
+    for (index, page) in padded.chunks(target.page_size).enumerate() {
+        programmer.write_page(index * target.page_size, page)?;
+        progress(FlashProgress::new(
+            FlashStage::Writing,
+            (index + 1) * target.page_size,
+            total,
+        ));
+    }
+    for (index, page) in padded.chunks(target.page_size).enumerate() {
+        let address = index * target.page_size;
+        if programmer.read_page(address, page.len())? != page {
+            bail!("Verification failed at address 0x{:05X}", address);
+        }
+        progress(FlashProgress::new(
+            FlashStage::Verifying,
+            address + page.len(),
+            total,
+        ));
+    }
+    programmer.leave()
+}
+
+/// (private)
+/// Opens the serial port and synchronizes with the bootloader, trying each baud rate of the target.
+fn connect(port: &str, target: &Target) -> Result<Box<dyn Programmer>> {
+    let mut last_error = anyhow!("No baud rate to try");
+    for baud in target.bauds {
+        let mut serial = serialport::new(port, *baud)
+            .timeout(Duration::from_millis(READ_TIMEOUT))
+            .open()
+            .map_err(|err| anyhow!("Cannot open port {}: {}", port, err))?;
+        reset(serial.as_mut())?;
+
+        let mut programmer: Box<dyn Programmer> = match target.protocol {
+            Protocol::Stk500v1 => Box::new(Stk500v1 { port: serial }),
+            Protocol::Stk500v2 => Box::new(Stk500v2 {
+                port: serial,
+                sequence: 0,
+                extended: target.flash_size > 128 * 1024,
+            }),
+        };
+        for _ in 0..SYNC_ATTEMPTS {
+            match programmer.sync() {
+                Ok(_) => return Ok(programmer),
+                Err(err) => last_error = err,
+            }
+        }
+    }
+    Err(anyhow!("Bootloader not responding: {}", last_error))
+}
+
+/// (private)
+/// Resets the board (toggling DTR/RTS) to start its bootloader.
+fn reset(port: &mut dyn SerialPort) -> Result<()> {
+    port.write_data_terminal_ready(false)?;
+    port.write_request_to_send(false)?;
+    pause_sync!(250);
+    port.write_data_terminal_ready(true)?;
+    port.write_request_to_send(true)?;
+    pause_sync!(50);
+    port.clear(ClearBuffer::Input)?;
+    Ok(())
+}
+
+/// A bootloader protocol.
+trait Programmer: Send {
+    /// Synchronizes with the bootloader.
+    fn sync(&mut self) -> Result<()>;
+    /// Enters the programming mode.
+    fn enter(&mut self) -> Result<()>;
+    /// Writes a page of flash at the given (byte) address.
+    fn write_page(&mut self, address: usize, data: &[u8]) -> Result<()>;
+    /// Reads a page of flash at the given (byte) address.
+    fn read_page(&mut self, address: usize, length: usize) -> Result<Vec<u8>>;
+    /// Leaves the programming mode (starting the firmware).
+    fn leave(&mut self) -> Result<()>;
+}
+
+// ########################################
+// STK500v1 (Arduino Nano, Uno)
+
+const STK_OK: u8 = 0x10;
+const STK_INSYNC: u8 = 0x14;
+const CRC_EOP: u8 = 0x20;
+const STK_GET_SYNC: u8 = 0x30;
+const STK_ENTER_PROGMODE: u8 = 0x50;
+const STK_LEAVE_PROGMODE: u8 = 0x51;
+const STK_LOAD_ADDRESS: u8 = 0x55;
+const STK_PROG_PAGE: u8 = 0x64;
+const STK_READ_PAGE: u8 = 0x74;
+
+struct Stk500v1 {
+    port: Box<dyn SerialPort>,
+}
+
+impl Stk500v1 {
+    /// Sends a command and reads `length` bytes of answer framed by INSYNC/OK.
+    fn command(&mut self, command: &[u8], length: usize) -> Result<Vec<u8>> {
+        let mut message = command.to_vec();
+        message.push(CRC_EOP);
+        self.port.write_all(&message)?;
+
+        let mut answer = vec![0; length + 2];
+        self.port.read_exact(&mut answer)?;
+        if answer[0] != STK_INSYNC || answer[length + 1] != STK_OK {
+            bail!(
+                "Unexpected STK500v1 answer to command 0x{:02X}: {:02X?}",
+                command[0],
+                answer
+            );
+        }
+        Ok(answer[1..=length].to_vec())
+    }
+
+    fn load_address(&mut self, address: usize) -> Result<()> {
+        let word = address / 2;
+        self.command(&[STK_LOAD_ADDRESS, word as u8, (word >> 8) as u8], 0)?;
+        Ok(())
+    }
+}
+
+impl Programmer for Stk500v1 {
+    fn sync(&mut self) -> Result<()> {
+        self.port.write_all(&[STK_GET_SYNC, CRC_EOP])?;
+        pause_sync!(50);
+        self.port.clear(ClearBuffer::Input)?;
+        self.command(&[STK_GET_SYNC], 0)?;
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.command(&[STK_ENTER_PROGMODE], 0)?;
+        Ok(())
+    }
+
+    fn write_page(&mut self, address: usize, data: &[u8]) -> Result<()> {
+        self.load_address(address)?;
+        let mut command = vec![
+            STK_PROG_PAGE,
+            (data.len() >> 8) as u8,
+            data.len() as u8,
+            b'F',
+        ];
+        command.extend_from_slice(data);
+        self.command(&command, 0)?;
+        Ok(())
+    }
+
+    fn read_page(&mut self, address: usize, length: usize) -> Result<Vec<u8>> {
+        self.load_address(address)?;
+        self.command(
+            &[STK_READ_PAGE, (length >> 8) as u8, length as u8, b'F'],
+            length,
+        )
+    }
+
+    fn leave(&mut self) -> Result<()> {
+        self.command(&[STK_LEAVE_PROGMODE], 0)?;
+        Ok(())
+    }
+}
+
+// ########################################
+// STK500v2 (Arduino Mega)
+
+const MESSAGE_START: u8 = 0x1B;
+const TOKEN: u8 = 0x0E;
+const STATUS_CMD_OK: u8 = 0x00;
+const CMD_SIGN_ON: u8 = 0x01;
+const CMD_LOAD_ADDRESS: u8 = 0x06;
+const CMD_ENTER_PROGMODE_ISP: u8 = 0x10;
+const CMD_LEAVE_PROGMODE_ISP: u8 = 0x11;
+const CMD_PROGRAM_FLASH_ISP: u8 = 0x13;
+const CMD_READ_FLASH_ISP: u8 = 0x14;
+
+struct Stk500v2 {
+    port: Box<dyn SerialPort>,
+    sequence: u8,
+    /// Whether addresses above 128KB need the extended address flag.
+    extended: bool,
+}
+
+/// (private)
+/// Wraps a command body into a STK500v2 message: start, sequence, size, token, body and xor checksum.
+fn frame(sequence: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![
+        MESSAGE_START,
+        sequence,
+        (body.len() >> 8) as u8,
+        body.len() as u8,
+        TOKEN,
+    ];
+    message.extend_from_slice(body);
+    message.push(message.iter().fold(0, |checksum, byte| checksum ^ byte));
+    message
+}
+
+impl Stk500v2 {
+    /// Sends a command and returns the body of the answer (command and status included).
+    fn command(&mut self, body: &[u8]) -> Result<Vec<u8>> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.port.write_all(&frame(sequence, body))?;
+
+        let mut byte = [0; 1];
+        loop {
+            self.port.read_exact(&mut byte)?;
+            if byte[0] == MESSAGE_START {
+                break;
+            }
+        }
+        let mut header = [0; 4];
+        self.port.read_exact(&mut header)?;
+        let size = ((header[1] as usize) << 8) | header[2] as usize;
+        let mut answer = vec![0; size + 1];
+        self.port.read_exact(&mut answer)?;
+        let checksum = answer.pop().unwrap_or_default();
+
+        let mut message = vec![MESSAGE_START];
+        message.extend_from_slice(&header);
+        message.extend_from_slice(&answer);
+        if header[0] != sequence
+            || header[3] != TOKEN
+            || message.iter().fold(0, |checksum, byte| checksum ^ byte) != checksum
+        {
+            bail!("Corrupted STK500v2 answer to command 0x{:02X}", body[0]);
+        }
+        if answer.len() < 2 || answer[0] != body[0] || answer[1] != STATUS_CMD_OK {
+            bail!("STK500v2 command 0x{:02X} failed: {:02X?}", body[0], answer);
+        }
+        Ok(answer)
+    }
+
+    fn load_address(&mut self, address: usize) -> Result<()> {
+        let mut word = (address / 2) as u32;
+        if self.extended {
+            word |= 0x8000_0000;
+        }
+        let mut command = vec![CMD_LOAD_ADDRESS];
+        command.extend_from_slice(&word.to_be_bytes());
+        self.command(&command)?;
+        Ok(())
+    }
+}
+
+impl Programmer for Stk500v2 {
+    fn sync(&mut self) -> Result<()> {
+        self.command(&[CMD_SIGN_ON])?;
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        // timeout, stabDelay, cmdexeDelay, synchLoops, byteDelay, pollValue, pollIndex, then the ISP command.
+        self.command(&[
+            CMD_ENTER_PROGMODE_ISP,
+            200,
+            100,
+            25,
+            32,
+            0,
+            0x53,
+            3,
+            0xAC,
+            0x53,
+            0x00,
+            0x00,
+        ])?;
+        Ok(())
+    }
+
+    fn write_page(&mut self, address: usize, data: &[u8]) -> Result<()> {
+        self.load_address(address)?;
+        // size, mode (page mode, write page), delay, then the ISP commands and poll values (unused by the bootloader).
+        let mut command = vec![
+            CMD_PROGRAM_FLASH_ISP,
+            (data.len() >> 8) as u8,
+            data.len() as u8,
+            0xC1,
+            10,
+            0x40,
+            0x4C,
+            0x20,
+            0x00,
+            0x00,
+        ];
+        command.extend_from_slice(data);
+        self.command(&command)?;
+        Ok(())
+    }
+
+    fn read_page(&mut self, address: usize, length: usize) -> Result<Vec<u8>> {
+        self.load_address(address)?;
+        let answer =
+            self.command(&[CMD_READ_FLASH_ISP, (length >> 8) as u8, length as u8, 0x20])?;
+        // The answer is: command, status, data then a final status.
+        match answer.get(2..2 + length) {
+            None => bail!("Truncated STK500v2 flash read at 0x{:05X}", address),
+            Some(data) => Ok(data.to_vec()),
+        }
+    }
+
+    fn leave(&mut self) -> Result<()> {
+        self.command(&[CMD_LEAVE_PROGMODE_ISP, 1, 1])?;
+        Ok(())
+    }
+}
+
+// ########################################
+
+/// Parses an Intel HEX file into a flat image starting at address 0 (gaps are filled with 0xFF).
+/// The image cannot exceed the given size (in bytes): the parsing stops as soon as a record goes beyond.
+pub fn parse_hex(content: &str, max_size: usize) -> Result<Vec<u8>> {
+    let mut image: Vec<u8> = vec![];
+    let mut base = 0usize;
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = match line.strip_prefix(':') {
+            Some(record) if record.is_ascii() && record.len() % 2 == 0 => record,
+            _ => bail!("Invalid HEX record at line {}", index + 1),
+        };
+        let bytes = (0..record.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&record[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| anyhow!("Invalid HEX record at line {}", index + 1))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            bail!("Invalid HEX record length at line {}", index + 1);
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            bail!("Invalid HEX checksum at line {}", index + 1);
+        }
+
+        let address = ((bytes[1] as usize) << 8) | bytes[2] as usize;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            // Data.
+            0x00 => {
+                let start = base + address;
+                let end = start + data.len();
+                if end > max_size {
+                    bail!(
+                        "Image too large: line {} ends at {} bytes (max: {} bytes)",
+                        index + 1,
+                        end,
+                        max_size
+                    );
+                }
+                if image.len() < end {
+                    image.resize(end, 0xFF);
+                }
+                image[start..end].copy_from_slice(data);
+            }
+            // End of file.
+            0x01 => break,
+            // Extended segment / linear address.
+            0x02 | 0x04 if data.len() == 2 => {
+                let value = ((data[0] as usize) << 8) | data[1] as usize;
+                base = match bytes[3] {
+                    0x02 => value << 4,
+                    _ => value << 16,
+                };
+            }
+            // Start segment / linear address: irrelevant for AVR.
+            0x03 | 0x05 => {}
+            kind => bail!(
+                "Unsupported HEX record type 0x{:02X} at line {}",
+                kind,
+                index + 1
+            ),
+        }
+    }
+    if image.is_empty() {
+        bail!("Empty HEX file");
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        let image = parse_hex(
+            ":020000040000FA\n:04000000DEADBEEFC4\n:02000800CAFE2E\n:00000001FF\n",
+            32,
+        )
+        .unwrap();
+        assert_eq!(
+            image,
+            vec![0xDE, 0xAD, 0xBE, 0xEF, 0xFF, 0xFF, 0xFF, 0xFF, 0xCA, 0xFE]
+        );
+
+        // Extended linear address.
+        let extended = ":020000040001F9\n:01000000AA55\n:00000001FF\n";
+        let image = parse_hex(extended, 0x20000).unwrap();
+        assert_eq!(image.len(), 0x10001);
+        assert_eq!(image[0x10000], 0xAA);
+        assert!(parse_hex(extended, 0x10000).is_err(), "too large");
+
+        assert!(parse_hex(":04000000DEADBEEFC5\n", 32).is_err(), "checksum");
+        assert!(
+            parse_hex("04000000DEADBEEFC4\n", 32).is_err(),
+            "no start code"
+        );
+        assert!(parse_hex(":00000001FF\n", 32).is_err(), "empty");
+    }
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(
+            frame(1, &[CMD_SIGN_ON]),
+            vec![0x1B, 0x01, 0x00, 0x01, 0x0E, 0x01, 0x14]
+        );
+    }
+}
@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::hardware::board::Board;
 use crate::hardware::device::DeviceType;
+use crate::hardware::pins::{Capability, PinUsage};
 use crate::impl_device;
 
 impl_device!(Led, {
@@ -17,9 +18,20 @@ impl_device!(Led, {
             &board.inner,
             current.get_pin(),
             current.get_default().as_bool(),
-        )?;
-        // @todo handle dimmable led.
-        // .set_brightness(current.get_brightness())?;
+        )?
+        .set_brightness(current.get_brightness())?;
         Ok(())
     }
+
+    // A dimmed LED needs a PWM pin.
+    fn get_pins(&self) -> Vec<PinUsage> {
+        let capability = match self.inner.get_brightness() < 255 {
+            true => Capability::Pwm,
+            false => Capability::Output,
+        };
+        vec![PinUsage {
+            pin: self.inner.get_pin(),
+            capability,
+        }]
+    }
 });
@@ -1,5 +1,16 @@
 pub mod board;
 pub mod device;
+pub mod display;
+pub mod firmware;
+pub mod flasher;
 pub mod led;
 pub mod mp3;
+pub mod pca9685;
+pub mod pins;
+pub mod power;
+pub mod profile;
+pub mod rgb;
+pub mod sampler;
 pub mod servo;
+pub mod template;
+pub mod tts;
@@ -34,7 +34,7 @@ impl DerefMut for Mp3Player {
 impl DeviceType for Mp3Player {
     fn set_board(&mut self, board: &Board) -> Result<()> {
         let current = self.inner.clone();
-        self.inner = crate::extra::mp3::Mp3Player::new(&board.inner)?.set_path(current.get_path());
+        self.inner = crate::extra::mp3::Mp3Player::new(&board.inner)?.with_settings_of(&current);
         Ok(())
     }
 
@@ -58,6 +58,22 @@ impl DeviceType for Mp3Player {
         Ok(Track::new(device))
     }
 
+    fn get_default(&self) -> State {
+        self.inner.get_default()
+    }
+
+    fn get_state(&self) -> State {
+        self.inner.get_state()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        self.inner.scale_state(previous, target, progress)
+    }
+
+    fn is_discrete(&self) -> bool {
+        true
+    }
+
     fn reset(&mut self) -> Result<State> {
         let state = self.animate(
             self.inner.get_default(),
@@ -0,0 +1,87 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+use anyhow::{bail, Result};
+use hermes_five::animation::Track;
+use hermes_five::devices::Output;
+use serde::{Deserialize, Serialize};
+
+use crate::extra::pca9685::{PcaChannel, CHANNELS};
+use crate::hardware::board::Board;
+use crate::hardware::device::{Device, DeviceType};
+use crate::impl_device;
+
+// The PCA9685 channels use the I2C bus of the board, shared by all chips: they use no pin of their own.
+
+impl_device!(PcaServo => crate::extra::pca9685::PcaServo, {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        self.inner = self.inner.clone().attach(&board.inner, board.id)?;
+        Ok(())
+    }
+
+    fn get_range(&self) -> Option<(u16, u16)> {
+        Some(self.inner.get_range())
+    }
+});
+
+impl_device!(PcaPwm => crate::extra::pca9685::PcaPwm, {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        self.inner = self.inner.clone().attach(&board.inner, board.id)?;
+        Ok(())
+    }
+
+    fn get_range(&self) -> Option<(u16, u16)> {
+        Some((0, 100))
+    }
+});
+
+/// Checks the PCA9685 channel used by a device (if any) against the other devices of its board: the channel must
+/// exist and be free, and all the channels of a chip must expect the same PWM frequency.
+pub fn validate(device: &Device, devices: &[Device]) -> Result<()> {
+    let channel = match get_channel(device) {
+        None => return Ok(()),
+        Some(channel) => channel,
+    };
+    if channel.channel >= CHANNELS {
+        bail!(
+            "PCA9685 channel {} does not exist (0-{})",
+            channel.channel,
+            CHANNELS - 1
+        );
+    }
+    for other in devices
+        .iter()
+        .filter(|other| other.id != device.id && other.bid == device.bid)
+    {
+        let used = match get_channel(other) {
+            Some(used) if used.address == channel.address => used,
+            _ => continue,
+        };
+        if used.channel == channel.channel {
+            bail!(
+                "PCA9685 channel {} at 0x{:02X} is already used by [{}]",
+                channel.channel,
+                channel.address,
+                other.name
+            );
+        }
+        if used.frequency != channel.frequency {
+            bail!(
+                "PCA9685 at 0x{:02X} runs at {}Hz for [{}]: all its channels must use the same frequency",
+                channel.address,
+                used.frequency,
+                other.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// (private)
+/// Retrieves the PCA9685 channel driven by a device (if it is plugged on one).
+fn get_channel(device: &Device) -> Option<PcaChannel> {
+    device
+        .downcast_ref::<PcaServo>()
+        .map(|servo| servo.get_channel())
+        .or_else(|| device.downcast_ref::<PcaPwm>().map(|pwm| pwm.get_channel()))
+}
@@ -0,0 +1,286 @@
+//! This file contains the pin capabilities of the boards and the validation of the pins used by devices.
+//!
+//! When a board is open, its capabilities come from the handshake (the pins and modes reported by the
+//! firmware). When it is closed, they come from the profile of its model (see [`BoardProfile`]) so that
+//! devices can be validated offline. A device is valid when each of its pins exists, supports the mode the
+//! device needs and is not used by another device of the same board.
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use anyhow::Result;
+use hermes_five::protocols::{PinModeId, Protocol};
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::board::{Board, BoardType};
+use crate::hardware::device::Device;
+use crate::hardware::profile::BoardProfile;
+use crate::utils::entity::Id;
+use crate::utils::validation::ValidationError;
+
+/// A capability (ie: a supported mode) of a pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Input,
+    Output,
+    Analog,
+    Pwm,
+    Servo,
+    I2c,
+    Spi,
+}
+
+impl Capability {
+    /// Converts a mode reported by the firmware (if relevant).
+    fn from_mode(mode: &PinModeId) -> Option<Self> {
+        match mode {
+            PinModeId::INPUT | PinModeId::PULLUP => Some(Capability::Input),
+            PinModeId::OUTPUT => Some(Capability::Output),
+            PinModeId::ANALOG => Some(Capability::Analog),
+            PinModeId::PWM => Some(Capability::Pwm),
+            PinModeId::SERVO => Some(Capability::Servo),
+            PinModeId::I2C => Some(Capability::I2c),
+            PinModeId::SPI => Some(Capability::Spi),
+            _ => None,
+        }
+    }
+}
+
+/// A pin of a board along with its capabilities.
+#[derive(Clone, Debug, Serialize)]
+pub struct PinInfo {
+    pub id: u16,
+    /// The pin name (ie: "D3", "A0").
+    pub name: String,
+    pub capabilities: Vec<Capability>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinSource {
+    /// The capabilities are reported by the board (handshake).
+    Hardware,
+    /// The capabilities are the known ones of the board model.
+    Model,
+}
+
+/// The pin capabilities of a board.
+#[derive(Clone, Debug, Serialize)]
+pub struct PinMap {
+    pub source: PinSource,
+    pub pins: BTreeMap<u16, PinInfo>,
+}
+
+impl PinMap {
+    /// Retrieves the pin map of a board: from the hardware when open, from its model otherwise.
+    pub fn get(board: &Board) -> Option<Self> {
+        match board.connected {
+            true => Some(Self::from_hardware(board)),
+            false => Self::from_model(&board.model),
+        }
+    }
+
+    /// Builds the pin map reported by an open board.
+    pub fn from_hardware(board: &Board) -> Self {
+        let protocol = board.inner.get_protocol();
+        let hardware = protocol.get_hardware().read();
+        let pins = hardware
+            .pins
+            .values()
+            .map(|pin| {
+                let mut capabilities: Vec<Capability> = pin
+                    .supported_modes
+                    .iter()
+                    .filter_map(|mode| Capability::from_mode(&mode.id))
+                    .collect();
+                capabilities.sort();
+                capabilities.dedup();
+                let info = PinInfo {
+                    id: pin.id,
+                    name: pin.name.clone(),
+                    capabilities,
+                };
+                (pin.id, info)
+            })
+            .collect();
+        Self {
+            source: PinSource::Hardware,
+            pins,
+        }
+    }
+
+    /// Builds the known pin map of a board model (if any, see [`BoardProfile`]).
+    pub fn from_model(model: &BoardType) -> Option<Self> {
+        let profile = BoardProfile::get(model)?;
+        let mut pins = BTreeMap::new();
+        for id in &profile.digital {
+            pins.insert(*id, pin_info(*id, format!("D{}", id), vec![]));
+        }
+        for (channel, id) in profile.analog.iter().enumerate() {
+            pins.insert(*id, pin_info(*id, format!("A{}", channel), vec![]));
+        }
+        for (id, info) in pins.iter_mut() {
+            let capabilities = [
+                (&profile.digital, Capability::Input),
+                (&profile.digital, Capability::Output),
+                (&profile.analog, Capability::Analog),
+                (&profile.pwm, Capability::Pwm),
+                (&profile.servo, Capability::Servo),
+                (&profile.i2c, Capability::I2c),
+                (&profile.spi, Capability::Spi),
+            ];
+            for (pins, capability) in capabilities {
+                if pins.contains(id) {
+                    info.capabilities.push(capability);
+                }
+            }
+        }
+        Some(Self {
+            source: PinSource::Model,
+            pins,
+        })
+    }
+}
+
+/// (private)
+fn pin_info(id: u16, name: String, capabilities: Vec<Capability>) -> PinInfo {
+    PinInfo {
+        id,
+        name,
+        capabilities,
+    }
+}
+
+/// A pin used by a device, along with the capability it needs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PinUsage {
+    pub pin: u16,
+    pub capability: Capability,
+}
+
+/// A reason for a device pin to be invalid.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PinError {
+    /// The pin does not exist on the board.
+    Unknown { pin: u16 },
+    /// The pin does not support the mode needed by the device.
+    Unsupported { pin: u16, capability: Capability },
+    /// The pin is already used by another device of the board.
+    Conflict { pin: u16, device: Id, name: String },
+}
+
+impl Display for PinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinError::Unknown { pin } => write!(f, "pin {} does not exist", pin),
+            PinError::Unsupported { pin, capability } => {
+                write!(f, "pin {} does not support {:?}", pin, capability)
+            }
+            PinError::Conflict { pin, name, .. } => {
+                write!(f, "pin {} is already used by [{}]", pin, name)
+            }
+        }
+    }
+}
+
+/// Checks the pins used by a device against the board capabilities (when known) and the other devices of the
+/// board.
+///
+/// # Errors
+/// * a [`ValidationError`] listing the [`PinError`]s found.
+pub fn validate(device: &Device, board: &Board, devices: &[Device]) -> Result<()> {
+    let errors = get_errors(
+        &device.inner.get_pins(),
+        device,
+        PinMap::get(board),
+        devices,
+    );
+    match errors.is_empty() {
+        true => Ok(()),
+        false => {
+            let message = errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(ValidationError::new(format!("Invalid pins: {}", message), errors).into())
+        }
+    }
+}
+
+/// (private)
+fn get_errors(
+    usages: &[PinUsage],
+    device: &Device,
+    map: Option<PinMap>,
+    devices: &[Device],
+) -> Vec<PinError> {
+    let mut errors = vec![];
+    for (index, usage) in usages.iter().enumerate() {
+        if let Some(map) = &map {
+            match map.pins.get(&usage.pin) {
+                None => errors.push(PinError::Unknown { pin: usage.pin }),
+                Some(info) if !info.capabilities.contains(&usage.capability) => {
+                    errors.push(PinError::Unsupported {
+                        pin: usage.pin,
+                        capability: usage.capability,
+                    })
+                }
+                _ => {}
+            }
+        }
+        // The device itself uses the same pin twice.
+        if usages[..index].iter().any(|other| other.pin == usage.pin) {
+            errors.push(PinError::Conflict {
+                pin: usage.pin,
+                device: device.id,
+                name: device.name.clone(),
+            });
+        }
+        let other = devices.iter().find(|other| {
+            other.id != device.id
+                && other.bid == device.bid
+                && other
+                    .inner
+                    .get_pins()
+                    .iter()
+                    .any(|other| other.pin == usage.pin)
+        });
+        if let Some(other) = other {
+            errors.push(PinError::Conflict {
+                pin: usage.pin,
+                device: other.id,
+                name: other.name.clone(),
+            });
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hardware::board::ArduinoType;
+
+    use super::*;
+
+    #[test]
+    fn test_model_pin_map() {
+        let uno = PinMap::from_model(&BoardType::Arduino(ArduinoType::UNO)).unwrap();
+        assert_eq!(uno.pins.len(), 18);
+        assert!(uno.pins[&3].capabilities.contains(&Capability::Pwm));
+        assert!(!uno.pins[&4].capabilities.contains(&Capability::Pwm));
+        assert_eq!(uno.pins[&14].name, "A0");
+        assert!(uno.pins[&18].capabilities.contains(&Capability::I2c));
+
+        let nano = PinMap::from_model(&BoardType::Arduino(ArduinoType::NANO)).unwrap();
+        assert_eq!(nano.pins[&20].capabilities, vec![Capability::Analog]);
+
+        let mega = PinMap::from_model(&BoardType::Arduino(ArduinoType::MEGA)).unwrap();
+        assert_eq!(mega.pins.len(), 68);
+        assert!(mega.pins[&45].capabilities.contains(&Capability::Pwm));
+        assert!(!mega.pins[&52].capabilities.contains(&Capability::Servo));
+
+        assert!(PinMap::from_model(&BoardType::Unknown).is_none());
+    }
+}
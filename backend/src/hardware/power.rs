@@ -0,0 +1,442 @@
+//! This file defines the `PowerModel`: the current drawn by the devices and the budget of the power rails.
+//!
+//! Moving all servos at once draws much more current than most supplies provide: the voltage drops and the
+//! board resets. When enabled, the model schedules the moves of a transition (or a reset) so that the devices
+//! moving at the same time on a rail never draw more than its budget: moves are either staggered (delayed until
+//! enough current is available) or slowed down (stretched proportionally to the overload).
+//!
+//! Delayed moves are played as keyframes starting at their delay, so that scheduling never blocks: only resets,
+//! which are instant, pause between devices.
+//!
+//! The keyframes of the animations are never staggered (it would break their timing): when the keyframes moving
+//! at once overload a rail, the whole animation is slowed down proportionally instead, whatever the strategy.
+//! Each animation (or playback layer) is budgeted on its own; its lead-in transition is scheduled as usual.
+//!
+//! The model is stored in the `power.json` file of the database folder.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use hermes_five::pause_sync;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::device::Device;
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
+
+const FILENAME: &str = "power.json";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerStrategy {
+    /// Delays the moves exceeding the budget until the previous ones are over.
+    #[default]
+    Stagger,
+    /// Slows all moves of an overloaded rail down.
+    Slow,
+}
+
+/// A power supply shared by some boards and/or devices.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerRail {
+    pub name: String,
+    /// The current (in mA) the rail can provide.
+    pub budget: u32,
+    /// The boards whose devices are powered by the rail.
+    pub boards: Vec<Id>,
+    /// The devices powered by the rail (whatever their board).
+    pub devices: Vec<Id>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerModel {
+    /// Whether moves are scheduled according to the model (default: false).
+    pub enabled: bool,
+    pub strategy: PowerStrategy,
+    /// The current (in mA) drawn by a moving device, per device type.
+    pub draws: BTreeMap<String, u32>,
+    /// The current (in mA) drawn by a moving device of a type missing from `draws` (default: 0).
+    pub default_draw: u32,
+    pub rails: Vec<PowerRail>,
+    /// The budget (in mA) of a board powering its own devices (default: 0, ie: unlimited).
+    pub default_budget: u32,
+    /// The time (in ms) a device is considered drawing current after a reset (default: 100ms).
+    pub reset_duration: u64,
+}
+
+impl Default for PowerModel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strategy: PowerStrategy::Stagger,
+            draws: BTreeMap::from([
+                (String::from("Servo"), 500),
+                (String::from("PcaServo"), 500),
+            ]),
+            default_draw: 0,
+            rails: vec![],
+            default_budget: 0,
+            reset_duration: 100,
+        }
+    }
+}
+
+/// When a device starts moving and for how long (in ms).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    pub delay: u64,
+    pub duration: u64,
+}
+
+impl Slot {
+    /// The time (in ms) the move is over.
+    pub fn end(&self) -> u64 {
+        self.delay + self.duration
+    }
+}
+
+/// The load of a device on its rail.
+#[derive(Clone, Debug, PartialEq)]
+struct Load {
+    rail: String,
+    budget: u32,
+    draw: u32,
+}
+
+/// The slots of a list of devices (in the same order).
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub slots: Vec<Slot>,
+}
+
+impl PowerModel {
+    /// Loads the model from the database folder (default model if missing or invalid).
+    pub fn load(database: &Database) -> Self {
+        match database.read_file(FILENAME) {
+            Err(_) => Self::default(),
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("Invalid power model (ignored): {}", error);
+                Self::default()
+            }),
+        }
+    }
+
+    pub fn save(&self, database: &Database) -> Result<()> {
+        database.write_file(FILENAME, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Retrieves the current (in mA) drawn by the device when moving.
+    pub fn get_draw(&self, device: &Device) -> u32 {
+        self.draws
+            .get(device.inner.typetag_name())
+            .copied()
+            .unwrap_or(self.default_draw)
+    }
+
+    /// Retrieves the rail powering the device: (name, budget).
+    /// A device listed in a rail takes precedence over the rail of its board.
+    fn get_rail(&self, device: &Device) -> (String, u32) {
+        let rail = self
+            .rails
+            .iter()
+            .find(|rail| rail.devices.contains(&device.id))
+            .or_else(|| {
+                self.rails
+                    .iter()
+                    .find(|rail| rail.boards.contains(&device.bid))
+            });
+        match rail {
+            Some(rail) => (format!("rail:{}", rail.name), rail.budget),
+            None => (format!("board:{}", device.bid), self.default_budget),
+        }
+    }
+
+    /// Schedules the moves of the given devices: each move lasts `duration` and starts at least `stagger` after
+    /// the previous one.
+    pub fn schedule<'a, I: IntoIterator<Item = &'a Device>>(
+        &self,
+        devices: I,
+        duration: u64,
+        stagger: u64,
+    ) -> Schedule {
+        self.plan(devices, duration, stagger, self.strategy)
+    }
+
+    /// Schedules the (instant) resets of the given devices: the `fallback` stagger applies if the model is
+    /// disabled. A reset cannot be slowed down: it is always staggered.
+    pub fn schedule_resets<'a, I: IntoIterator<Item = &'a Device>>(
+        &self,
+        devices: I,
+        fallback: u64,
+    ) -> Schedule {
+        match self.enabled {
+            false => self.plan(devices, 0, fallback, PowerStrategy::Stagger),
+            true => self.plan(devices, self.reset_duration, 0, PowerStrategy::Stagger),
+        }
+    }
+
+    /// Computes the factor (at least 1.0) the given moves must be slowed down by so that no rail is overloaded:
+    /// each move is given as (device, start, end), in ms.
+    pub fn get_slowdown<'a, I: IntoIterator<Item = (&'a Device, u64, u64)>>(
+        &self,
+        moves: I,
+    ) -> f64 {
+        if !self.enabled {
+            return 1.0;
+        }
+        let moves: Vec<(Load, u64, u64)> = moves
+            .into_iter()
+            .map(|(device, start, end)| {
+                let (rail, budget) = self.get_rail(device);
+                let draw = self.get_draw(device);
+                (Load { rail, budget, draw }, start, end)
+            })
+            .collect();
+        plan_slowdown(&moves)
+    }
+
+    /// (private)
+    fn plan<'a, I: IntoIterator<Item = &'a Device>>(
+        &self,
+        devices: I,
+        duration: u64,
+        stagger: u64,
+        strategy: PowerStrategy,
+    ) -> Schedule {
+        let loads: Vec<Load> = devices
+            .into_iter()
+            .map(|device| {
+                let (rail, budget) = self.get_rail(device);
+                let draw = match self.enabled {
+                    true => self.get_draw(device),
+                    false => 0,
+                };
+                Load { rail, budget, draw }
+            })
+            .collect();
+        let slots = match strategy {
+            PowerStrategy::Stagger => plan_stagger(&loads, duration, stagger),
+            PowerStrategy::Slow => plan_slow(&loads, duration, stagger),
+        };
+        Schedule { slots }
+    }
+}
+
+/// Starts each move as soon as its rail can afford it (but not before its own stagger).
+fn plan_stagger(loads: &[Load], duration: u64, stagger: u64) -> Vec<Slot> {
+    let mut slots: Vec<Slot> = vec![];
+    for (index, load) in loads.iter().enumerate() {
+        let mut slot = Slot {
+            delay: index as u64 * stagger,
+            duration,
+        };
+        if load.budget > 0 && load.draw > 0 {
+            loop {
+                // The moves of the rail overlapping the candidate slot.
+                let overlapping: Vec<(&Slot, u32)> = slots
+                    .iter()
+                    .zip(loads)
+                    .filter(|(other, other_load)| {
+                        other_load.rail == load.rail
+                            && other.delay < slot.end().max(slot.delay + 1)
+                            && other.end() > slot.delay
+                    })
+                    .map(|(other, other_load)| (other, other_load.draw))
+                    .collect();
+                // The peak load is reached at the start of the slot or at the start of an overlapping move.
+                let peak = std::iter::once(slot.delay)
+                    .chain(
+                        overlapping
+                            .iter()
+                            .map(|(other, _)| other.delay.max(slot.delay)),
+                    )
+                    .map(|time| {
+                        overlapping
+                            .iter()
+                            .filter(|(other, _)| other.delay <= time && other.end() > time)
+                            .map(|(_, draw)| draw)
+                            .sum::<u32>()
+                    })
+                    .max()
+                    .unwrap_or(0);
+                // A device drawing more than the budget on its own still moves (alone).
+                if overlapping.is_empty() || peak + load.draw <= load.budget {
+                    break;
+                }
+                slot.delay = overlapping
+                    .iter()
+                    .map(|(other, _)| other.end())
+                    .filter(|end| *end > slot.delay)
+                    .min()
+                    .unwrap_or(slot.delay + 1);
+            }
+        }
+        slots.push(slot);
+    }
+    slots
+}
+
+/// Stretches the moves of each overloaded rail proportionally to its overload.
+fn plan_slow(loads: &[Load], duration: u64, stagger: u64) -> Vec<Slot> {
+    let mut totals: HashMap<&str, u32> = HashMap::new();
+    for load in loads {
+        *totals.entry(load.rail.as_str()).or_default() += load.draw;
+    }
+    loads
+        .iter()
+        .enumerate()
+        .map(|(index, load)| {
+            let total = totals[load.rail.as_str()];
+            let duration = match load.budget > 0 && total > load.budget {
+                true => (duration * total as u64).div_ceil(load.budget as u64),
+                false => duration,
+            };
+            Slot {
+                delay: index as u64 * stagger,
+                duration,
+            }
+        })
+        .collect()
+}
+
+/// Computes the peak load of each rail over its budget: the peak is reached at the start of a move.
+fn plan_slowdown(moves: &[(Load, u64, u64)]) -> f64 {
+    moves
+        .iter()
+        .filter(|(load, _, _)| load.budget > 0 && load.draw > 0)
+        .map(|(load, time, _)| {
+            let peak: u32 = moves
+                .iter()
+                .filter(|(other, start, end)| {
+                    other.rail == load.rail && start <= time && end > time
+                })
+                .map(|(other, _, _)| other.draw)
+                .sum();
+            peak as f64 / load.budget as f64
+        })
+        .fold(1.0, f64::max)
+}
+
+impl Schedule {
+    /// The time (in ms) all moves are over.
+    pub fn span(&self) -> u64 {
+        self.slots.iter().map(Slot::end).max().unwrap_or(0)
+    }
+
+    /// Calls the given function for each item (in the order of their slots), pausing between the starts.
+    pub fn run<T, F: FnMut(T, Slot) -> Result<()>>(&self, items: Vec<T>, mut f: F) -> Result<()> {
+        let mut items: Vec<(T, Slot)> = items.into_iter().zip(self.slots.clone()).collect();
+        items.sort_by_key(|(_, slot)| slot.delay);
+        let mut elapsed = 0;
+        for (item, slot) in items {
+            if slot.delay > elapsed {
+                pause_sync!(slot.delay - elapsed);
+                elapsed = slot.delay;
+            }
+            f(item, slot)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(rail: &str, budget: u32, draw: u32) -> Load {
+        Load {
+            rail: rail.to_string(),
+            budget,
+            draw,
+        }
+    }
+
+    fn delays(slots: &[Slot]) -> Vec<u64> {
+        slots.iter().map(|slot| slot.delay).collect()
+    }
+
+    #[test]
+    fn test_plan_stagger() {
+        // Unlimited budget: all devices move together (or with the transition own stagger).
+        let loads = vec![load("a", 0, 500); 3];
+        assert_eq!(delays(&plan_stagger(&loads, 500, 0)), vec![0, 0, 0]);
+        assert_eq!(delays(&plan_stagger(&loads, 500, 50)), vec![0, 50, 100]);
+
+        // Two servos at once on a 1A rail.
+        let loads = vec![load("a", 1000, 500); 5];
+        assert_eq!(
+            delays(&plan_stagger(&loads, 500, 0)),
+            vec![0, 0, 500, 500, 1000]
+        );
+        assert_eq!(
+            delays(&plan_stagger(&loads, 500, 300)),
+            vec![0, 300, 600, 900, 1200]
+        );
+        assert_eq!(
+            delays(&plan_stagger(&loads, 500, 100)),
+            vec![0, 100, 500, 600, 1000]
+        );
+
+        // Rails are independent; a device drawing more than the budget moves alone.
+        let loads = [
+            load("a", 500, 500),
+            load("b", 500, 500),
+            load("a", 500, 800),
+            load("a", 500, 0),
+        ];
+        assert_eq!(delays(&plan_stagger(&loads, 500, 0)), vec![0, 0, 500, 0]);
+    }
+
+    #[test]
+    fn test_plan_slow() {
+        let loads = [
+            load("a", 1000, 500),
+            load("a", 1000, 500),
+            load("a", 1000, 500),
+            load("b", 1000, 500),
+        ];
+        let slots = plan_slow(&loads, 500, 0);
+        assert_eq!(delays(&slots), vec![0, 0, 0, 0]);
+        let durations: Vec<u64> = slots.iter().map(|slot| slot.duration).collect();
+        assert_eq!(durations, vec![750, 750, 750, 500]);
+    }
+
+    #[test]
+    fn test_plan_slowdown() {
+        // Two servos at once on a 1A rail: fine.
+        let moves = [
+            (load("a", 1000, 500), 0, 500),
+            (load("a", 1000, 500), 0, 500),
+        ];
+        assert_eq!(plan_slowdown(&moves), 1.0);
+
+        // Three overlapping servos (the third one on another rail does not count).
+        let moves = [
+            (load("a", 1000, 500), 0, 1000),
+            (load("a", 1000, 500), 200, 400),
+            (load("a", 1000, 500), 300, 600),
+            (load("b", 1000, 500), 300, 600),
+        ];
+        assert_eq!(plan_slowdown(&moves), 1.5);
+
+        // Consecutive moves and unlimited budgets do not overlap.
+        let moves = [
+            (load("a", 500, 500), 0, 500),
+            (load("a", 500, 500), 500, 1000),
+        ];
+        assert_eq!(plan_slowdown(&moves), 1.0);
+        let moves = [(load("a", 0, 500), 0, 500), (load("a", 0, 500), 0, 500)];
+        assert_eq!(plan_slowdown(&moves), 1.0);
+    }
+
+    #[test]
+    fn test_schedule_span() {
+        let schedule = Schedule {
+            slots: plan_stagger(&vec![load("a", 1000, 500); 3], 500, 0),
+        };
+        assert_eq!(schedule.span(), 1000);
+        assert_eq!(Schedule { slots: vec![] }.span(), 0);
+    }
+}
@@ -0,0 +1,96 @@
+//! This file contains the `BoardProfile`s: the known characteristics of each board model.
+//!
+//! A profile describes the pins of a model (used to validate devices offline and to build the UI pin pickers,
+//! see [`crate::hardware::pins`]), the default serial settings applied when a board is created and the
+//! firmware image flashed onto it (see [`crate::hardware::flasher`]).
+use serde::Serialize;
+
+use crate::hardware::board::{ArduinoType, BoardType};
+
+/// The characteristics of a board model.
+#[derive(Clone, Debug, Serialize)]
+pub struct BoardProfile {
+    pub model: BoardType,
+    /// The model human name.
+    pub name: &'static str,
+    /// The number of pins (serial ones included).
+    pub pins: u16,
+    /// The pins usable as digital input/output (pins 0 and 1 are the serial port used by the firmware).
+    pub digital: Vec<u16>,
+    /// The analog pins, by channel (ie: A0 first).
+    pub analog: Vec<u16>,
+    /// The PWM capable pins.
+    pub pwm: Vec<u16>,
+    /// The pins the firmware can drive servos on.
+    pub servo: Vec<u16>,
+    /// The I2C pins (SDA, SCL).
+    pub i2c: Vec<u16>,
+    /// The SPI pins (SS, MOSI, MISO, SCK).
+    pub spi: Vec<u16>,
+    /// The default serial baud rate of the firmware (set on the serial protocol of the boards created).
+    pub baud: u32,
+    /// The default serial port of the board.
+    pub port: &'static str,
+    /// The firmware image (see the `firmwares` folder).
+    pub firmware: Option<&'static str>,
+}
+
+impl BoardProfile {
+    /// Retrieves all known profiles.
+    pub fn list() -> Vec<Self> {
+        [ArduinoType::NANO, ArduinoType::UNO, ArduinoType::MEGA]
+            .into_iter()
+            .filter_map(|model| Self::get(&BoardType::Arduino(model)))
+            .collect()
+    }
+
+    /// Retrieves the profile of a board model (if known).
+    pub fn get(model: &BoardType) -> Option<Self> {
+        match model {
+            BoardType::Arduino(ArduinoType::UNO) => Some(Self {
+                model: model.clone(),
+                name: "Arduino Uno",
+                pins: 20,
+                digital: (2..20).collect(),
+                analog: (14..20).collect(),
+                pwm: vec![3, 5, 6, 9, 10, 11],
+                servo: (2..14).collect(),
+                i2c: vec![18, 19],
+                spi: vec![10, 11, 12, 13],
+                baud: 57600,
+                port: "/dev/ttyACM0",
+                firmware: Some("firmware_nano.hex"),
+            }),
+            // Nano clones use a CH340 usb-serial converter; A6 and A7 are analog only.
+            BoardType::Arduino(ArduinoType::NANO) => Some(Self {
+                model: model.clone(),
+                name: "Arduino Nano",
+                pins: 22,
+                digital: (2..20).collect(),
+                analog: (14..22).collect(),
+                pwm: vec![3, 5, 6, 9, 10, 11],
+                servo: (2..14).collect(),
+                i2c: vec![18, 19],
+                spi: vec![10, 11, 12, 13],
+                baud: 57600,
+                port: "/dev/ttyUSB0",
+                firmware: Some("firmware_nano.hex"),
+            }),
+            BoardType::Arduino(ArduinoType::MEGA) => Some(Self {
+                model: model.clone(),
+                name: "Arduino Mega 2560",
+                pins: 70,
+                digital: (2..70).collect(),
+                analog: (54..70).collect(),
+                pwm: (2..14).chain(44..47).collect(),
+                servo: (2..50).collect(),
+                i2c: vec![20, 21],
+                spi: vec![53, 51, 50, 52],
+                baud: 57600,
+                port: "/dev/ttyACM0",
+                firmware: Some("firmware_mega.hex"),
+            }),
+            _ => None,
+        }
+    }
+}
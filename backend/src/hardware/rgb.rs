@@ -0,0 +1,48 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Result;
+use hermes_five::animation::Track;
+use hermes_five::devices::Output;
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::board::Board;
+use crate::hardware::device::DeviceType;
+use crate::hardware::pins::{Capability, PinUsage};
+use crate::impl_device;
+
+impl_device!(RgbLed => crate::extra::rgb::RgbLed, {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        self.inner = self.inner.clone().attach(&board.inner)?;
+        Ok(())
+    }
+
+    fn get_pins(&self) -> Vec<PinUsage> {
+        self.inner
+            .get_pins()
+            .into_iter()
+            .map(|pin| PinUsage {
+                pin,
+                capability: Capability::Pwm,
+            })
+            .collect()
+    }
+});
+
+impl_device!(PixelStrip => crate::extra::strip::PixelStrip, {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        self.inner = self.inner.clone().attach(&board.inner)?;
+        Ok(())
+    }
+
+    fn get_pins(&self) -> Vec<PinUsage> {
+        vec![PinUsage {
+            pin: self.inner.get_pin(),
+            capability: Capability::Output,
+        }]
+    }
+
+    fn validate(&self) -> Result<()> {
+        Ok(self.inner.check_pin()?)
+    }
+});
@@ -0,0 +1,81 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Result;
+use hermes_five::animation::Track;
+use hermes_five::devices::Output;
+use hermes_five::utils::State;
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::board::Board;
+use crate::hardware::device::DeviceType;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sampler {
+    #[serde(flatten)]
+    pub inner: crate::extra::sampler::Sampler,
+}
+
+impl Deref for Sampler {
+    type Target = crate::extra::sampler::Sampler;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for Sampler {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[typetag::serde]
+impl DeviceType for Sampler {
+    fn set_board(&mut self, board: &Board) -> Result<()> {
+        let current = self.inner.clone();
+        self.inner = crate::extra::sampler::Sampler::new(&board.inner)?.with_settings_of(&current);
+        Ok(())
+    }
+
+    fn animate(
+        &mut self,
+        state: State,
+        duration: u64,
+        transition: hermes_five::utils::Easing,
+    ) -> Result<State> {
+        self.inner.animate(state.clone(), duration, transition);
+        Ok(state)
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State> {
+        let state = self.inner.set_state(state.clone())?;
+        Ok(state)
+    }
+
+    fn into_track(&self) -> Result<Track> {
+        let device = self.inner.clone();
+        Ok(Track::new(device))
+    }
+
+    fn get_default(&self) -> State {
+        self.inner.get_default()
+    }
+
+    fn get_state(&self) -> State {
+        self.inner.get_state()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        self.inner.scale_state(previous, target, progress)
+    }
+
+    fn is_discrete(&self) -> bool {
+        true
+    }
+
+    fn reset(&mut self) -> Result<State> {
+        self.inner.stop();
+        Ok(self.inner.get_state())
+    }
+}
@@ -4,16 +4,41 @@ use std::ops::{Deref, DerefMut};
 use anyhow::Result;
 use hermes_five::animation::Track;
 use hermes_five::devices::Output;
+use hermes_five::utils::State;
 use serde::{Deserialize, Serialize};
 
+use crate::extra::servo::{CalibratedServo, Calibration};
 use crate::hardware::board::Board;
 use crate::hardware::device::DeviceType;
-use crate::impl_device;
+use crate::hardware::pins::{Capability, PinUsage};
 
-impl_device!(Servo, {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Servo {
+    #[serde(flatten)]
+    pub inner: CalibratedServo,
+}
+
+impl Deref for Servo {
+    type Target = CalibratedServo;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for Servo {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[typetag::serde]
+impl DeviceType for Servo {
     fn set_board(&mut self, board: &Board) -> Result<()> {
-        let current = self.inner.clone();
-        self.inner = hermes_five::devices::Servo::create(
+        let calibration = self.inner.get_calibration().clone();
+        // The settings are read from the hermes servo itself: in commanded units.
+        let current: &hermes_five::devices::Servo = &self.inner;
+        let servo = hermes_five::devices::Servo::create(
             &board.inner,
             current.get_pin(),
             current.get_default().as_integer() as u16,
@@ -25,6 +50,82 @@ impl_device!(Servo, {
         .set_range(current.get_range())
         .set_auto_detach(current.is_auto_detach())
         .set_detach_delay(current.get_detach_delay());
+        self.inner = CalibratedServo::new(servo, calibration);
         Ok(())
     }
-});
+
+    fn animate(
+        &mut self,
+        state: State,
+        duration: u64,
+        transition: hermes_five::utils::Easing,
+    ) -> Result<State> {
+        self.inner.animate(state.clone(), duration, transition);
+        Ok(state)
+    }
+
+    fn set_state(&mut self, state: State) -> Result<State> {
+        let state = self.inner.set_state(state.clone())?;
+        Ok(state)
+    }
+
+    fn into_track(&self) -> Result<Track> {
+        let device = self.inner.clone();
+        Ok(Track::new(device))
+    }
+
+    fn get_default(&self) -> State {
+        self.inner.get_default()
+    }
+
+    fn get_state(&self) -> State {
+        self.inner.get_state()
+    }
+
+    fn scale_state(&mut self, previous: State, target: State, progress: f32) -> State {
+        self.inner.scale_state(previous, target, progress)
+    }
+
+    fn reset(&mut self) -> Result<State> {
+        let state = self.animate(
+            self.inner.get_default(),
+            500,
+            hermes_five::utils::Easing::SineInOut,
+        )?;
+        Ok(state)
+    }
+
+    fn get_range(&self) -> Option<(u16, u16)> {
+        // The range of the hermes servo is in commanded units: a decreasing calibration swaps its bounds.
+        let range = self.inner.get_range();
+        let calibration = self.inner.get_calibration();
+        let (start, end) = (
+            calibration.to_logical(range.start as f32),
+            calibration.to_logical(range.end as f32),
+        );
+        let logical = |angle: f32| angle.round().max(0.0) as u16;
+        Some((logical(start.min(end)), logical(start.max(end))))
+    }
+
+    fn get_pins(&self) -> Vec<PinUsage> {
+        vec![PinUsage {
+            pin: self.inner.get_pin(),
+            capability: Capability::Servo,
+        }]
+    }
+}
+
+impl Servo {
+    /// Replaces the calibration of the servo (once validated): its states stay expressed in logical units.
+    pub fn calibrate(&mut self, calibration: Calibration) -> Result<()> {
+        calibration.validate()?;
+        self.inner.set_calibration(calibration)?;
+        Ok(())
+    }
+
+    /// Moves the servo to a raw (uncalibrated) angle, ie: to measure calibration points.
+    pub fn move_commanded(&mut self, angle: u16) -> Result<State> {
+        let state = self.inner.set_commanded(angle)?;
+        Ok(state)
+    }
+}
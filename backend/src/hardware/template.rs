@@ -0,0 +1,163 @@
+//! This file contains the `DeviceTemplate`s: reusable device configurations (servo models, LEDs, players...).
+//!
+//! The built-in catalogue is made of the data files of the `templates` folder (embedded at compile time):
+//! extending it is a matter of adding entries to those files. Users can save their own templates (ie: from an
+//! existing device): those are stored in the database. Creating a device from a template prefills its
+//! configuration, the board, name and pin being given on creation.
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::hardware::device::Device;
+use crate::impl_entity;
+use crate::utils::database::Database;
+use crate::utils::entity::Id;
+
+/// The built-in templates data files.
+const BUILTIN_TEMPLATES: [&str; 4] = [
+    include_str!("../../templates/servos.json"),
+    include_str!("../../templates/leds.json"),
+    include_str!("../../templates/players.json"),
+    include_str!("../../templates/displays.json"),
+];
+
+/// The device fields which are not part of a template.
+const INSTANCE_FIELDS: [&str; 3] = ["id", "bid", "name"];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeviceTemplate {
+    #[serde(default)]
+    pub id: Id,
+    /// The template name (unique).
+    pub name: String,
+    /// The template category (ie: "servo", "led", "player", "display").
+    pub category: String,
+    #[serde(default)]
+    pub description: String,
+    /// Whether the template is part of the built-in catalogue (read-only).
+    #[serde(default)]
+    pub builtin: bool,
+    /// The device configuration: its type and settings (see [`Device`]).
+    pub device: Map<String, Value>,
+}
+impl_entity!(DeviceTemplate);
+
+impl DeviceTemplate {
+    /// Retrieves the built-in templates.
+    pub fn builtins() -> Vec<Self> {
+        BUILTIN_TEMPLATES
+            .iter()
+            .flat_map(|file| {
+                serde_json::from_str::<Vec<Self>>(file).expect("Invalid built-in templates file")
+            })
+            .map(|template| Self {
+                builtin: true,
+                ..template
+            })
+            .collect()
+    }
+
+    /// Retrieves all templates: built-in ones first, then the user ones.
+    pub fn list(database: &Database) -> Result<Vec<Self>> {
+        let mut templates = Self::builtins();
+        let mut user: Vec<Self> = database.list::<Self>()?.into_values().collect();
+        user.sort_by(|a, b| (&a.category, &a.name).cmp(&(&b.category, &b.name)));
+        templates.extend(user);
+        Ok(templates)
+    }
+
+    /// Finds a template by name.
+    pub fn find(database: &Database, name: &str) -> Result<Self> {
+        match Self::list(database)?
+            .into_iter()
+            .find(|template| template.name == name)
+        {
+            None => bail!("Template [{}] not found", name),
+            Some(template) => Ok(template),
+        }
+    }
+
+    /// Builds a user template out of an existing device.
+    pub fn from_device(device: &Device, name: String, category: String) -> Result<Self> {
+        let mut configuration = match serde_json::to_value(device)? {
+            Value::Object(configuration) => configuration,
+            _ => bail!("Device [{}] cannot be serialized", device.id),
+        };
+        for field in INSTANCE_FIELDS {
+            configuration.remove(field);
+        }
+        Ok(Self {
+            id: 0,
+            name,
+            category,
+            description: format!("Saved from device [{}].", device.name),
+            builtin: false,
+            device: configuration,
+        })
+    }
+
+    /// Checks a user template before saving it: its name must be unique and its configuration valid.
+    pub fn validate(&self, database: &Database) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("Template name is required");
+        }
+        if Self::list(database)?
+            .iter()
+            .any(|other| other.name == self.name && (other.builtin || other.id != self.id))
+        {
+            bail!("Template [{}] already exists", self.name);
+        }
+        self.instantiate(0, &self.name, Map::new())?;
+        Ok(())
+    }
+
+    /// Creates a (not yet saved) device from the template: the given fields (ie: pin) override the template.
+    pub fn instantiate(
+        &self,
+        bid: Id,
+        name: &str,
+        overrides: Map<String, Value>,
+    ) -> Result<Device> {
+        let mut configuration = self.device.clone();
+        configuration.extend(overrides);
+        configuration.insert(String::from("id"), Value::from(0));
+        configuration.insert(String::from("bid"), Value::from(bid));
+        configuration.insert(String::from("name"), Value::from(name));
+        serde_json::from_value(Value::Object(configuration))
+            .map_err(|err| anyhow!("Invalid template [{}]: {}", self.name, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_builtins() {
+        let templates = DeviceTemplate::builtins();
+        assert!(templates.iter().all(|template| template.builtin));
+        assert!(templates
+            .iter()
+            .all(|template| template.device.contains_key("type")));
+        let names: HashSet<&String> = templates.iter().map(|template| &template.name).collect();
+        assert_eq!(names.len(), templates.len(), "Template names are unique");
+        assert!(templates
+            .iter()
+            .any(|template| template.category == "servo" && template.name == "MG996R"));
+
+        // Each template builds a valid device.
+        for template in &templates {
+            let device = template
+                .instantiate(1, &template.name, Map::new())
+                .unwrap_or_else(|err| panic!("{}", err));
+            assert_eq!(device.name, template.name);
+            assert!(
+                device.inner.validate().is_ok(),
+                "Template [{}] is invalid",
+                template.name
+            );
+        }
+    }
+}
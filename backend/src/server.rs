@@ -10,9 +10,15 @@ use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
 
 use crate::{tui_success, tui_warn};
+use crate::animation::executor::ArcExecutor;
+use crate::animation::playback::ArcPlayback;
 use crate::api::AppState;
 use crate::api::rest::build_rest_routes;
+use crate::api::sockets::calibration::ArcCalibration;
+use crate::api::sockets::collaboration::ArcCollaboration;
 use crate::api::sockets::register_socket_events;
+use crate::extra::media::MediaLibrary;
+use crate::hardware::flasher::Flasher;
 use crate::utils::config::Config;
 use crate::utils::database::Database;
 
@@ -59,6 +65,13 @@ impl Server {
 
     /// Starts the server.
     pub async fn start(self) -> anyhow::Result<()> {
+        // Build the media library.
+        let media = MediaLibrary::from(&self.config);
+        let flasher = Flasher::from(&self.config);
+        let playback = ArcPlayback::default();
+        let executor = ArcExecutor::default();
+        let collaboration = ArcCollaboration::default();
+
         // Build the database.
         let path = self.config.database_path;
         let database = Arc::new(RwLock::new(
@@ -84,6 +97,12 @@ impl Server {
         // Build the socket API server.
         let (socket_layer, socket_io) = SocketIo::builder()
             .with_state(database.clone())
+            .with_state(collaboration.clone())
+            .with_state(ArcCalibration::default())
+            .with_state(playback.clone())
+            .with_state(executor.clone())
+            .with_state(flasher)
+            .with_state(media.clone())
             .build_layer();
         socket_io.ns("/ws", move |socket: SocketRef| {
             info!("Socket.IO connected: {:?} {:?}", socket.ns(), socket.id);
@@ -109,6 +128,10 @@ impl Server {
             .with_state(AppState {
                 database,
                 socket: socket_io,
+                media,
+                playback,
+                executor,
+                collaboration,
             });
 
         let listener = tokio::net::TcpListener::bind((self.config.host, self.config.port)).await?;
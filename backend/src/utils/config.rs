@@ -31,6 +31,12 @@ pub struct Config {
     pub database_path: PathBuf,
     /// The website path.
     pub website_path: PathBuf,
+    /// The media library path (audio files shared by all devices).
+    pub media_path: PathBuf,
+    /// The maximum size (in bytes) of an uploaded media file.
+    pub media_max_size: usize,
+    /// The firmwares path (Firmata images flashed onto the boards).
+    pub firmware_path: PathBuf,
 }
 
 impl Default for Config {
@@ -47,6 +53,9 @@ impl Default for Config {
             logfile_path: current_path.join("logs/debug.log"),
             database_path: current_path.join("database"),
             website_path: current_path.join("website"),
+            media_path: current_path.join("media"),
+            media_max_size: 50 * 1024 * 1024,
+            firmware_path: current_path.join("firmwares"),
         }
     }
 }
@@ -6,3 +6,4 @@ pub mod entity;
 pub mod interface;
 pub mod logger;
 pub mod tui;
+pub mod validation;
@@ -0,0 +1,32 @@
+//! This file defines a `ValidationError`: an error carrying structured details along with its message.
+//!
+//! Such errors are returned through `anyhow` like any other error: the socket acknowledgements (see
+//! [`crate::api::sockets::ack::Ack`]) recognize them and expose their details for the UI to point at the
+//! invalid fields.
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+    pub message: String,
+    pub details: Value,
+}
+
+impl ValidationError {
+    pub fn new<M: Into<String>, D: Serialize>(message: M, details: D) -> Self {
+        Self {
+            message: message.into(),
+            details: serde_json::to_value(details).unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}